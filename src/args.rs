@@ -16,21 +16,63 @@ pub enum Commands {
     catFile(catFile),
     HashObject(HashObject),
     lsTree(lsTree),
-    WriteTree,
+    WriteTree(WriteTree),
     CommitTree(CommitTree),
     Clone(Clone),
+    UnpackObjects(UnpackObjects),
+    VerifyPack(VerifyPack),
+    ShowIndex(ShowIndex),
+    Mktag,
+    Tag(Tag),
+    Commit(Commit),
+    Add(Add),
+    Rm(Rm),
+    Mv(Mv),
+    Status(Status),
+    LsFiles(LsFiles),
+    UpdateIndex(UpdateIndex),
+    ReadTree(ReadTree),
+    CheckoutIndex(CheckoutIndex),
+    Switch(Switch),
+    Checkout(Checkout),
 }
 
 #[derive(clap::Args)]
 pub struct catFile {
     #[clap(short = 'p')]
-    pub sha1: String,
+    pub pretty: bool,
+    #[clap(short = 't')]
+    pub show_type: bool,
+    #[clap(short = 's')]
+    pub size: bool,
+    #[clap(short = 'e')]
+    pub exists: bool,
+    #[clap(long = "batch", min_values = 0, require_equals = true)]
+    pub batch: Option<Option<String>>,
+    #[clap(long = "batch-check", min_values = 0, require_equals = true)]
+    pub batch_check: Option<Option<String>>,
+    #[clap(long = "buffer")]
+    pub buffer: bool,
+    #[clap(long = "batch-all-objects")]
+    pub batch_all_objects: bool,
+    /// The object, or the expected type when an object follows
+    pub object_type: Option<String>,
+    pub object: Option<String>,
 }
 
 #[derive(clap::Args)]
 pub struct HashObject {
+    #[clap(short = 't', default_value = "blob")]
+    pub object_type: String,
     #[clap(short = 'w')]
-    pub path: PathBuf,
+    pub write: bool,
+    #[clap(long = "stdin")]
+    pub stdin: bool,
+    #[clap(long = "stdin-paths")]
+    pub stdin_paths: bool,
+    #[clap(long = "literally")]
+    pub literally: bool,
+    pub paths: Vec<PathBuf>,
 }
 
 #[derive(clap::Args)]
@@ -39,13 +81,21 @@ pub struct lsTree {
     pub sha1: String,
 }
 
+#[derive(clap::Args)]
+pub struct WriteTree {
+    #[clap(long = "prefix")]
+    pub prefix: Option<String>,
+}
+
 #[derive(clap::Args)]
 pub struct CommitTree {
     pub tree_sha: String,
     #[clap(short = 'p')]
-    pub commit_sha: String,
+    pub parents: Vec<String>,
     #[clap(short = 'm')]
-    pub message: String,
+    pub message: Vec<String>,
+    #[clap(short = 'F')]
+    pub file: Vec<PathBuf>,
 }
 
 #[derive(clap::Args)]
@@ -53,3 +103,238 @@ pub struct Clone {
     pub url: String,
     pub dir: String,
 }
+
+#[derive(clap::Args)]
+pub struct UnpackObjects {
+    #[clap(long = "strict")]
+    pub strict: bool,
+    pub pack: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+pub struct VerifyPack {
+    #[clap(short = 'v', long = "verbose")]
+    pub verbose: bool,
+    #[clap(required = true)]
+    pub packs: Vec<PathBuf>,
+}
+
+#[derive(clap::Args)]
+pub struct ShowIndex {
+    pub index: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+pub struct Tag {
+    #[clap(short = 'a')]
+    pub annotate: bool,
+    #[clap(short = 'm')]
+    pub message: Vec<String>,
+    #[clap(short = 'f')]
+    pub force: bool,
+    #[clap(short = 'l', long = "list")]
+    pub list: bool,
+    pub name: Option<String>,
+    pub object: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct Commit {
+    #[clap(short = 'm', long = "message")]
+    pub message: Vec<String>,
+    #[clap(short = 'F', long = "file")]
+    pub file: Option<PathBuf>,
+    #[clap(short = 'a', long = "all")]
+    pub all: bool,
+    #[clap(long = "amend")]
+    pub amend: bool,
+    #[clap(long = "allow-empty")]
+    pub allow_empty: bool,
+    #[clap(long = "author")]
+    pub author: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct Add {
+    #[clap(short = 'A', long = "all")]
+    pub all: bool,
+    #[clap(short = 'u', long = "update")]
+    pub update: bool,
+    #[clap(short = 'n', long = "dry-run")]
+    pub dry_run: bool,
+    #[clap(short = 'f', long = "force")]
+    pub force: bool,
+    #[clap(short = 'v', long = "verbose")]
+    pub verbose: bool,
+    pub pathspec: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct Rm {
+    #[clap(long = "cached")]
+    pub cached: bool,
+    #[clap(short = 'r')]
+    pub recursive: bool,
+    #[clap(short = 'f', long = "force")]
+    pub force: bool,
+    #[clap(short = 'n', long = "dry-run")]
+    pub dry_run: bool,
+    #[clap(short = 'q', long = "quiet")]
+    pub quiet: bool,
+    #[clap(required = true)]
+    pub pathspec: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct Mv {
+    #[clap(short = 'f', long = "force")]
+    pub force: bool,
+    #[clap(short = 'k')]
+    pub skip_errors: bool,
+    #[clap(short = 'n', long = "dry-run")]
+    pub dry_run: bool,
+    #[clap(short = 'v', long = "verbose")]
+    pub verbose: bool,
+    #[clap(required = true, min_values = 2)]
+    pub paths: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct Status {
+    #[clap(short = 's', long = "short")]
+    pub short: bool,
+    #[clap(short = 'b', long = "branch")]
+    pub branch: bool,
+    #[clap(long = "porcelain", min_values = 0, require_equals = true)]
+    pub porcelain: Option<Option<String>>,
+    #[clap(short = 'z')]
+    pub null_terminated: bool,
+    /// `-u` takes its mode attached, as in `-uno`
+    #[clap(short = 'u', long = "untracked-files", min_values = 0, max_values = 1)]
+    pub untracked_files: Option<Option<String>>,
+    #[clap(long = "ignored")]
+    pub ignored: bool,
+    #[clap(long = "no-renames")]
+    pub no_renames: bool,
+    pub pathspec: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct LsFiles {
+    #[clap(short = 'c', long = "cached")]
+    pub cached: bool,
+    #[clap(short = 'd', long = "deleted")]
+    pub deleted: bool,
+    #[clap(short = 'm', long = "modified")]
+    pub modified: bool,
+    #[clap(short = 'o', long = "others")]
+    pub others: bool,
+    #[clap(short = 'i', long = "ignored")]
+    pub ignored: bool,
+    #[clap(short = 's', long = "stage")]
+    pub stage: bool,
+    #[clap(short = 'u', long = "unmerged")]
+    pub unmerged: bool,
+    #[clap(short = 'z')]
+    pub null_terminated: bool,
+    #[clap(long = "exclude-standard")]
+    pub exclude_standard: bool,
+    pub pathspec: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct UpdateIndex {
+    #[clap(long = "add")]
+    pub add: bool,
+    #[clap(long = "remove")]
+    pub remove: bool,
+    #[clap(long = "cacheinfo", value_name = "MODE,OBJECT,PATH")]
+    pub cacheinfo: Vec<String>,
+    #[clap(long = "index-info")]
+    pub index_info: bool,
+    #[clap(long = "refresh")]
+    pub refresh: bool,
+    #[clap(short = 'q')]
+    pub quiet: bool,
+    #[clap(long = "assume-unchanged")]
+    pub assume_unchanged: bool,
+    #[clap(long = "no-assume-unchanged")]
+    pub no_assume_unchanged: bool,
+    #[clap(long = "skip-worktree")]
+    pub skip_worktree: bool,
+    #[clap(long = "no-skip-worktree")]
+    pub no_skip_worktree: bool,
+    #[clap(long = "chmod", value_name = "(+|-)x", allow_hyphen_values = true)]
+    pub chmod: Option<String>,
+    pub paths: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct ReadTree {
+    #[clap(short = 'm')]
+    pub merge: bool,
+    #[clap(long = "reset")]
+    pub reset: bool,
+    #[clap(short = 'u')]
+    pub update: bool,
+    #[clap(long = "prefix", value_name = "prefix/")]
+    pub prefix: Option<String>,
+    #[clap(long = "empty")]
+    pub empty: bool,
+    #[clap(value_name = "tree-ish")]
+    pub trees: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct CheckoutIndex {
+    #[clap(short = 'a', long = "all")]
+    pub all: bool,
+    #[clap(short = 'f', long = "force")]
+    pub force: bool,
+    #[clap(short = 'q', long = "quiet")]
+    pub quiet: bool,
+    #[clap(short = 'n', long = "no-create")]
+    pub no_create: bool,
+    /// Take the stat data of the written files into the index
+    #[clap(short = 'u', long = "index")]
+    pub index: bool,
+    #[clap(long = "prefix", value_name = "string")]
+    pub prefix: Option<String>,
+    pub paths: Vec<String>,
+}
+
+#[derive(clap::Args)]
+pub struct Switch {
+    #[clap(short = 'c', long = "create", value_name = "new-branch")]
+    pub create: Option<String>,
+    #[clap(short = 'd', long = "detach")]
+    pub detach: bool,
+    #[clap(short = 'f', long = "force", alias = "discard-changes")]
+    pub force: bool,
+    #[clap(short = 'm', long = "merge")]
+    pub merge: bool,
+    #[clap(short = 'q', long = "quiet")]
+    pub quiet: bool,
+    /// The branch to switch to, or where the new branch or detached HEAD starts
+    #[clap(value_name = "branch")]
+    pub branch: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct Checkout {
+    #[clap(short = 'b', value_name = "new-branch")]
+    pub new_branch: Option<String>,
+    #[clap(long = "detach")]
+    pub detach: bool,
+    #[clap(short = 'f', long = "force")]
+    pub force: bool,
+    #[clap(short = 'm', long = "merge")]
+    pub merge: bool,
+    #[clap(short = 'q', long = "quiet")]
+    pub quiet: bool,
+    /// A branch or commit, then the paths to check out of it
+    #[clap(value_name = "branch")]
+    pub args: Vec<String>,
+    #[clap(last = true)]
+    pub paths: Vec<String>,
+}
@@ -0,0 +1,111 @@
+use std::io::{self, BufRead, BufWriter, Write};
+
+use crate::args::catFile;
+use crate::object::Object;
+use crate::refs;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+pub struct Batch {}
+
+impl Batch {
+    /// Streams `cat-file --batch`/`--batch-check` records for every object named
+    /// on stdin, or for every object in the repository with `--batch-all-objects`.
+    pub fn cat_file(args: &catFile) -> Result<()> {
+        if args.object_type.is_some() || args.pretty || args.show_type || args.size || args.exists {
+            return Err("--batch and --batch-check can't be combined with other modes".into());
+        }
+        let (format, with_content) = match (&args.batch, &args.batch_check) {
+            (Some(format), None) => (format, true),
+            (None, Some(format)) => (format, false),
+            _ => return Err("--batch and --batch-check are mutually exclusive".into()),
+        };
+        let format = format.as_deref().unwrap_or(DEFAULT_FORMAT);
+        Batch::check_format(format)?;
+
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+
+        if args.batch_all_objects {
+            for sha1 in Object::all_objects()? {
+                Batch::write_record(&mut out, format, &sha1, "", with_content)?;
+                if !args.buffer {
+                    out.flush()?;
+                }
+            }
+            out.flush()?;
+            return Ok(());
+        }
+
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line?;
+            // the line is only split into a name and the rest when the format asks for it
+            let (name, rest) = match line.find(char::is_whitespace) {
+                Some(index) if format.contains("%(rest)") => {
+                    (&line[..index], line[index..].trim_start())
+                }
+                _ => (line.as_str(), ""),
+            };
+
+            match refs::rev_parse(name) {
+                Ok(sha1) => Batch::write_record(&mut out, format, &sha1, rest, with_content)?,
+                Err(_) => writeln!(out, "{} missing", name)?,
+            }
+            if !args.buffer {
+                out.flush()?;
+            }
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    fn check_format(format: &str) -> Result<()> {
+        let mut rest = format;
+        while let Some(start) = rest.find("%(") {
+            let end = rest[start..]
+                .find(')')
+                .ok_or_else(|| format!("unterminated format atom in {}", format))?;
+            match &rest[start + 2..start + end] {
+                "objectname" | "objecttype" | "objectsize" | "rest" => {}
+                atom => return Err(format!("unknown format element: {}", atom).into()),
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(())
+    }
+
+    fn write_record<W: Write>(
+        out: &mut W,
+        format: &str,
+        sha1: &str,
+        rest: &str,
+        with_content: bool,
+    ) -> Result<()> {
+        let mut reader = match Object::open_reader(sha1) {
+            Ok(reader) => reader,
+            Err(_) => {
+                writeln!(out, "{} missing", sha1)?;
+                return Ok(());
+            }
+        };
+
+        let record = format
+            .replace("%(objectname)", sha1)
+            .replace("%(objecttype)", &reader.object_type.to_string())
+            .replace("%(objectsize)", &reader.size.to_string())
+            .replace("%(rest)", rest);
+        writeln!(out, "{}", record)?;
+
+        if with_content {
+            reader.copy_to(out)?;
+            out.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
-use std::str;
+use std::io;
+
+use crate::object::ObjectReader;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -6,24 +8,14 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub struct Blob {}
 
 impl Blob {
-    pub fn cat_file(content: Vec<u8>) -> Result<()> {
-        let content = str::from_utf8(&content).expect("stream did not contain valid utf8");
-        // Git first constructs a header which starts by identifying the type of object --- in this case, a blob.
-        // To that first part of the header, Git adds a space followed by the size in bytes of the content, and adding a final null byte:
-        let s = &content[5..content.len()];
-        //start_length = 5 to remove "blob "
-
-        for i in 0..s.len() {
-            if let Ok(length) = s[..i].parse::<usize>() {
-                if length == s[i + 1..].len() {
-                    let s = s.replace("\u{0000}", "");
-                    let content = &s[i..];
-                    print!("{}", content);
-                    return Ok(());
-                }
-            }
+    /// Copies the blob to stdout byte for byte, without holding all of it in memory.
+    pub fn cat_file(mut reader: ObjectReader) -> Result<()> {
+        // the header ("blob <size>\0") has already been consumed by the reader
+        let copied = reader.copy_to(&mut io::stdout().lock())?;
+        if copied != reader.size as u64 {
+            return Err("Invalid Object".into());
         }
 
-        Err("Invalid Object".into())
+        Ok(())
     }
 }
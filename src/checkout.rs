@@ -0,0 +1,340 @@
+use std::ffi::OsStr;
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::args::CheckoutIndex;
+use crate::config::Config;
+use crate::index::{Index, IndexEntry};
+use crate::object::Object;
+use crate::utils;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// What became of an entry handed to [`Checkout::checkout_entries`].
+pub enum Outcome {
+    /// Written out; the stat data of the new file.
+    Written(Metadata),
+    /// Already there as staged, or left alone.
+    Skipped,
+    /// Not written, and the reason was reported.
+    Failed,
+}
+
+/// What is left to do for an entry once what was in its way is dealt with.
+enum Step {
+    Done(Outcome),
+    /// A blob to write to this path, which can be done on another thread.
+    Write(String),
+}
+
+/// Writes index entries out to the working tree: blobs as files with their
+/// executable bit, symlinks as symlinks and submodules as empty directories.
+#[derive(Default)]
+pub struct Checkout {
+    /// Put in front of every path (`--prefix`), to write the files elsewhere.
+    pub base_dir: String,
+    /// Replace what is in the way instead of refusing to.
+    pub force: bool,
+    /// Don't report files that are in the way.
+    pub quiet: bool,
+    /// Leave out files that are missing, only writing over the ones already
+    /// there (`-n`).
+    pub not_new: bool,
+}
+
+impl Checkout {
+    /// Writes each of `entries` unless its file already looks as staged, and
+    /// returns their outcomes in order. From `checkout.thresholdForParallelism`
+    /// files on, they are written by `checkout.workers` threads; everything
+    /// that touches what was already there is still done here beforehand.
+    pub fn checkout_entries(&self, index: &Index, entries: &[&IndexEntry]) -> Result<Vec<Outcome>> {
+        let config = Config::cached()?;
+        let workers = match config.get_int("checkout.workers")?.unwrap_or(1) {
+            workers if workers < 1 => thread::available_parallelism().map_or(1, |x| x.get()),
+            workers => workers as usize,
+        };
+        let threshold = config
+            .get_int("checkout.thresholdForParallelism")?
+            .unwrap_or(100);
+
+        // with a single worker, each file is written as soon as it can be
+        let mut outcomes = Vec::with_capacity(entries.len());
+        let mut pending = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            match self.prepare_entry(index, entry)? {
+                Step::Done(outcome) => outcomes.push(outcome),
+                Step::Write(path) if workers > 1 => {
+                    outcomes.push(Outcome::Skipped);
+                    pending.push((i, path));
+                }
+                Step::Write(path) => outcomes.push(written(write_entry(entry, &path))),
+            }
+        }
+
+        if pending.len() as i64 >= threshold {
+            let results = write_parallel(entries, &pending, workers);
+            for ((i, _), result) in pending.iter().zip(results) {
+                outcomes[*i] = written(result.map_err(Error::from));
+            }
+        } else {
+            for (i, path) in &pending {
+                outcomes[*i] = written(write_entry(entries[*i], path));
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Makes way for `entry`: nothing to do when its file already looks as
+    /// staged, and something else at the path is only removed with `force`.
+    /// Symlinks and submodules are written right away.
+    fn prepare_entry(&self, index: &Index, entry: &IndexEntry) -> Result<Step> {
+        if !utils::verify_path(&entry.path) {
+            eprintln!("error: invalid path '{}'", entry.path);
+            return Ok(Step::Done(Outcome::Failed));
+        }
+        let path = format!("{}{}", self.base_dir, entry.path);
+
+        match self.lstat(&path) {
+            Some(metadata) => {
+                // a submodule only needs its directory
+                if metadata.is_dir() && entry.mode == 0o160000 {
+                    return Ok(Step::Done(Outcome::Skipped));
+                }
+                if !index.stat_changed(entry, &metadata)? {
+                    return Ok(Step::Done(Outcome::Skipped));
+                }
+                if !self.force {
+                    if !self.quiet {
+                        eprintln!("{} already exists, no checkout", path);
+                    }
+                    return Ok(Step::Done(Outcome::Failed));
+                }
+                // written afresh rather than over, so the new mode applies
+                match metadata.is_dir() {
+                    true => fs::remove_dir_all(&path)?,
+                    false => fs::remove_file(&path)
+                        .map_err(|e| format!("unable to unlink old '{}': {}", path, e))?,
+                }
+            }
+            None if self.not_new => return Ok(Step::Done(Outcome::Skipped)),
+            None => {}
+        }
+
+        self.create_directories(&path)?;
+        match entry.mode {
+            0o160000 | 0o120000 => Ok(Step::Done(written(write_entry(entry, &path)))),
+            _ => Ok(Step::Write(path)),
+        }
+    }
+
+    /// Deletes the file at `path`, which left the index, and the directories
+    /// it leaves empty.
+    pub fn remove_entry(&self, path: &str) -> Result<()> {
+        let path = format!("{}{}", self.base_dir, path);
+        match self.lstat(&path) {
+            Some(metadata) if metadata.is_dir() => {
+                // a submodule's directory only goes when it is empty
+                let _ = fs::remove_dir(&path);
+            }
+            Some(_) => fs::remove_file(&path)?,
+            None => return Ok(()),
+        }
+
+        let mut dir = Path::new(&path).parent();
+        while let Some(parent) = dir {
+            if parent.as_os_str().len() <= self.base_dir.trim_end_matches('/').len()
+                || fs::remove_dir(parent).is_err()
+            {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+
+    /// Whether every directory leading to `path` is a real directory. Files
+    /// are never written through a symlink, except for one in `base_dir`.
+    fn has_dirs_only_path(&self, path: &str) -> bool {
+        path.match_indices('/').all(|(index, _)| {
+            let metadata = match index < self.base_dir.len() {
+                true => fs::metadata(&path[..index]),
+                false => fs::symlink_metadata(&path[..index]),
+            };
+            metadata.is_ok_and(|metadata| metadata.is_dir())
+        })
+    }
+
+    /// What is at `path`, where nothing counts when something other than a
+    /// directory is in the way of its leading directories.
+    fn lstat(&self, path: &str) -> Option<Metadata> {
+        match self.has_dirs_only_path(path) {
+            true => fs::symlink_metadata(path).ok(),
+            false => None,
+        }
+    }
+
+    /// Makes the directories leading to `path`. A file or symlink in the way
+    /// is only replaced with `force`.
+    fn create_directories(&self, path: &str) -> Result<()> {
+        for (index, _) in path.match_indices('/') {
+            let dir = &path[..index];
+            if self.has_dirs_only_path(&path[..index + 1]) {
+                continue;
+            }
+            if let Err(e) = fs::create_dir(dir) {
+                let replaced = e.kind() == ErrorKind::AlreadyExists
+                    && self.force
+                    && fs::remove_file(dir).is_ok()
+                    && fs::create_dir(dir).is_ok();
+                if !replaced {
+                    return Err(format!("cannot create directory at '{}': {}", dir, e).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes staged files out, as `checkout-index` does: the named ones or,
+    /// with `-a`, every merged entry.
+    pub fn checkout_index(args: &CheckoutIndex) -> Result<()> {
+        if args.all && !args.paths.is_empty() {
+            return Err("git checkout-index: don't mix '--all' and explicit filenames".into());
+        }
+        let checkout = Checkout {
+            base_dir: args.prefix.clone().unwrap_or_default(),
+            force: args.force,
+            quiet: args.quiet,
+            not_new: args.no_create,
+        };
+        let mut index = Index::read()?;
+
+        let mut failed = false;
+        let mut positions = Vec::new();
+        for path in &args.paths {
+            let path = utils::normalize_pathspec(path);
+            let stages: Vec<usize> = (0..index.entries.len())
+                .filter(|i| index.entries[*i].path == path)
+                .collect();
+            match stages.iter().find(|i| index.entries[**i].stage == 0) {
+                // a path named twice is only written once
+                Some(i) if positions.contains(i) => {}
+                Some(i) => positions.push(*i),
+                None if args.quiet => failed = true,
+                None => {
+                    let reason = match stages.is_empty() {
+                        true => "is not in the cache",
+                        false => "is unmerged",
+                    };
+                    eprintln!("git checkout-index: {} {}", path, reason);
+                    failed = true;
+                }
+            }
+        }
+        if args.all {
+            positions = (0..index.entries.len())
+                .filter(|i| index.entries[*i].stage == 0 && !index.entries[*i].skip_worktree)
+                .collect();
+        }
+
+        let entries: Vec<&IndexEntry> = positions.iter().map(|i| &index.entries[*i]).collect();
+        let outcomes = checkout.checkout_entries(&index, &entries)?;
+        for (i, outcome) in positions.into_iter().zip(outcomes) {
+            match outcome {
+                Outcome::Written(metadata) => {
+                    index.entries[i] = index.entries[i].with_stat(&metadata)
+                }
+                Outcome::Skipped => {}
+                Outcome::Failed => failed = true,
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+
+        if args.index {
+            index.write()?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of writing a file, with the reason reported if that failed.
+fn written(result: Result<Metadata>) -> Outcome {
+    match result {
+        Ok(metadata) => Outcome::Written(metadata),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            Outcome::Failed
+        }
+    }
+}
+
+/// Writes the `pending` blobs of `entries` on `workers` threads, which take
+/// the next file whenever they are done with one.
+fn write_parallel(
+    entries: &[&IndexEntry],
+    pending: &[(usize, String)],
+    workers: usize,
+) -> Vec<std::result::Result<Metadata, String>> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.min(pending.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let n = next.fetch_add(1, Ordering::Relaxed);
+                        let (i, path) = match pending.get(n) {
+                            Some(pending) => pending,
+                            None => break,
+                        };
+                        // the error type can't leave the thread, its message can
+                        let result = write_entry(entries[*i], path).map_err(|e| e.to_string());
+                        results.push((n, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("checkout worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(n, _)| *n);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Writes the object of `entry` to `path`, where nothing is in the way, and
+/// returns the stat data of what was written.
+fn write_entry(entry: &IndexEntry, path: &str) -> Result<Metadata> {
+    match entry.mode {
+        0o160000 => fs::create_dir(path)
+            .map_err(|_| format!("cannot create submodule directory {}", path))?,
+        0o120000 => {
+            let target = Object::open(&entry.sha1)
+                .map_err(|_| format!("unable to read sha1 file of {} ({})", path, entry.sha1))?;
+            symlink(OsStr::from_bytes(target.body()), path)
+                .map_err(|e| format!("unable to create symlink {}: {}", path, e))?;
+        }
+        mode => {
+            let mut reader = Object::open_reader(&entry.sha1)
+                .map_err(|_| format!("unable to read sha1 file of {} ({})", path, entry.sha1))?;
+            // the umask applies, as it would to any new file
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(if mode & 0o100 != 0 { 0o777 } else { 0o666 })
+                .open(path)
+                .map_err(|e| format!("unable to create file {}: {}", path, e))?;
+            reader.copy_to(&mut file)?;
+        }
+    }
+    Ok(fs::symlink_metadata(path)?)
+}
@@ -1,9 +1,15 @@
+use std::fs;
 use std::path::PathBuf;
 
 use reqwest::StatusCode;
 
 use crate::args::Clone as CloneStruct;
+use crate::config::Config;
+use crate::index::Index;
 use crate::packfile::Packfile;
+use crate::tree::Tree;
+use crate::unpack::{Merge, Unpack};
+use crate::{packindex, refs};
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -11,13 +17,15 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub struct Clone {
     client: reqwest::Client,
     url: String,
-    _path: PathBuf,
+    /// The URL as given, which is what `remote.origin.url` records.
+    remote: String,
+    path: PathBuf,
 }
 
 impl Clone {
     pub fn new(args: &CloneStruct) -> Self {
         let mut url = args.url.clone();
-        let _path = PathBuf::from(&args.dir);
+        let path = PathBuf::from(&args.dir);
 
         if url.ends_with(".git/") {
             url.pop();
@@ -31,16 +39,109 @@ impl Clone {
         Clone {
             client: reqwest::Client::new(),
             url,
-            _path,
+            remote: args.url.clone(),
+            path,
         }
     }
 
     pub async fn clone(&self) -> Result<()> {
-        Clone::request(&self).await?;
+        let is_empty = match fs::read_dir(&self.path) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(_) => !self.path.exists(),
+        };
+        if !is_empty {
+            return Err(format!(
+                "destination path '{}' already exists and is not an empty directory.",
+                self.path.display()
+            )
+            .into());
+        }
+        fs::create_dir_all(&self.path)?;
+        std::env::set_current_dir(&self.path)?;
+        // whatever was read so far belongs to the repository we started in
+        Config::reset_cache();
+        packindex::reset_packs();
+        fs::create_dir_all(".git/objects")?;
+        fs::create_dir_all(".git/refs/heads")?;
+        fs::create_dir_all(".git/refs/tags")?;
+
+        let (advertised, head_branch) = self.request().await?;
+
+        let mut head = None;
+        for (sha1, name) in &advertised {
+            if let Some(branch) = name.strip_prefix("refs/heads/") {
+                refs::write_ref(&format!("refs/remotes/origin/{}", branch), sha1)?;
+            } else if name.starts_with("refs/tags/") && !name.ends_with("^{}") {
+                refs::write_ref(name, sha1)?;
+            } else if name == "HEAD" {
+                head = Some(sha1);
+            }
+        }
+
+        // the branch the remote HEAD points at becomes ours, and gets checked out
+        let head = match head {
+            Some(head) => head,
+            None => {
+                fs::write(".git/HEAD", "ref: refs/heads/master\n")?;
+                return self.write_config(None);
+            }
+        };
+        let branch = head_branch.or_else(|| {
+            advertised
+                .iter()
+                .find(|(sha1, name)| sha1 == head && name.starts_with("refs/heads/"))
+                .map(|(_, name)| name.clone())
+        });
+        match &branch {
+            Some(branch) => {
+                refs::write_ref(branch, head)?;
+                fs::write(".git/HEAD", format!("ref: {}\n", branch))?;
+            }
+            None => fs::write(".git/HEAD", format!("{}\n", head))?,
+        }
+        self.write_config(branch.as_deref())?;
+
+        let tree = refs::peel_to_tree(head)?;
+        let mut index = Index::new();
+        let mut unpack = Unpack::new(Merge::OneWay);
+        unpack.update = true;
+        unpack.unpack(&mut index, std::slice::from_ref(&tree))?;
+        index.cache_tree = Some(Tree::cache_tree(&tree)?);
+        index.write()?;
+        Ok(())
+    }
+
+    /// Writes the repository's config: `origin` pointing at the remote with
+    /// its branches fetched under `refs/remotes/origin/`, and `branch` (a full
+    /// ref name) set to track its namesake there.
+    fn write_config(&self, branch: Option<&str>) -> Result<()> {
+        let mut config = String::from(
+            "[core]\n\
+             \trepositoryformatversion = 0\n\
+             \tfilemode = true\n\
+             \tbare = false\n\
+             \tlogallrefupdates = true\n",
+        );
+        config.push_str(&format!(
+            "[remote \"origin\"]\n\turl = {}\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n",
+            self.remote
+        ));
+        if let Some(branch) = branch {
+            if let Some(name) = branch.strip_prefix("refs/heads/") {
+                config.push_str(&format!(
+                    "[branch \"{}\"]\n\tremote = origin\n\tmerge = {}\n",
+                    name, branch
+                ));
+            }
+        }
+        fs::write(".git/config", config)?;
+        Config::reset_cache();
         Ok(())
     }
 
-    async fn request(&self) -> Result<()> {
+    /// Fetches every advertised ref into the object store, returning the refs
+    /// and the branch the remote's `HEAD` points at.
+    async fn request(&self) -> Result<(Vec<(String, String)>, Option<String>)> {
         let get_uri = format!("{}/info/refs?service=git-upload-pack", self.url);
         let post_uri = format!("{}/git-upload-pack", self.url);
 
@@ -50,6 +151,7 @@ impl Clone {
         }
 
         let parsed_resp = parsed_response(resp.text().await?); //parsed_resp is a Vec<String>, every element ends at "\n"
+        let advertised = advertised_refs(&parsed_resp);
 
         let hash_vec = post_content(parsed_resp);
 
@@ -70,10 +172,10 @@ impl Clone {
             let _ack = iter.next().expect("ack reply missing");
             let pack_bytes = iter.next().expect("pack reply missing");
             let pack_file = Packfile::new(pack_bytes)?;
-            pack_file.parse_pack();
+            pack_file.unpack(false)?;
         }
 
-        Ok(())
+        Ok(advertised)
     }
 }
 
@@ -91,6 +193,35 @@ fn parsed_response(resp: String) -> Vec<String> {
     parsed_resp
 }
 
+/// The `(id, name)` of every ref in the advertisement, and the target of
+/// `HEAD` when the server names it among its capabilities.
+fn advertised_refs(resp: &[String]) -> (Vec<(String, String)>, Option<String>) {
+    let mut refs = Vec::new();
+    let mut head = None;
+    for line in resp {
+        // the flush packet ending the service announcement sticks to the first ref
+        let line = line.trim_end_matches('\n');
+        let line = line.strip_prefix("0000").unwrap_or(line);
+        let (sha1, rest) = match line.get(4..44).zip(line.get(44..)) {
+            Some(split) => split,
+            None => continue,
+        };
+        if !sha1.bytes().all(|x| x.is_ascii_hexdigit()) {
+            continue;
+        }
+        let rest = rest.trim_start_matches(' ');
+        let (name, capabilities) = rest.split_once('\0').unwrap_or((rest, ""));
+        if let Some(target) = capabilities
+            .split(' ')
+            .find_map(|capability| capability.strip_prefix("symref=HEAD:"))
+        {
+            head = Some(target.to_string());
+        }
+        refs.push((sha1.to_string(), name.to_string()));
+    }
+    (refs, head)
+}
+
 fn post_content(resp: Vec<String>) -> Vec<String> {
     let mut hash_vec = Vec::new();
     for line in resp {
@@ -101,8 +232,8 @@ fn post_content(resp: Vec<String>) -> Vec<String> {
                 .expect("Could not parse ref hash from response")[4..];
             let mut want_string = String::new();
             want_string.push_str("0032want ");
-            want_string.push_str(&hash);
-            want_string.push_str("\n");
+            want_string.push_str(hash);
+            want_string.push('\n');
             want_string.push_str("00000009done\n");
             hash_vec.push(want_string);
         }
@@ -1,10 +1,24 @@
 use crate::args::*;
+use crate::batch::Batch;
 use crate::blob::Blob;
+use crate::checkout::Checkout;
 use crate::commit::Commit;
+use crate::fsck;
+use crate::index::Index;
+use crate::ls_files::LsFiles;
 use crate::object::{Object, ObjectType};
+use crate::packfile::Packfile;
+use crate::packindex::PackIndex;
+use crate::refs;
+use crate::staging::Staging;
+use crate::status::Status;
+use crate::switch::Switch;
 use crate::tree::Tree;
+use crate::unpack::Unpack;
 use clap::Parser;
 use std::fs;
+use std::io::{BufRead, Read};
+use std::path::Path;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -17,9 +31,25 @@ pub async fn run() -> Result<()> {
         Commands::catFile(args) => cat_file(args),
         Commands::HashObject(args) => hash_object(args),
         Commands::lsTree(args) => ls_tree(args),
-        Commands::WriteTree => Tree::write_tree(),
+        Commands::WriteTree(args) => Tree::write_tree(args),
         Commands::CommitTree(args) => commit_tree(args),
         Commands::Clone(args) => clone(args).await,
+        Commands::UnpackObjects(args) => unpack_objects(args),
+        Commands::VerifyPack(args) => verify_pack(args),
+        Commands::ShowIndex(args) => show_index(args),
+        Commands::Mktag => crate::tag::Tag::mktag(),
+        Commands::Tag(args) => tag(args),
+        Commands::Commit(args) => commit(args),
+        Commands::Add(args) => Staging::add(args),
+        Commands::Rm(args) => Staging::rm(args),
+        Commands::Mv(args) => Staging::mv(args),
+        Commands::Status(args) => Status::status(args),
+        Commands::LsFiles(args) => LsFiles::ls_files(args),
+        Commands::UpdateIndex(args) => Index::update_index(args),
+        Commands::ReadTree(args) => Unpack::read_tree(args),
+        Commands::CheckoutIndex(args) => Checkout::checkout_index(args),
+        Commands::Switch(args) => Switch::switch(args),
+        Commands::Checkout(args) => Switch::checkout(args),
     }
 }
 
@@ -33,18 +63,110 @@ pub fn init() -> Result<()> {
 }
 
 pub fn cat_file(args: &catFile) -> Result<()> {
-    let object = Object::open(&args.sha1)?;
+    if args.batch.is_some() || args.batch_check.is_some() {
+        return Batch::cat_file(args);
+    }
 
-    match object.object_type {
-        ObjectType::Blob => Blob::cat_file(object.content),
-        _ => todo!(),
+    let modes = [args.pretty, args.show_type, args.size, args.exists];
+    let mode_count = modes.iter().filter(|x| **x).count();
+
+    let (expected_type, name) = match (&args.object_type, &args.object, mode_count) {
+        (Some(object_type), Some(object), 0) => (Some(object_type.as_str()), object.as_str()),
+        (Some(object), None, 1) => (None, object.as_str()),
+        _ => return Err("usage: cat-file (-t | -s | -e | -p | <type>) <object>".into()),
+    };
+
+    let sha1 = refs::rev_parse(name)?;
+    let reader = match Object::open_reader(&sha1) {
+        Ok(reader) => reader,
+        Err(_) if args.exists => std::process::exit(1),
+        Err(e) => return Err(e),
+    };
+
+    if args.exists {
+        return Ok(());
+    }
+    if args.show_type {
+        println!("{}", reader.object_type);
+        return Ok(());
+    }
+    if args.size {
+        println!("{}", reader.size);
+        return Ok(());
+    }
+
+    if let Some(expected_type) = expected_type {
+        ObjectType::from_name(expected_type)
+            .map_err(|_| format!("invalid object type \"{}\"", expected_type))?;
+        let mut reader = if reader.object_type.to_string() == expected_type {
+            reader
+        } else {
+            let peeled = refs::peel(&sha1, expected_type)
+                .map_err(|_| format!("git cat-file {}: bad file", name))?;
+            Object::open_reader(&peeled)?
+        };
+        reader.copy_to(&mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+
+    match reader.object_type {
+        ObjectType::Blob => Blob::cat_file(reader),
+        ObjectType::Tree => Tree::cat_file(Object::open(&sha1)?.body()),
+        _ => {
+            let mut reader = reader;
+            reader.copy_to(&mut std::io::stdout().lock())?;
+            Ok(())
+        }
     }
 }
 
 pub fn hash_object(args: &HashObject) -> Result<()> {
-    let byte_vec = fs::read(&args.path)?;
-    let hex_sha1 = Object::hash_object(ObjectType::Blob, byte_vec)?;
-    println!("{}", hex_sha1);
+    if args.stdin_paths && (args.stdin || !args.paths.is_empty()) {
+        return Err("Can't use --stdin-paths with --stdin or with paths".into());
+    }
+    if !args.literally && !["blob", "tree", "commit", "tag"].contains(&args.object_type.as_str()) {
+        return Err(format!("invalid object type \"{}\"", args.object_type).into());
+    }
+
+    // blobs need no validation, so they are streamed instead of read into memory
+    let streamed = args.literally || args.object_type == "blob";
+    let hash = |byte_vec: Vec<u8>, source: &str| -> Result<()> {
+        if !args.literally {
+            fsck::check_object(&args.object_type, &byte_vec)
+                .map_err(|e| format!("{}: corrupt {}: {}", source, args.object_type, e))?;
+        }
+        let hex_sha1 = Object::hash_bytes(&args.object_type, byte_vec, args.write)?;
+        println!("{}", hex_sha1);
+        Ok(())
+    };
+    let hash_path = |path: &Path| -> Result<()> {
+        if !streamed {
+            return hash(fs::read(path)?, &path.display().to_string());
+        }
+        let f = fs::File::open(path)?;
+        let size = f.metadata()?.len();
+        let hex_sha1 = Object::hash_reader(&args.object_type, f, size, args.write)?;
+        println!("{}", hex_sha1);
+        Ok(())
+    };
+
+    if args.stdin {
+        let mut byte_vec = Vec::new();
+        std::io::stdin().read_to_end(&mut byte_vec)?;
+        hash(byte_vec, "<stdin>")?;
+    }
+
+    for path in &args.paths {
+        hash_path(path)?;
+    }
+
+    if args.stdin_paths {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            hash_path(Path::new(&line?))?;
+        }
+    }
+
     Ok(())
 }
 
@@ -65,3 +187,46 @@ pub async fn clone(args: &Clone) -> Result<()> {
     clone.clone().await?;
     Ok(())
 }
+
+pub fn unpack_objects(args: &UnpackObjects) -> Result<()> {
+    let bytes = match &args.pack {
+        Some(path) => fs::read(path)?,
+        None => {
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes)?;
+            bytes
+        }
+    };
+
+    let pack_file = Packfile::new(bytes)?;
+    pack_file.unpack(args.strict)?;
+    Ok(())
+}
+
+pub fn verify_pack(args: &VerifyPack) -> Result<()> {
+    for pack in &args.packs {
+        PackIndex::verify_pack(pack, args.verbose)?;
+    }
+    Ok(())
+}
+
+pub fn show_index(args: &ShowIndex) -> Result<()> {
+    let bytes = match &args.index {
+        Some(path) => fs::read(path)?,
+        None => {
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes)?;
+            bytes
+        }
+    };
+
+    PackIndex::new(bytes)?.show_index()
+}
+
+pub fn tag(args: &Tag) -> Result<()> {
+    crate::tag::Tag::tag(args)
+}
+
+pub fn commit(args: &crate::args::Commit) -> Result<()> {
+    Commit::commit(args)
+}
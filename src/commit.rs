@@ -1,137 +1,428 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::io::Read;
+
+use crate::args::Commit as CommitArgs;
+use crate::index::Index;
 use crate::object::ObjectType;
+use crate::refs;
+use crate::signature::Signature;
+use crate::tree::{Tree, EMPTY_TREE};
+use crate::utils;
 use crate::{args::CommitTree, object::Object};
 
-use std::{
-    convert::TryInto,
-    time::{SystemTime, UNIX_EPOCH},
-};
-
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub struct Commit {}
-
-pub struct CommitEntry<'a> {
-    tree: [u8; 40],
-    parent: [u8; 40], //only one parent will be provided
-    author: Author,
-    committer: Committer,
-    message: &'a str, //only one message will be provided
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub encoding: Option<Vec<u8>>,
+    /// Every other header (`gpgsig`, `mergetag` or ones we don't know about), in
+    /// the order they appeared. Continuation lines are joined with `\n`.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    pub message: Vec<u8>,
 }
 
-struct Author {
-    name: String,
-    email: String,
-    time: u64,
-    offset: String,
-}
+impl Commit {
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let (header, message) = match content.windows(2).position(|x| x == b"\n\n") {
+            Some(index) => (&content[..index], &content[index + 2..]),
+            None => return Err("commit has no blank line after its headers".into()),
+        };
+        // values are bytes: idents and messages of commits with an `encoding`
+        // header, and whatever unknown headers hold, need not be UTF-8
+        // multi-line values (signatures, embedded tags) continue on lines starting with a space
+        let mut headers: Vec<(&str, Vec<u8>)> = Vec::new();
+        for line in header.split(|x| *x == b'\n') {
+            if let Some(continuation) = line.strip_prefix(b" ") {
+                let (_, value) = headers
+                    .last_mut()
+                    .ok_or("commit starts with a continuation line")?;
+                value.push(b'\n');
+                value.extend(continuation);
+                continue;
+            }
+            let bad = || format!("bad commit header {:?}", String::from_utf8_lossy(line));
+            let space = line.iter().position(|x| *x == b' ').ok_or_else(bad)?;
+            let key = std::str::from_utf8(&line[..space]).map_err(|_| bad())?;
+            headers.push((key, line[space + 1..].to_vec()));
+        }
 
-struct Committer {
-    name: String,
-    email: String,
-    time: u64,
-    offset: String,
-}
+        let mut headers = headers.into_iter().peekable();
+        let tree = match headers.next() {
+            Some(("tree", tree)) => String::from_utf8(tree)?,
+            _ => return Err("commit has no tree".into()),
+        };
 
-trait StructToBytes {
-    fn as_bytes(&self) -> Vec<u8>;
-}
+        let mut parents = Vec::new();
+        while let Some(("parent", _)) = headers.peek() {
+            parents.push(String::from_utf8(headers.next().unwrap().1)?);
+        }
 
-impl StructToBytes for Author {
-    fn as_bytes(&self) -> Vec<u8> {
-        let mut vec: Vec<u8> = Vec::new();
-        vec.extend("author ".as_bytes());
-        vec.extend(self.name.as_bytes());
-        vec.extend(" <".as_bytes());
-        vec.extend(self.email.as_bytes());
-        vec.extend("> ".as_bytes());
-        vec.extend(self.time.to_string().as_bytes());
-        vec.push(' ' as u8);
-        vec.extend(self.offset.as_bytes());
-        vec.push('\n' as u8);
-        vec
+        let author = match headers.next() {
+            Some(("author", author)) => Signature::parse(&author)?,
+            _ => return Err("commit has no author".into()),
+        };
+        let committer = match headers.next() {
+            Some(("committer", committer)) => Signature::parse(&committer)?,
+            _ => return Err("commit has no committer".into()),
+        };
+
+        let encoding = match headers.peek() {
+            Some(("encoding", _)) => Some(headers.next().unwrap().1),
+            _ => None,
+        };
+
+        Ok(Commit {
+            tree,
+            parents,
+            author,
+            committer,
+            encoding,
+            extra_headers: headers
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+            message: message.to_vec(),
+        })
     }
-}
 
-impl StructToBytes for Committer {
-    fn as_bytes(&self) -> Vec<u8> {
+    /// The raw object content. An unmodified parsed commit serializes back to
+    /// exactly the bytes it was parsed from, so its id doesn't change.
+    pub fn serialize(&self) -> Vec<u8> {
         let mut vec: Vec<u8> = Vec::new();
-        vec.extend("committer ".as_bytes());
-        vec.extend(self.name.as_bytes());
-        vec.extend(" <".as_bytes());
-        vec.extend(self.email.as_bytes());
-        vec.extend("> ".as_bytes());
-        vec.extend(self.time.to_string().as_bytes());
-        vec.push(' ' as u8);
-        vec.extend(self.offset.as_bytes());
-        vec.push('\n' as u8);
+
+        vec.extend(format!("tree {}\n", self.tree).as_bytes());
+        for parent in &self.parents {
+            vec.extend(format!("parent {}\n", parent).as_bytes());
+        }
+        vec.extend(b"author ");
+        vec.extend(self.author.to_bytes());
+        vec.extend(b"\ncommitter ");
+        vec.extend(self.committer.to_bytes());
+        vec.push(b'\n');
+        if let Some(encoding) = &self.encoding {
+            vec.extend(b"encoding ");
+            vec.extend(encoding);
+            vec.push(b'\n');
+        }
+        for (key, value) in &self.extra_headers {
+            vec.extend(format!("{} ", key).as_bytes());
+            for byte in value {
+                vec.push(*byte);
+                if *byte == b'\n' {
+                    vec.push(b' ');
+                }
+            }
+            vec.push(b'\n');
+        }
+
+        vec.push(b'\n');
+        vec.extend(&self.message);
+
         vec
     }
-}
 
-impl Commit {
-    pub fn commit_tree(args: &CommitTree) -> Result<()> {
-        let author = Author {
-            name: String::from("Aayush author"),
-            email: String::from("aayushauthor@gmail.com"),
-            time: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            offset: String::from("+0530"),
+    /// The first paragraph of the message on a single line, as shown in
+    /// summaries and reflogs.
+    pub fn subject(&self) -> String {
+        let message = String::from_utf8_lossy(&self.message);
+        let paragraph = message.trim_start().split("\n\n").next().unwrap_or("");
+        paragraph
+            .lines()
+            .map(|line| line.trim())
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    /// How many commits `ours` has that `theirs` doesn't, and the other way
+    /// round, as reported against a branch's upstream.
+    pub fn ahead_behind(ours: &str, theirs: &str) -> Result<(usize, usize)> {
+        let (ours, theirs) = Commit::paint_down(&[ours], &[theirs])?;
+        Ok((ours.len(), theirs.len()))
+    }
+
+    /// The commits reachable from `ours` but from none of `theirs`, and the
+    /// other way round, each newest first.
+    ///
+    /// Both sides are painted in commit date order the way git's commit-reach
+    /// does it: a commit reached from both sides is common and so is all of
+    /// its history, and the walk stops once only common commits are left to
+    /// visit, so what lies below the merge bases is never read.
+    pub fn paint_down(ours: &[&str], theirs: &[&str]) -> Result<(Vec<String>, Vec<String>)> {
+        const OURS: u8 = 1;
+        const THEIRS: u8 = 2;
+        const BOTH: u8 = OURS | THEIRS;
+
+        struct Walk {
+            paint: HashMap<String, u8>,
+            // the parents of every commit reached so far
+            parents: HashMap<String, Vec<String>>,
+            queue: BinaryHeap<(i64, String)>,
+        }
+        impl Walk {
+            // queues a commit the first time it is reached; one already reached
+            // passes new paint on to its history straight away
+            fn paint(&mut self, sha1: &str, flags: u8) -> Result<()> {
+                let mut pending = vec![sha1.to_string()];
+                while let Some(sha1) = pending.pop() {
+                    let old = self.paint.get(&sha1).copied();
+                    match old {
+                        Some(old) if old | flags == old => continue,
+                        Some(old) => {
+                            self.paint.insert(sha1.clone(), old | flags);
+                            if let Some(parents) = self.parents.get(&sha1) {
+                                pending.extend(parents.iter().cloned());
+                            }
+                        }
+                        None => {
+                            let commit = Commit::parse(Object::open(&sha1)?.body())?;
+                            self.paint.insert(sha1.clone(), flags);
+                            self.parents.insert(sha1.clone(), commit.parents);
+                            self.queue.push((commit.committer.time, sha1));
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let mut walk = Walk {
+            paint: HashMap::new(),
+            parents: HashMap::new(),
+            queue: BinaryHeap::new(),
         };
+        for sha1 in ours {
+            walk.paint(sha1, OURS)?;
+        }
+        for sha1 in theirs {
+            walk.paint(sha1, THEIRS)?;
+        }
 
-        let committer = Committer {
-            name: String::from("Aayush committer"),
-            email: String::from("aayushcommitter@gmail.com"),
-            time: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            offset: String::from("+0530"),
+        let mut visited: Vec<String> = Vec::new();
+        while walk.queue.iter().any(|(_, sha1)| walk.paint[sha1] != BOTH) {
+            let (_, sha1) = match walk.queue.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            let flags = walk.paint[&sha1];
+            for parent in walk.parents[&sha1].clone() {
+                walk.paint(&parent, flags)?;
+            }
+            visited.push(sha1);
+        }
+
+        let only = |side: u8| -> Vec<String> {
+            visited
+                .iter()
+                .filter(|sha1| walk.paint[*sha1] == side)
+                .cloned()
+                .collect()
         };
+        Ok((only(OURS), only(THEIRS)))
+    }
 
-        let tree: [u8; 40] = args.tree_sha.as_bytes().try_into()?;
-        let parent: [u8; 40] = args.commit_sha.as_bytes().try_into()?;
-        let message = &args.message;
+    /// Opens `name` and checks it is an object of `object_type`, returning its id.
+    fn resolve_typed(name: &str, object_type: &str) -> Result<String> {
+        let sha1 = refs::rev_parse(name)?;
+        if Object::open_reader(&sha1)?.object_type.to_string() != object_type {
+            return Err(format!("{} is not a valid '{}' object", sha1, object_type).into());
+        }
+        Ok(sha1)
+    }
+
+    /// Builds the message the way `git commit-tree` does: each `-m` and `-F` is a
+    /// paragraph of its own, and stdin is read only when neither is given.
+    fn read_message(args: &CommitTree) -> Result<Vec<u8>> {
+        let mut message: Vec<u8> = Vec::new();
+        let mut paragraphs: Vec<Vec<u8>> = args
+            .message
+            .iter()
+            .map(|paragraph| paragraph.clone().into_bytes())
+            .collect();
+        for path in &args.file {
+            let mut paragraph = Vec::new();
+            if path.as_os_str() == "-" {
+                std::io::stdin().read_to_end(&mut paragraph)?;
+            } else {
+                paragraph = fs::read(path)
+                    .map_err(|e| format!("could not read log file '{}': {}", path.display(), e))?;
+            }
+            paragraphs.push(paragraph);
+        }
+        if paragraphs.is_empty() {
+            std::io::stdin().read_to_end(&mut message)?;
+            return Ok(message);
+        }
+
+        for paragraph in paragraphs {
+            if !message.is_empty() {
+                message.push(b'\n');
+            }
+            message.extend(paragraph);
+            if !message.is_empty() && !message.ends_with(b"\n") {
+                message.push(b'\n');
+            }
+        }
+        Ok(message)
+    }
 
-        let commit_entry = CommitEntry {
+    pub fn commit_tree(args: &CommitTree) -> Result<()> {
+        let tree = Commit::resolve_typed(&args.tree_sha, "tree")?;
+
+        let mut parents: Vec<String> = Vec::new();
+        for parent in &args.parents {
+            let parent = Commit::resolve_typed(parent, "commit")?;
+            if parents.contains(&parent) {
+                eprintln!("duplicate parent {} ignored", parent);
+                continue;
+            }
+            parents.push(parent);
+        }
+
+        let message = Commit::read_message(args)?;
+
+        let author = Signature::author()?;
+        let committer = Signature::committer()?;
+
+        let commit = Commit {
             tree,
-            parent,
+            parents,
             author,
             committer,
+            encoding: None,
+            extra_headers: Vec::new(),
             message,
         };
 
-        let commit_entry_in_bytes = Commit::commit_entry_to_bytes(commit_entry);
-
-        let hex_sha1 = Object::hash_object(ObjectType::Commit, commit_entry_in_bytes)?;
+        let hex_sha1 = Object::hash_object(ObjectType::Commit, commit.serialize())?;
 
         println!("{}", hex_sha1);
 
         Ok(())
     }
 
-    pub fn commit_entry_to_bytes(entry: CommitEntry) -> Vec<u8> {
-        let mut vec: Vec<u8> = Vec::new();
+    /// Records a new commit on the current branch, or replaces its tip with
+    /// `--amend`, and prints the `[branch abbrev] subject` summary.
+    pub fn commit(args: &CommitArgs) -> Result<()> {
+        let head = refs::read_ref("HEAD")?;
+        let previous = match (&head, args.amend) {
+            (Some(head), true) => Some(Commit::parse(Object::open(head)?.body())?),
+            (None, true) => return Err("You have nothing to amend.".into()),
+            (_, false) => None,
+        };
+
+        let mut message = args.message.join("\n\n");
+        if let Some(path) = &args.file {
+            if !args.message.is_empty() {
+                return Err("Option -m cannot be combined with -F".into());
+            }
+            message = if path.as_os_str() == "-" {
+                let mut message = String::new();
+                std::io::stdin().read_to_string(&mut message)?;
+                message
+            } else {
+                fs::read_to_string(path)
+                    .map_err(|e| format!("could not read log file '{}': {}", path.display(), e))?
+            };
+        }
+        let message = match (&previous, args.message.is_empty() && args.file.is_none()) {
+            (Some(previous), true) => previous.message.clone(),
+            (None, true) => return Err("no commit message given, use -m or -F".into()),
+            (_, false) => utils::cleanup_message(&message).into_bytes(),
+        };
+        if message.is_empty() {
+            return Err("Aborting commit due to empty commit message.".into());
+        }
 
-        vec.extend("tree ".as_bytes());
-        vec.extend(entry.tree);
-        vec.push('\n' as u8);
+        let mut index = Index::read()?;
+        if args.all {
+            index.update_tracked()?;
+        }
+        let tree = Tree::write_index_tree(&mut index)?;
 
-        vec.extend("parent ".as_bytes());
-        vec.extend(entry.parent);
-        vec.push('\n' as u8);
+        if !args.allow_empty && !args.amend {
+            match &head {
+                Some(head) if refs::peel_to_tree(head)? == tree => {
+                    return Err(
+                        "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+                            .into(),
+                    )
+                }
+                None if tree == EMPTY_TREE => {
+                    return Err(
+                        "nothing to commit (create/copy files and use \"git add\" to track)".into(),
+                    )
+                }
+                _ => {}
+            }
+        }
 
-        vec.extend(entry.author.as_bytes());
+        // amending keeps the original author and date unless --author says otherwise
+        let author = match (&args.author, &previous) {
+            (Some(ident), Some(previous)) => Signature {
+                time: previous.author.time,
+                offset: previous.author.offset.clone(),
+                ..Signature::from_ident(ident, "author")?
+            },
+            (Some(ident), None) => Signature::from_ident(ident, "author")?,
+            (None, Some(previous)) => previous.author.clone(),
+            (None, None) => Signature::author()?,
+        };
+        let committer = Signature::committer()?;
 
-        vec.extend(entry.committer.as_bytes());
+        let commit = Commit {
+            tree,
+            parents: match &previous {
+                Some(previous) => previous.parents.clone(),
+                None => head.iter().cloned().collect(),
+            },
+            author,
+            committer,
+            encoding: None,
+            extra_headers: Vec::new(),
+            message,
+        };
+        let hex_sha1 = Object::hash_object(ObjectType::Commit, commit.serialize())?;
+        // only now that the commit is going ahead does what -a staged stick
+        index.write()?;
 
-        vec.push('\n' as u8);
+        let action = if args.amend {
+            "commit (amend)"
+        } else if head.is_none() {
+            "commit (initial)"
+        } else {
+            "commit"
+        };
+        refs::update_ref(
+            "HEAD",
+            &hex_sha1,
+            &format!("{}: {}", action, commit.subject()),
+            &commit.committer,
+        )?;
 
-        vec.extend(entry.message.as_bytes());
-        vec.push('\n' as u8);
+        let branch = match refs::symbolic_ref("HEAD")? {
+            Some(branch) => branch
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&branch)
+                .to_string(),
+            None => String::from("detached HEAD"),
+        };
+        let root = if commit.parents.is_empty() {
+            " (root-commit)"
+        } else {
+            ""
+        };
+        println!(
+            "[{}{} {}] {}",
+            branch,
+            root,
+            Object::shortest_abbrev(&hex_sha1)?,
+            commit.subject()
+        );
 
-        vec
+        Ok(())
     }
 }
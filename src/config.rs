@@ -0,0 +1,270 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The merged view of the system, global and repository config files, read
+/// in that order so later files override earlier ones.
+pub struct Config {
+    // keys are `section.name` or `section.subsection.name`, with the section and
+    // name lowercased since git treats them case-insensitively
+    entries: Vec<(String, Option<String>)>,
+}
+
+thread_local! {
+    static CONFIG: RefCell<Option<Rc<Config>>> = const { RefCell::new(None) };
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let mut config = Config {
+            entries: Vec::new(),
+        };
+
+        let mut paths = Vec::new();
+        if env::var_os("GIT_CONFIG_NOSYSTEM").is_none() {
+            paths.push(PathBuf::from("/etc/gitconfig"));
+        }
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(xdg) if !xdg.is_empty() => paths.push(PathBuf::from(xdg).join("git/config")),
+            _ => {
+                if let Some(home) = env::var_os("HOME") {
+                    paths.push(PathBuf::from(home).join(".config/git/config"));
+                }
+            }
+        }
+        if let Some(home) = env::var_os("HOME") {
+            paths.push(PathBuf::from(home).join(".gitconfig"));
+        }
+        paths.push(PathBuf::from(".git/config"));
+
+        for path in paths {
+            if let Ok(text) = fs::read_to_string(&path) {
+                config
+                    .parse(&text)
+                    .map_err(|e| format!("bad config file {}: {}", path.display(), e))?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The config of the current process, read on first use.
+    pub fn cached() -> Result<Rc<Config>> {
+        CONFIG.with(|cached| {
+            let mut cached = cached.borrow_mut();
+            if cached.is_none() {
+                *cached = Some(Rc::new(Config::load()?));
+            }
+            Ok(cached.as_ref().unwrap().clone())
+        })
+    }
+
+    /// Forgets the cached config, for the next [`Config::cached`] to read the
+    /// files again once they were written or another repository was entered.
+    pub fn reset_cache() {
+        CONFIG.with(|cached| *cached.borrow_mut() = None);
+    }
+
+    fn parse(&mut self, text: &str) -> Result<()> {
+        let mut section = String::new();
+        let mut lines = text.lines().enumerate();
+
+        while let Some((number, line)) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let line = if line.starts_with('[') {
+                let end = line
+                    .find(']')
+                    .ok_or_else(|| format!("line {}: unterminated section", number + 1))?;
+                section = Config::parse_section(&line[1..end])?;
+                line[end + 1..].trim()
+            } else {
+                line
+            };
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if section.is_empty() {
+                return Err(format!("line {}: key outside of a section", number + 1).into());
+            }
+
+            let (name, raw_value) = match line.find('=') {
+                Some(index) => (line[..index].trim(), Some(line[index + 1..].trim_start())),
+                None => (line, None),
+            };
+            if name.is_empty() || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '-') {
+                return Err(format!("line {}: bad key {:?}", number + 1, name).into());
+            }
+
+            let value = match raw_value {
+                None => None,
+                Some(raw_value) => {
+                    // a trailing backslash continues the value on the next line
+                    let mut raw_value = raw_value.to_string();
+                    while raw_value.ends_with('\\') && !raw_value.ends_with("\\\\") {
+                        raw_value.pop();
+                        match lines.next() {
+                            Some((_, next)) => raw_value.push_str(next),
+                            None => break,
+                        }
+                    }
+                    Some(Config::parse_value(&raw_value)?)
+                }
+            };
+
+            self.entries
+                .push((format!("{}.{}", section, name.to_lowercase()), value));
+        }
+
+        Ok(())
+    }
+
+    fn parse_section(header: &str) -> Result<String> {
+        let header = header.trim();
+        match header.find('"') {
+            Some(index) => {
+                let name = header[..index].trim().to_lowercase();
+                let subsection = header[index + 1..]
+                    .strip_suffix('"')
+                    .ok_or("unterminated subsection")?
+                    .replace("\\\"", "\"")
+                    .replace("\\\\", "\\");
+                Ok(format!("{}.{}", name, subsection))
+            }
+            // the deprecated [section.subsection] form lowercases the subsection
+            None => Ok(header.to_lowercase()),
+        }
+    }
+
+    fn parse_value(raw_value: &str) -> Result<String> {
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut pending_space = String::new();
+        let mut chars = raw_value.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '#' | ';' if !quoted => break,
+                '\\' => {
+                    value.push_str(&pending_space);
+                    pending_space.clear();
+                    match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('b') => {
+                            value.pop();
+                        }
+                        Some('"') => value.push('"'),
+                        Some('\\') => value.push('\\'),
+                        other => return Err(format!("bad escape {:?}", other).into()),
+                    }
+                    continue;
+                }
+                c if c.is_whitespace() && !quoted => {
+                    pending_space.push(c);
+                    continue;
+                }
+                c => {
+                    value.push_str(&pending_space);
+                    pending_space.clear();
+                    value.push(c);
+                }
+            }
+        }
+        if quoted {
+            return Err("unterminated quote".into());
+        }
+
+        Ok(value)
+    }
+
+    fn normalize(key: &str) -> String {
+        // only the section and the variable name are case-insensitive
+        let first = key.find('.').unwrap_or(0);
+        let last = key.rfind('.').unwrap_or(0);
+        format!(
+            "{}{}{}",
+            key[..first].to_lowercase(),
+            &key[first..last],
+            key[last..].to_lowercase()
+        )
+    }
+
+    /// The last value set for `key`; a key without `=` reads as an empty string.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let key = Config::normalize(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.clone().unwrap_or_default())
+    }
+
+    /// Every value set for a multi-valued `key`, in the order they were read.
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        let key = Config::normalize(key);
+        self.entries
+            .iter()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value.clone().unwrap_or_default())
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        let normalized = Config::normalize(key);
+        let value = match self
+            .entries
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == normalized)
+        {
+            Some((_, value)) => value,
+            None => return Ok(None),
+        };
+
+        match value.as_deref().map(|x| x.to_lowercase()).as_deref() {
+            None | Some("true") | Some("yes") | Some("on") | Some("1") => Ok(Some(true)),
+            Some("false") | Some("no") | Some("off") | Some("0") | Some("") => Ok(Some(false)),
+            Some(other) => {
+                Err(format!("bad boolean config value '{}' for '{}'", other, key).into())
+            }
+        }
+    }
+
+    /// An integer value, which may carry a `k`, `m` or `g` unit suffix.
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        let value = match self.get(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let bad = |reason| {
+            format!(
+                "bad numeric config value '{}' for '{}': {}",
+                value, key, reason
+            )
+        };
+
+        let trimmed = value.trim();
+        let (number, factor) = match trimmed.chars().last().map(|x| x.to_ascii_lowercase()) {
+            Some('k') => (&trimmed[..trimmed.len() - 1], 1 << 10),
+            Some('m') => (&trimmed[..trimmed.len() - 1], 1 << 20),
+            Some('g') => (&trimmed[..trimmed.len() - 1], 1 << 30),
+            _ => (trimmed, 1),
+        };
+        let number: i64 = number.parse().map_err(|_| bad("invalid unit"))?;
+        Ok(Some(
+            number
+                .checked_mul(factor)
+                .ok_or_else(|| bad("out of range"))?,
+        ))
+    }
+}
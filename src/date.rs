@@ -0,0 +1,579 @@
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Seconds since the epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Formats an offset from UTC in seconds the way identities store it, e.g. `+0530`.
+pub fn format_offset(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// The local zone's offset from UTC in seconds at `time`. The zone comes from
+/// `TZ` or `/etc/localtime`; like libc, anything unreadable counts as UTC.
+pub fn local_offset(time: i64) -> i64 {
+    let tz = match env::var("TZ") {
+        Ok(tz) => tz,
+        Err(_) => {
+            return fs::read("/etc/localtime")
+                .ok()
+                .and_then(|data| tzif_offset(&data, time))
+                .unwrap_or(0)
+        }
+    };
+
+    let name = tz.strip_prefix(':').unwrap_or(&tz);
+    let path = if name.starts_with('/') {
+        PathBuf::from(name)
+    } else {
+        let dir = env::var_os("TZDIR").unwrap_or_else(|| "/usr/share/zoneinfo".into());
+        PathBuf::from(dir).join(name)
+    };
+
+    // TZ is either a zone name or a rule such as `EST5EDT,M3.2.0,M11.1.0`
+    match fs::read(&path)
+        .ok()
+        .and_then(|data| tzif_offset(&data, time))
+    {
+        Some(offset) => offset,
+        None => PosixRule::parse(name).map_or(0, |rule| rule.offset(time)),
+    }
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Option<usize> {
+    let word = data.get(pos..pos + 4)?;
+    Some(u32::from_be_bytes(word.try_into().unwrap()) as usize)
+}
+
+/// Looks `time` up in a compiled zoneinfo (TZif) file.
+fn tzif_offset(data: &[u8], time: i64) -> Option<i64> {
+    // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+    let counts = |pos: usize| -> Option<[usize; 6]> {
+        if data.get(pos..pos + 4)? != b"TZif" {
+            return None;
+        }
+        let mut counts = [0; 6];
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count = be_u32(data, pos + 20 + i * 4)?;
+        }
+        Some(counts)
+    };
+    let block_len = |counts: [usize; 6], time_size: usize| {
+        let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = counts;
+        timecnt * (time_size + 1)
+            + typecnt * 6
+            + charcnt
+            + leapcnt * (time_size + 4)
+            + isstdcnt
+            + isutcnt
+    };
+
+    let mut header = 0;
+    let mut header_counts = counts(0)?;
+    let mut time_size = 4;
+    // version 2 and later repeat the data with 64 bit times, followed by a rule
+    // for times after the last transition
+    let version = *data.get(4)?;
+    if version >= b'2' {
+        header = 44 + block_len(header_counts, 4);
+        header_counts = counts(header)?;
+        time_size = 8;
+    }
+
+    let [_, _, _, timecnt, typecnt, _] = header_counts;
+    let times = header + 44;
+    let indices = times + timecnt * time_size;
+    let types = indices + timecnt;
+    let utoff = |index: usize| -> Option<i64> {
+        let pos = types + index * 6;
+        Some(i32::from_be_bytes(data.get(pos..pos + 4)?.try_into().unwrap()) as i64)
+    };
+    let transition = |i: usize| -> Option<i64> {
+        let pos = times + i * time_size;
+        let bytes = data.get(pos..pos + time_size)?;
+        Some(match time_size {
+            8 => i64::from_be_bytes(bytes.try_into().unwrap()),
+            _ => i32::from_be_bytes(bytes.try_into().unwrap()) as i64,
+        })
+    };
+
+    let mut last = None;
+    for i in 0..timecnt {
+        if transition(i)? > time {
+            break;
+        }
+        last = Some(i);
+    }
+
+    match last {
+        None => {
+            // before the first transition, use the first standard time type
+            let first_std = (0..typecnt).find(|&index| data.get(types + index * 6 + 4) == Some(&0));
+            utoff(first_std.unwrap_or(0))
+        }
+        Some(i) if i + 1 == timecnt && version >= b'2' => {
+            let footer = data.get(header + 44 + block_len(header_counts, 8)..)?;
+            let footer = std::str::from_utf8(footer).ok()?.trim_matches('\n');
+            match PosixRule::parse(footer) {
+                Some(rule) => Some(rule.offset(time)),
+                None => utoff(*data.get(indices + i)? as usize),
+            }
+        }
+        Some(i) => utoff(*data.get(indices + i)? as usize),
+    }
+}
+
+enum RuleDate {
+    /// `Jn`, day 1 to 365 not counting February 29th
+    Julian(i64),
+    /// `n`, day 0 to 365 counting February 29th
+    Day(i64),
+    /// `Mm.w.d`, weekday `d` of week `w` (5 meaning the last one) of month `m`
+    Month(i64, i64, i64),
+}
+
+impl RuleDate {
+    /// Days since the epoch of this date in `year`.
+    fn days(&self, year: i64) -> i64 {
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        match *self {
+            RuleDate::Julian(day) => {
+                days_from_civil(year, 1, 1) + day - 1 + if leap && day >= 60 { 1 } else { 0 }
+            }
+            RuleDate::Day(day) => days_from_civil(year, 1, 1) + day,
+            RuleDate::Month(month, week, weekday) => {
+                let first = days_from_civil(year, month, 1);
+                let length = days_from_civil(year + month / 12, month % 12 + 1, 1) - first;
+                // the epoch was a Thursday
+                let mut day = (weekday - (first + 4)).rem_euclid(7) + (week - 1) * 7;
+                while day >= length {
+                    day -= 7;
+                }
+                first + day
+            }
+        }
+    }
+}
+
+/// A POSIX TZ rule like `CET-1CEST,M3.5.0,M10.5.0/3`.
+struct PosixRule {
+    std_offset: i64,
+    // offset, start and end with the time of day of each switch
+    dst: Option<(i64, RuleDate, i64, RuleDate, i64)>,
+}
+
+impl PosixRule {
+    fn parse(rule: &str) -> Option<Self> {
+        let mut rest = rule;
+        PosixRule::name(&mut rest)?;
+        // POSIX offsets count hours west of Greenwich
+        let std_offset = -PosixRule::time(&mut rest)?;
+        if rest.is_empty() {
+            return Some(PosixRule {
+                std_offset,
+                dst: None,
+            });
+        }
+
+        PosixRule::name(&mut rest)?;
+        let dst_offset = match rest.starts_with(',') || rest.is_empty() {
+            true => std_offset + 3600,
+            false => -PosixRule::time(&mut rest)?,
+        };
+        // without explicit dates the US rules apply, as they do in glibc
+        if rest.is_empty() {
+            rest = ",M3.2.0,M11.1.0";
+        }
+
+        let mut switches = Vec::new();
+        for _ in 0..2 {
+            rest = rest.strip_prefix(',')?;
+            let date = if let Some(day) = rest.strip_prefix('J') {
+                rest = day;
+                RuleDate::Julian(PosixRule::number(&mut rest)?)
+            } else if let Some(month) = rest.strip_prefix('M') {
+                rest = month;
+                let month = PosixRule::number(&mut rest)?;
+                rest = rest.strip_prefix('.')?;
+                let week = PosixRule::number(&mut rest)?;
+                rest = rest.strip_prefix('.')?;
+                let weekday = PosixRule::number(&mut rest)?;
+                if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+                    return None;
+                }
+                RuleDate::Month(month, week, weekday)
+            } else {
+                RuleDate::Day(PosixRule::number(&mut rest)?)
+            };
+            let time = match rest.strip_prefix('/') {
+                Some(time) => {
+                    rest = time;
+                    PosixRule::time(&mut rest)?
+                }
+                None => 7200,
+            };
+            switches.push((date, time));
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+
+        let (end, end_time) = switches.pop().unwrap();
+        let (start, start_time) = switches.pop().unwrap();
+        Some(PosixRule {
+            std_offset,
+            dst: Some((dst_offset, start, start_time, end, end_time)),
+        })
+    }
+
+    fn name(rest: &mut &str) -> Option<()> {
+        let length = match rest.strip_prefix('<') {
+            Some(quoted) => quoted.find('>')? + 2,
+            None => rest
+                .find(|x: char| !x.is_ascii_alphabetic())
+                .unwrap_or(rest.len()),
+        };
+        if length < 3 {
+            return None;
+        }
+        *rest = &rest[length..];
+        Some(())
+    }
+
+    fn number(rest: &mut &str) -> Option<i64> {
+        let length = rest
+            .find(|x: char| !x.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let number = rest[..length].parse().ok()?;
+        *rest = &rest[length..];
+        Some(number)
+    }
+
+    /// `[+-]hh[:mm[:ss]]` in seconds.
+    fn time(rest: &mut &str) -> Option<i64> {
+        let sign = match rest.chars().next()? {
+            '-' => -1,
+            _ => 1,
+        };
+        *rest = rest.trim_start_matches(['+', '-']);
+
+        let mut seconds = PosixRule::number(rest)? * 3600;
+        for unit in [60, 1] {
+            match rest.strip_prefix(':') {
+                Some(next) => {
+                    *rest = next;
+                    seconds += PosixRule::number(rest)? * unit;
+                }
+                None => break,
+            }
+        }
+        Some(sign * seconds)
+    }
+
+    fn offset(&self, time: i64) -> i64 {
+        let (dst_offset, start, start_time, end, end_time) = match &self.dst {
+            Some(dst) => dst,
+            None => return self.std_offset,
+        };
+
+        let (year, _, _) = civil_from_days((time + self.std_offset).div_euclid(86400));
+        // the switch to summer time happens in standard time and back in summer time
+        let start = start.days(year) * 86400 + start_time - self.std_offset;
+        let end = end.days(year) * 86400 + end_time - dst_offset;
+        let dst = if start < end {
+            start <= time && time < end
+        } else {
+            // southern hemisphere zones are in summer time over the new year
+            !(end <= time && time < start)
+        };
+
+        if dst {
+            *dst_offset
+        } else {
+            self.std_offset
+        }
+    }
+}
+
+/// Days since the epoch of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Parses `+hhmm`, `+hh:mm`, `+hh` or `Z` into seconds east of UTC.
+fn parse_offset(zone: &str) -> Option<i64> {
+    if matches!(zone, "Z" | "z" | "UT" | "UTC" | "GMT") {
+        return Some(0);
+    }
+    let sign = match zone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = zone[1..].replacen(':', "", 1);
+    if !digits.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i64>().ok()?,
+            digits[2..].parse::<i64>().ok()?,
+        ),
+        _ => return None,
+    };
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parses `hh:mm[:ss]` into seconds since midnight.
+fn parse_clock(clock: &str) -> Option<i64> {
+    let mut fields = clock.split(':');
+    let hours: i64 = fields.next()?.parse().ok()?;
+    let minutes: i64 = fields.next()?.parse().ok()?;
+    let seconds: i64 = match fields.next() {
+        Some(seconds) => seconds.parse().ok()?,
+        None => 0,
+    };
+    if fields.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Turns a calendar date and time into a timestamp and offset, reading the time
+/// as local when no zone was given.
+fn timestamp(
+    year: i64,
+    month: i64,
+    day: i64,
+    clock: i64,
+    offset: Option<i64>,
+) -> Option<(i64, i64)> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let naive = days_from_civil(year, month, day) * 86400 + clock;
+    let offset = offset.unwrap_or_else(|| local_offset(naive - local_offset(naive)));
+    Some((naive - offset, offset))
+}
+
+/// `1112911993 +0200` or `@1112911993`, the way git stores dates.
+fn parse_internal(date: &str) -> Option<(i64, i64)> {
+    let mut fields = date.split_whitespace();
+    let time = fields.next()?;
+    let time = time.strip_prefix('@').unwrap_or(time);
+    if time.is_empty() || !time.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let time: i64 = time.parse().ok()?;
+    let offset = match fields.next() {
+        Some(zone) => parse_offset(zone)?,
+        None => local_offset(time),
+    };
+    match fields.next() {
+        Some(_) => None,
+        None => Some((time, offset)),
+    }
+}
+
+/// `Thu, 07 Apr 2005 22:13:13 +0200`, with the weekday and zone optional.
+fn parse_rfc2822(date: &str) -> Option<(i64, i64)> {
+    let date = date.split_once(',').map_or(date, |(_, date)| date);
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    if fields.len() != 4 && fields.len() != 5 {
+        return None;
+    }
+
+    let day = fields[0].parse().ok()?;
+    let month = MONTHS
+        .iter()
+        .position(|month| fields[1].to_lowercase().starts_with(month))? as i64
+        + 1;
+    let year = fields[2].parse().ok()?;
+    let clock = parse_clock(fields[3])?;
+    let offset = match fields.get(4) {
+        Some(zone) => Some(parse_offset(zone)?),
+        None => None,
+    };
+    timestamp(year, month, day, clock, offset)
+}
+
+/// `2005-04-07T22:13:13+02:00`, also with a space instead of the `T`,
+/// fractional seconds, a `Z` zone or no time of day at all.
+fn parse_iso8601(date: &str) -> Option<(i64, i64)> {
+    let (day, rest) = match date.find(['T', ' ']) {
+        Some(index) => (&date[..index], date[index + 1..].trim()),
+        None => (date, ""),
+    };
+
+    let mut fields = day.split('-');
+    let year = fields.next()?.parse().ok()?;
+    let month = fields.next()?.parse().ok()?;
+    let day = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+
+    let zone_start = rest.find(['Z', 'z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (clock, zone) = rest.split_at(zone_start);
+    let clock = match clock.split('.').next()? {
+        "" if zone.is_empty() => 0,
+        clock => parse_clock(clock)?,
+    };
+    let offset = match zone.trim() {
+        "" => None,
+        zone => Some(parse_offset(zone)?),
+    };
+    timestamp(year, month, day, clock, offset)
+}
+
+/// Parses a date in git's internal, RFC 2822 or ISO 8601 format into a
+/// timestamp and an offset from UTC in seconds.
+pub fn parse_date(date: &str) -> Result<(i64, i64)> {
+    let trimmed = date.trim();
+    parse_internal(trimmed)
+        .or_else(|| parse_rfc2822(trimmed))
+        .or_else(|| parse_iso8601(trimmed))
+        .ok_or_else(|| format!("invalid date format: {}", date).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_offsets() {
+        assert_eq!(format_offset(19800), "+0530");
+        assert_eq!(format_offset(-18000), "-0500");
+        assert_eq!(format_offset(0), "+0000");
+    }
+
+    #[test]
+    fn parses_internal_dates() {
+        assert_eq!(parse_date("1112911993 +0200").unwrap(), (1112911993, 7200));
+        assert_eq!(parse_date("@0 -0130").unwrap(), (0, -5400));
+        assert!(parse_date("1112911993 +0200 extra").is_err());
+    }
+
+    #[test]
+    fn parses_rfc2822_dates() {
+        let expected = (1112904793, 7200);
+        assert_eq!(
+            parse_date("Thu, 07 Apr 2005 22:13:13 +0200").unwrap(),
+            expected
+        );
+        assert_eq!(parse_date("7 Apr 2005 22:13:13 +0200").unwrap(), expected);
+        assert_eq!(
+            parse_date("Thu, 07 Apr 2005 20:13:13 GMT").unwrap(),
+            (1112904793, 0)
+        );
+    }
+
+    #[test]
+    fn parses_iso8601_dates() {
+        let expected = (1112904793, 7200);
+        assert_eq!(parse_date("2005-04-07T22:13:13+02:00").unwrap(), expected);
+        assert_eq!(parse_date("2005-04-07 22:13:13 +0200").unwrap(), expected);
+        assert_eq!(
+            parse_date("2005-04-07T20:13:13.250Z").unwrap(),
+            (1112904793, 0)
+        );
+        assert!(parse_date("2005-13-07T20:13:13Z").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn converts_civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 2, 29), 11016);
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in [-800_000, -1, 0, 59, 60, 11_016, 19_782, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn applies_posix_rules() {
+        let cet = PosixRule::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert_eq!(cet.offset(1704067200), 3600);
+        assert_eq!(cet.offset(1719792000), 7200);
+        // summer time starts at 01:00 UTC on the last Sunday of March 2024
+        assert_eq!(cet.offset(1711846799), 3600);
+        assert_eq!(cet.offset(1711846800), 7200);
+
+        let sydney = PosixRule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset(1704067200), 39600);
+        assert_eq!(sydney.offset(1719792000), 36000);
+
+        // without dates, the US rules apply
+        let eastern = PosixRule::parse("EST5EDT").unwrap();
+        assert_eq!(eastern.offset(1704067200), -18000);
+        assert_eq!(eastern.offset(1719792000), -14400);
+
+        let fixed = PosixRule::parse("<+0530>-5:30").unwrap();
+        assert_eq!(fixed.offset(0), 19800);
+        assert!(PosixRule::parse("X1").is_none());
+    }
+
+    #[test]
+    fn reads_tzif_transitions() {
+        // a version 1 file with one switch from standard to summer time at 1000
+        let mut data = b"TZif".to_vec();
+        data.extend([0; 16]);
+        for count in [0u32, 0, 0, 1, 2, 8] {
+            data.extend(count.to_be_bytes());
+        }
+        data.extend(1000i32.to_be_bytes());
+        data.push(1);
+        data.extend(3600i32.to_be_bytes());
+        data.extend([0, 0]);
+        data.extend(7200i32.to_be_bytes());
+        data.extend([1, 4]);
+        data.extend(b"STD\0DST\0");
+
+        assert_eq!(tzif_offset(&data, 999), Some(3600));
+        assert_eq!(tzif_offset(&data, 1000), Some(7200));
+        assert_eq!(tzif_offset(b"not a zone file", 0), None);
+    }
+}
@@ -0,0 +1,211 @@
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+const TREE_MODES: [&str; 5] = ["100644", "100755", "120000", "40000", "160000"];
+
+/// Checks that `content` is a well formed object of the given type, the way
+/// `git fsck` would before letting it into the repository.
+pub fn check_object(object_type: &str, content: &[u8]) -> Result<()> {
+    match object_type {
+        "blob" => Ok(()),
+        "tree" => check_tree(content),
+        "commit" => check_commit(content),
+        "tag" => check_tag(content),
+        _ => Err(format!("invalid object type {}", object_type).into()),
+    }
+}
+
+/// Lists the objects `content` points at, paired with the type each one must have.
+/// Gitlinks are left out since they name commits in another repository.
+pub fn object_links(object_type: &str, content: &[u8]) -> Vec<(String, String)> {
+    let mut links = Vec::new();
+    match object_type {
+        "tree" => {
+            let mut pos = 0;
+            while let Some(space) = content[pos..].iter().position(|x| *x == b' ') {
+                let mode = &content[pos..pos + space];
+                let nul = match content[pos..].iter().position(|x| *x == 0) {
+                    Some(nul) => pos + nul,
+                    None => break,
+                };
+                let sha1 = match content.get(nul + 1..nul + 21) {
+                    Some(sha1) => hex::encode(sha1),
+                    None => break,
+                };
+                match mode {
+                    b"40000" => links.push((sha1, String::from("tree"))),
+                    b"160000" => {}
+                    _ => links.push((sha1, String::from("blob"))),
+                }
+                pos = nul + 21;
+            }
+        }
+        "commit" | "tag" => {
+            let mut target_type = String::from("commit");
+            for line in &header_lines(content) {
+                if let Some(sha1) = line.strip_prefix("tree ") {
+                    links.push((sha1.to_string(), String::from("tree")));
+                } else if let Some(sha1) = line.strip_prefix("parent ") {
+                    links.push((sha1.to_string(), String::from("commit")));
+                } else if let Some(sha1) = line.strip_prefix("object ") {
+                    links.push((sha1.to_string(), String::new()));
+                } else if let Some(name) = line.strip_prefix("type ") {
+                    target_type = name.to_string();
+                }
+            }
+            for link in links.iter_mut().filter(|link| link.1.is_empty()) {
+                link.1 = target_type.clone();
+            }
+        }
+        _ => {}
+    }
+    links
+}
+
+/// The header lines, without continuations. Idents need not be UTF-8, and
+/// only ever have their structure checked.
+fn header_lines(content: &[u8]) -> Vec<String> {
+    let end = content
+        .windows(2)
+        .position(|x| x == b"\n\n")
+        .unwrap_or(content.len());
+    String::from_utf8_lossy(&content[..end])
+        .split('\n')
+        .filter(|line| !line.starts_with(' '))
+        .map(String::from)
+        .collect()
+}
+
+fn is_hex_sha1(s: &str) -> bool {
+    s.len() == 40 && s.bytes().all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn check_tree(content: &[u8]) -> Result<()> {
+    let mut pos = 0;
+    let mut previous: Option<Vec<u8>> = None;
+
+    while pos < content.len() {
+        let space = content[pos..]
+            .iter()
+            .position(|x| *x == b' ')
+            .ok_or("tree entry has no mode")?;
+        let mode = std::str::from_utf8(&content[pos..pos + space])?;
+        if !TREE_MODES.contains(&mode) {
+            return Err(format!("tree has bad mode {}", mode).into());
+        }
+        pos += space + 1;
+
+        let nul = content[pos..]
+            .iter()
+            .position(|x| *x == 0)
+            .ok_or("tree entry has no name")?;
+        let name = &content[pos..pos + nul];
+        if name.is_empty() || name.contains(&b'/') {
+            return Err("tree entry has a bad name".into());
+        }
+        if name == b"." || name == b".." || name.eq_ignore_ascii_case(b".git") {
+            return Err(format!("tree contains {}", String::from_utf8_lossy(name)).into());
+        }
+        pos += nul + 1;
+
+        if content.len() < pos + 20 {
+            return Err("tree entry is truncated".into());
+        }
+        pos += 20;
+
+        // trees sort as if directory names had a trailing slash
+        let mut sort_key = name.to_vec();
+        if mode == "40000" {
+            sort_key.push(b'/');
+        }
+        if let Some(previous) = &previous {
+            if *previous >= sort_key {
+                return Err("tree entries are not sorted".into());
+            }
+        }
+        previous = Some(sort_key);
+    }
+
+    Ok(())
+}
+
+pub fn check_ident(ident: &str) -> Result<()> {
+    let bad = || -> Error { format!("bad identity {:?}", ident).into() };
+
+    let open = ident.find(" <").ok_or_else(bad)?;
+    let close = ident.find("> ").ok_or_else(bad)?;
+    if close < open || ident[open + 2..close].contains('<') {
+        return Err(bad());
+    }
+
+    let mut date = ident[close + 2..].split(' ');
+    let time = date.next().ok_or_else(bad)?;
+    let offset = date.next().ok_or_else(bad)?;
+    if time.is_empty() || !time.bytes().all(|x| x.is_ascii_digit()) {
+        return Err(bad());
+    }
+    if offset.len() != 5
+        || !(offset.starts_with('+') || offset.starts_with('-'))
+        || !offset[1..].bytes().all(|x| x.is_ascii_digit())
+        || date.next().is_some()
+    {
+        return Err(bad());
+    }
+
+    Ok(())
+}
+
+fn check_commit(content: &[u8]) -> Result<()> {
+    let lines = header_lines(content);
+    let mut lines = lines.iter().map(String::as_str);
+
+    match lines.next().and_then(|line| line.strip_prefix("tree ")) {
+        Some(sha1) if is_hex_sha1(sha1) => {}
+        _ => return Err("commit has no valid tree line".into()),
+    }
+
+    let mut line = lines.next();
+    while let Some(sha1) = line.and_then(|line| line.strip_prefix("parent ")) {
+        if !is_hex_sha1(sha1) {
+            return Err("commit has a bad parent line".into());
+        }
+        line = lines.next();
+    }
+
+    match line.and_then(|line| line.strip_prefix("author ")) {
+        Some(ident) => check_ident(ident)?,
+        None => return Err("commit has no author".into()),
+    }
+    match lines
+        .next()
+        .and_then(|line| line.strip_prefix("committer "))
+    {
+        Some(ident) => check_ident(ident)?,
+        None => return Err("commit has no committer".into()),
+    }
+
+    Ok(())
+}
+
+fn check_tag(content: &[u8]) -> Result<()> {
+    let lines = header_lines(content);
+    let mut lines = lines.iter().map(String::as_str);
+
+    match lines.next().and_then(|line| line.strip_prefix("object ")) {
+        Some(sha1) if is_hex_sha1(sha1) => {}
+        _ => return Err("tag has no valid object line".into()),
+    }
+    match lines.next().and_then(|line| line.strip_prefix("type ")) {
+        Some("blob") | Some("tree") | Some("commit") | Some("tag") => {}
+        _ => return Err("tag has no valid type line".into()),
+    }
+    match lines.next().and_then(|line| line.strip_prefix("tag ")) {
+        Some(name) if !name.is_empty() => {}
+        _ => return Err("tag has no valid tag line".into()),
+    }
+    if let Some(ident) = lines.next().and_then(|line| line.strip_prefix("tagger ")) {
+        check_ident(ident)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1055 @@
+use std::convert::TryInto;
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+
+use crate::args::UpdateIndex;
+use crate::object::Object;
+use crate::utils;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+const INDEX_PATH: &str = ".git/index";
+const LOCK_PATH: &str = ".git/index.lock";
+
+const ASSUME_VALID: u16 = 0x8000;
+const EXTENDED: u16 = 0x4000;
+const SKIP_WORKTREE: u16 = 0x4000;
+const INTENT_TO_ADD: u16 = 0x2000;
+const NAME_MASK: u16 = 0x0fff;
+
+/// The staging area in `.git/index`, in the DIRC format stock git reads and writes.
+pub struct Index {
+    pub version: u32,
+    /// Sorted by path, then by stage.
+    pub entries: Vec<IndexEntry>,
+    /// The trees last written from these entries (the TREE extension).
+    pub cache_tree: Option<CacheTree>,
+    /// Other extensions as read, by signature. Only the ones that stay valid
+    /// when entries change are written back.
+    pub extensions: Vec<([u8; 4], Vec<u8>)>,
+    // when the index file was last written, to spot racily clean entries
+    timestamp: Option<(u32, u32)>,
+}
+
+#[derive(Clone)]
+pub struct IndexEntry {
+    pub ctime: u32,
+    pub ctime_nsec: u32,
+    pub mtime: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    /// The octal git mode, e.g. `0o100644`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub sha1: String,
+    pub assume_valid: bool,
+    /// 0 when merged, 1 to 3 for the base, ours and theirs sides of a conflict.
+    pub stage: u8,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    pub path: String,
+}
+
+/// A node of the cache tree: the id of a tree written from the index and the
+/// number of entries it covers, so unchanged directories needn't be rehashed.
+#[derive(Default)]
+pub struct CacheTree {
+    /// The directory's name within its parent, empty for the root.
+    pub name: String,
+    pub entry_count: usize,
+    /// `None` once an entry below has changed.
+    pub sha1: Option<String>,
+    /// In git's order: shorter names first, then bytewise.
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    fn parse(data: &[u8], pos: &mut usize) -> Result<Self> {
+        let bad = || -> Error { "index cache tree is corrupt".into() };
+
+        let nul = data[*pos..].iter().position(|x| *x == 0).ok_or_else(bad)?;
+        let name = std::str::from_utf8(&data[*pos..*pos + nul])?.to_string();
+        *pos += nul + 1;
+        let newline = data[*pos..]
+            .iter()
+            .position(|x| *x == b'\n')
+            .ok_or_else(bad)?;
+        let counts = std::str::from_utf8(&data[*pos..*pos + newline])?;
+        *pos += newline + 1;
+
+        let (entry_count, subtree_count) = counts.split_once(' ').ok_or_else(bad)?;
+        let entry_count: i64 = entry_count.parse().map_err(|_| bad())?;
+        let subtree_count: usize = subtree_count.parse().map_err(|_| bad())?;
+        // a negative count marks a tree that has to be written again
+        let sha1 = if entry_count >= 0 {
+            let sha1 = data.get(*pos..*pos + 20).ok_or_else(bad)?;
+            *pos += 20;
+            Some(hex::encode(sha1))
+        } else {
+            None
+        };
+
+        // the smallest subtree, `\0-1 0\n`, takes 6 bytes
+        if subtree_count > (data.len() - *pos) / 6 {
+            return Err(bad());
+        }
+        let mut subtrees = Vec::with_capacity(subtree_count);
+        for _ in 0..subtree_count {
+            subtrees.push(CacheTree::parse(data, pos)?);
+        }
+
+        Ok(CacheTree {
+            name,
+            entry_count: entry_count.max(0) as usize,
+            sha1,
+            subtrees,
+        })
+    }
+
+    fn serialize(&self, bytes: &mut Vec<u8>) {
+        bytes.extend(self.name.as_bytes());
+        bytes.push(0);
+        match &self.sha1 {
+            Some(sha1) => {
+                bytes.extend(format!("{} {}\n", self.entry_count, self.subtrees.len()).as_bytes());
+                bytes.extend(hex::decode(sha1).unwrap_or_else(|_| vec![0; 20]));
+            }
+            None => bytes.extend(format!("-1 {}\n", self.subtrees.len()).as_bytes()),
+        }
+        for subtree in &self.subtrees {
+            subtree.serialize(bytes);
+        }
+    }
+
+    /// Marks every tree on the way to `path` as changed. A path naming a
+    /// subtree means a file took the directory's place, so that subtree goes.
+    fn invalidate(&mut self, path: &str) {
+        self.sha1 = None;
+        match path.split_once('/') {
+            Some((dir, rest)) => {
+                if let Some(subtree) = self.subtrees.iter_mut().find(|x| x.name == dir) {
+                    subtree.invalidate(rest);
+                }
+            }
+            None => self.subtrees.retain(|subtree| subtree.name != path),
+        }
+    }
+}
+
+fn be_u32(bytes: &[u8], pos: usize) -> Result<u32> {
+    let word = bytes.get(pos..pos + 4).ok_or("index file is truncated")?;
+    Ok(u32::from_be_bytes(word.try_into().unwrap()))
+}
+
+fn be_u16(bytes: &[u8], pos: usize) -> Result<u16> {
+    let word = bytes.get(pos..pos + 2).ok_or("index file is truncated")?;
+    Ok(u16::from_be_bytes(word.try_into().unwrap()))
+}
+
+/// The variable length integer version 4 uses for the length of the path
+/// prefix an entry drops from the one before it.
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut byte = *bytes.get(*pos).ok_or("index file is truncated")?;
+    *pos += 1;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = *bytes.get(*pos).ok_or("index file is truncated")?;
+        *pos += 1;
+        value = value
+            .checked_add(1)
+            .and_then(|x| x.checked_mul(1 << 7))
+            .ok_or("index file is corrupt")?
+            | (byte & 0x7f) as usize;
+    }
+    Ok(value)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    let mut encoded = vec![(value & 0x7f) as u8];
+    while value >> 7 != 0 {
+        value = (value >> 7) - 1;
+        encoded.push(0x80 | (value & 0x7f) as u8);
+    }
+    encoded.reverse();
+    bytes.extend(encoded);
+}
+
+/// Whether a path given on the command line climbs out of the working tree.
+fn escapes_repository(path: &str) -> bool {
+    let mut depth = 0;
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if depth == 0 => return true,
+            ".." => depth -= 1,
+            _ => depth += 1,
+        }
+    }
+    path.starts_with('/')
+}
+
+/// Whether `metadata` still has the stat data recorded in `entry`.
+fn same_stat(entry: &IndexEntry, metadata: &Metadata) -> bool {
+    let fresh = IndexEntry::from_metadata(&entry.path, &entry.sha1, metadata);
+    entry.mtime == fresh.mtime
+        && entry.mtime_nsec == fresh.mtime_nsec
+        && entry.ctime == fresh.ctime
+        && entry.ctime_nsec == fresh.ctime_nsec
+        && entry.ino == fresh.ino
+        && entry.uid == fresh.uid
+        && entry.gid == fresh.gid
+        && entry.size == fresh.size
+        && entry.mode == fresh.mode
+}
+
+/// Hashes a working tree file the way `add` stages it, writing it to the object
+/// store when `write` is set; a symlink is stored as a blob holding its target.
+pub fn hash_worktree_file(path: &str, metadata: &Metadata, write: bool) -> Result<String> {
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+        return Object::hash_bytes("blob", target.as_os_str().as_bytes(), write);
+    }
+    Object::hash_file(path, write)
+}
+
+impl IndexEntry {
+    /// An entry for `path` staged with the stat data of `metadata`.
+    pub fn from_metadata(path: &str, sha1: &str, metadata: &Metadata) -> Self {
+        let mode = if metadata.file_type().is_symlink() {
+            0o120000
+        } else if metadata.mode() & 0o100 != 0 {
+            0o100755
+        } else {
+            0o100644
+        };
+
+        IndexEntry {
+            ctime: metadata.ctime() as u32,
+            ctime_nsec: metadata.ctime_nsec() as u32,
+            mtime: metadata.mtime() as u32,
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+            sha1: sha1.to_string(),
+            assume_valid: false,
+            stage: 0,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.to_string(),
+        }
+    }
+
+    /// An entry for an object that isn't in the working tree (yet), with no
+    /// stat data. The mode is normalized the way git stores it.
+    pub fn new(path: &str, mode: u32, sha1: &str) -> Self {
+        let mode = match mode & 0o170000 {
+            0o120000 => 0o120000,
+            0o040000 | 0o160000 => 0o160000,
+            _ if mode & 0o100 != 0 => 0o100755,
+            _ => 0o100644,
+        };
+
+        IndexEntry {
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            sha1: sha1.to_string(),
+            assume_valid: false,
+            stage: 0,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.to_string(),
+        }
+    }
+
+    /// This entry with the stat data of its freshly written file, keeping the
+    /// staged mode and flags.
+    pub fn with_stat(&self, metadata: &Metadata) -> Self {
+        IndexEntry {
+            mode: self.mode,
+            stage: self.stage,
+            assume_valid: self.assume_valid,
+            skip_worktree: self.skip_worktree,
+            intent_to_add: self.intent_to_add,
+            ..IndexEntry::from_metadata(&self.path, &self.sha1, metadata)
+        }
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+impl Index {
+    const SIGNATURE: &'static [u8; 4] = b"DIRC";
+
+    pub fn new() -> Self {
+        Index {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            extensions: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    /// Reads `.git/index`; a repository without one has nothing staged.
+    pub fn read() -> Result<Self> {
+        let bytes = match fs::read(INDEX_PATH) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Index::new()),
+            Err(e) => return Err(format!("{}: {}", INDEX_PATH, e).into()),
+        };
+        let mut index = Index::parse(&bytes)?;
+
+        let metadata = fs::metadata(INDEX_PATH)?;
+        index.timestamp = Some((metadata.mtime() as u32, metadata.mtime_nsec() as u32));
+        Ok(index)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 32 || &bytes[..4] != Index::SIGNATURE {
+            return Err("index file has a bad signature".into());
+        }
+        let version = be_u32(bytes, 4)?;
+        if !(2..=4).contains(&version) {
+            return Err(format!("index file has unsupported version {}", version).into());
+        }
+
+        // `index.skipHash` writes a null checksum, which git accepts as well
+        let body = &bytes[..bytes.len() - 20];
+        let checksum = &bytes[bytes.len() - 20..];
+        if checksum.iter().any(|x| *x != 0) && utils::hex_sha1(body) != hex::encode(checksum) {
+            return Err("index file is corrupt: bad checksum".into());
+        }
+
+        let count = be_u32(bytes, 8)? as usize;
+        // every entry takes at least its 62 bytes of stat data, id and flags
+        if count > (body.len() - 12) / 62 {
+            return Err("index file is truncated".into());
+        }
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(count);
+        let mut pos = 12;
+        for _ in 0..count {
+            let start = pos;
+            let stat = |field: usize| be_u32(body, start + field * 4);
+            let sha1 = body
+                .get(start + 40..start + 60)
+                .ok_or("index file is truncated")?;
+            let flags = be_u16(body, start + 60)?;
+            pos += 62;
+
+            let extended_flags = if flags & EXTENDED != 0 {
+                if version < 3 {
+                    return Err("index entry has extended flags in a version 2 index".into());
+                }
+                pos += 2;
+                be_u16(body, start + 62)?
+            } else {
+                0
+            };
+
+            let path = if version == 4 {
+                let strip = read_varint(body, &mut pos)?;
+                let previous = entries.last().map_or("", |entry| entry.path.as_str());
+                let prefix = previous
+                    .len()
+                    .checked_sub(strip)
+                    .ok_or("index entry strips more than the previous path")?;
+                let nul = body
+                    .get(pos..)
+                    .and_then(|rest| rest.iter().position(|x| *x == 0))
+                    .ok_or("index entry path is not terminated")?;
+                // the shared prefix can end inside a character, so the path is
+                // put together from bytes
+                let mut path = previous.as_bytes()[..prefix].to_vec();
+                path.extend(&body[pos..pos + nul]);
+                pos += nul + 1;
+                String::from_utf8(path)?
+            } else {
+                let length = match (flags & NAME_MASK) as usize {
+                    // longer names are only found by their terminating NUL
+                    0xfff => body
+                        .get(pos..)
+                        .and_then(|rest| rest.iter().position(|x| *x == 0))
+                        .ok_or("index entry path is not terminated")?,
+                    length => length,
+                };
+                let path = body
+                    .get(pos..pos + length)
+                    .ok_or("index file is truncated")?;
+                let path = std::str::from_utf8(path)?.to_string();
+                // entries are NUL padded to a multiple of eight bytes
+                pos = start + ((pos - start + length + 8) & !7);
+                path
+            };
+
+            entries.push(IndexEntry {
+                ctime: stat(0)?,
+                ctime_nsec: stat(1)?,
+                mtime: stat(2)?,
+                mtime_nsec: stat(3)?,
+                dev: stat(4)?,
+                ino: stat(5)?,
+                mode: stat(6)?,
+                uid: stat(7)?,
+                gid: stat(8)?,
+                size: stat(9)?,
+                sha1: hex::encode(sha1),
+                assume_valid: flags & ASSUME_VALID != 0,
+                stage: ((flags >> 12) & 0x3) as u8,
+                skip_worktree: extended_flags & SKIP_WORKTREE != 0,
+                intent_to_add: extended_flags & INTENT_TO_ADD != 0,
+                path,
+            });
+        }
+
+        let mut cache_tree = None;
+        let mut extensions = Vec::new();
+        while pos < body.len() {
+            let signature: [u8; 4] = body
+                .get(pos..pos + 4)
+                .ok_or("index file is truncated")?
+                .try_into()
+                .unwrap();
+            let size = be_u32(body, pos + 4)? as usize;
+            let data = body
+                .get(pos + 8..pos + 8 + size)
+                .ok_or("index extension is truncated")?;
+            pos += 8 + size;
+
+            // extensions starting with a capital letter are optional, the rest
+            // change how the index must be read
+            if !signature[0].is_ascii_uppercase() {
+                return Err(format!(
+                    "index uses {} extension, which we do not understand",
+                    String::from_utf8_lossy(&signature)
+                )
+                .into());
+            }
+            if &signature == b"TREE" {
+                cache_tree = Some(CacheTree::parse(data, &mut 0)?);
+            } else {
+                extensions.push((signature, data.to_vec()));
+            }
+        }
+
+        Ok(Index {
+            version,
+            entries,
+            cache_tree,
+            extensions,
+            timestamp: None,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        // extended flags need at least version 3
+        let version = match self.version {
+            2 if self.entries.iter().any(|entry| entry.is_extended()) => 3,
+            version => version,
+        };
+
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend(Index::SIGNATURE);
+        bytes.extend(version.to_be_bytes());
+        bytes.extend((self.entries.len() as u32).to_be_bytes());
+
+        let mut previous = "";
+        for entry in &self.entries {
+            let start = bytes.len();
+            for field in [
+                entry.ctime,
+                entry.ctime_nsec,
+                entry.mtime,
+                entry.mtime_nsec,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                bytes.extend(field.to_be_bytes());
+            }
+            bytes.extend(hex::decode(&entry.sha1).unwrap_or_else(|_| vec![0; 20]));
+
+            let mut flags = (entry.path.len().min(NAME_MASK as usize) as u16)
+                | ((entry.stage as u16 & 0x3) << 12);
+            if entry.assume_valid {
+                flags |= ASSUME_VALID;
+            }
+            if entry.is_extended() {
+                flags |= EXTENDED;
+            }
+            bytes.extend(flags.to_be_bytes());
+            if entry.is_extended() {
+                let mut extended_flags = 0;
+                if entry.skip_worktree {
+                    extended_flags |= SKIP_WORKTREE;
+                }
+                if entry.intent_to_add {
+                    extended_flags |= INTENT_TO_ADD;
+                }
+                bytes.extend(extended_flags.to_be_bytes());
+            }
+
+            if version == 4 {
+                let common = previous
+                    .bytes()
+                    .zip(entry.path.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                write_varint(&mut bytes, previous.len() - common);
+                bytes.extend(&entry.path.as_bytes()[common..]);
+                bytes.push(0);
+            } else {
+                bytes.extend(entry.path.as_bytes());
+                let length = (bytes.len() - start + 8) & !7;
+                bytes.resize(start + length, 0);
+            }
+            previous = &entry.path;
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut data = Vec::new();
+            cache_tree.serialize(&mut data);
+            bytes.extend(b"TREE");
+            bytes.extend((data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        }
+        for (signature, data) in &self.extensions {
+            // the others describe the old entries and git rebuilds them anyway
+            if signature != b"REUC" {
+                continue;
+            }
+            bytes.extend(signature);
+            bytes.extend((data.len() as u32).to_be_bytes());
+            bytes.extend(data);
+        }
+
+        let checksum = utils::hex_sha1(&bytes);
+        bytes.extend(hex::decode(checksum).unwrap());
+        bytes
+    }
+
+    /// Replaces `.git/index` through `.git/index.lock`, failing like git does
+    /// when another process holds the lock.
+    pub fn write(&self) -> Result<()> {
+        let mut lock = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(LOCK_PATH)
+        {
+            Ok(lock) => lock,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(format!(
+                    "Unable to create '{}': File exists. Another git process seems to be running in this repository",
+                    LOCK_PATH
+                )
+                .into())
+            }
+            Err(e) => return Err(format!("{}: {}", LOCK_PATH, e).into()),
+        };
+
+        if let Err(e) = lock.write_all(&self.serialize()) {
+            let _ = fs::remove_file(LOCK_PATH);
+            return Err(e.into());
+        }
+        fs::rename(LOCK_PATH, INDEX_PATH)?;
+        Ok(())
+    }
+
+    fn position(&self, path: &str, stage: u8) -> std::result::Result<usize, usize> {
+        self.entries.binary_search_by(|entry| {
+            entry
+                .path
+                .as_bytes()
+                .cmp(path.as_bytes())
+                .then(entry.stage.cmp(&stage))
+        })
+    }
+
+    /// The merged entry for `path`, if it is tracked and not in conflict.
+    pub fn find(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path, 0)
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    /// Whether `path` has an entry at any stage.
+    pub fn is_tracked(&self, path: &str) -> bool {
+        let index = self.position(path, 0).unwrap_or_else(|index| index);
+        self.entries.get(index).map(|entry| entry.path.as_str()) == Some(path)
+    }
+
+    /// Stages `entry`, replacing what was there for its path. Adding a merged
+    /// entry resolves any conflict recorded for the path.
+    pub fn add(&mut self, entry: IndexEntry) {
+        let path = entry.path.clone();
+        if entry.stage == 0 {
+            self.entries
+                .retain(|other| other.path != entry.path || other.stage == 0);
+        }
+        match self.position(&entry.path, entry.stage) {
+            Ok(index) => self.entries[index] = entry,
+            Err(index) => self.entries.insert(index, entry),
+        }
+        self.invalidate(&path);
+    }
+
+    /// Unstages `path` at every stage.
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|entry| entry.path != path);
+        self.invalidate(path);
+    }
+
+    /// Forgets the cached trees above `path`; a stale cache tree would make
+    /// git write the old trees.
+    pub fn invalidate(&mut self, path: &str) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(path);
+        }
+    }
+
+    /// Whether the working tree file still looks exactly as when it was staged.
+    /// Entries written in the same instant as the index itself could have
+    /// changed unnoticed afterwards, so those never count as clean.
+    pub fn is_clean(&self, entry: &IndexEntry, metadata: &Metadata) -> bool {
+        !self.is_racy(entry) && same_stat(entry, metadata)
+    }
+
+    fn is_racy(&self, entry: &IndexEntry) -> bool {
+        match self.timestamp {
+            Some(timestamp) => (entry.mtime, entry.mtime_nsec) >= timestamp,
+            None => true,
+        }
+    }
+
+    /// Whether the working tree file changed since it was staged, going by the
+    /// stat data the way git does before overwriting files: one that was only
+    /// touched counts as changed, and the content is only compared for entries
+    /// that are racily clean.
+    pub fn stat_changed(&self, entry: &IndexEntry, metadata: &Metadata) -> Result<bool> {
+        if entry.intent_to_add || metadata.is_dir() || !same_stat(entry, metadata) {
+            return Ok(true);
+        }
+        if !self.is_racy(entry) {
+            return Ok(false);
+        }
+        Ok(hash_worktree_file(&entry.path, metadata, false)? != entry.sha1)
+    }
+
+    /// Whether the working tree file still has the staged content, looking at
+    /// the stat data first and at the content only when that changed.
+    pub fn worktree_matches(&self, entry: &IndexEntry, metadata: &Metadata) -> Result<bool> {
+        if self.is_clean(entry, metadata) {
+            return Ok(true);
+        }
+        let fresh = IndexEntry::from_metadata(&entry.path, &entry.sha1, metadata);
+        Ok(fresh.mode == entry.mode
+            && hash_worktree_file(&entry.path, metadata, false)? == entry.sha1)
+    }
+
+    /// Restages every tracked file that changed in the working tree and drops
+    /// the ones that were deleted, the way `commit -a` does.
+    pub fn update_tracked(&mut self) -> Result<()> {
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(self.entries.len());
+        let mut changed: Vec<String> = Vec::new();
+        for entry in &self.entries {
+            // the merged result replaces all the stages of a conflict
+            if entries.last().map(|last| &last.path) == Some(&entry.path) {
+                continue;
+            }
+            changed.push(entry.path.clone());
+            if entry.skip_worktree || entry.mode == 0o160000 {
+                entries.push(entry.clone());
+                continue;
+            }
+
+            let metadata = match fs::symlink_metadata(&entry.path) {
+                Ok(metadata) if !metadata.is_dir() => metadata,
+                _ => continue,
+            };
+            if entry.stage == 0 && !entry.intent_to_add && self.is_clean(entry, &metadata) {
+                changed.pop();
+                entries.push(entry.clone());
+                continue;
+            }
+            let sha1 = hash_worktree_file(&entry.path, &metadata, true)?;
+            entries.push(IndexEntry::from_metadata(&entry.path, &sha1, &metadata));
+        }
+
+        self.entries = entries;
+        for path in changed {
+            self.invalidate(&path);
+        }
+        Ok(())
+    }
+}
+
+impl Index {
+    /// Edits the index directly, for scripts: stages files or bare objects,
+    /// drops missing files, flips entry flags and refreshes stat data.
+    ///
+    /// Whatever order they are given in, `--cacheinfo` entries go in first,
+    /// then the `--index-info` lines, then the paths; `--chmod` applies to the
+    /// `--cacheinfo` entries and the paths.
+    pub fn update_index(args: &UpdateIndex) -> Result<()> {
+        let executable = match args.chmod.as_deref() {
+            Some("+x") => Some(true),
+            Some("-x") => Some(false),
+            Some(_) => return Err("option 'chmod' expects \"+x\" or \"-x\"".into()),
+            None => None,
+        };
+        let mut index = Index::read()?;
+
+        let mut needs_update = false;
+        if args.refresh {
+            // -q asks for a clean exit even when files need updating
+            needs_update = index.refresh(args.quiet)? && !args.quiet;
+        }
+
+        for cacheinfo in &args.cacheinfo {
+            let bad = || -> Error { "option 'cacheinfo' expects <mode>,<sha1>,<path>".into() };
+            let (mode, rest) = cacheinfo.split_once(',').ok_or_else(bad)?;
+            let (sha1, path) = rest.split_once(',').ok_or_else(bad)?;
+            let mode = u32::from_str_radix(mode, 8).map_err(|_| bad())?;
+            if sha1.len() != 40 || !sha1.bytes().all(|x| x.is_ascii_hexdigit()) {
+                return Err(bad());
+            }
+
+            let added = match utils::verify_path(path) {
+                true => index.check_addable(path, args.add),
+                false => Err(format!("Invalid path '{}'", path).into()),
+            };
+            if let Err(e) = added {
+                eprintln!("error: {}", e);
+                return Err(format!("git update-index: --cacheinfo cannot add {}", path).into());
+            }
+            index.add(IndexEntry::new(path, mode, &sha1.to_lowercase()));
+            if let Some(executable) = executable {
+                index.chmod(path, executable)?;
+            }
+        }
+
+        if args.index_info {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            for line in input.lines() {
+                index.add_index_info(line)?;
+            }
+        }
+
+        for path in &args.paths {
+            if escapes_repository(path) {
+                return Err(format!(
+                    "'{}' is outside repository at '{}'",
+                    path,
+                    std::env::current_dir()?.display()
+                )
+                .into());
+            }
+            let path = utils::normalize_pathspec(path);
+            let flags = [
+                (args.assume_unchanged, args.no_assume_unchanged),
+                (args.skip_worktree, args.no_skip_worktree),
+            ];
+            if flags.iter().any(|(set, clear)| *set || *clear) {
+                let entry = match index.position(&path, 0) {
+                    Ok(position) => &mut index.entries[position],
+                    Err(_) => return Err(format!("Unable to mark file {}", path).into()),
+                };
+                if args.assume_unchanged || args.no_assume_unchanged {
+                    entry.assume_valid = args.assume_unchanged;
+                }
+                if args.skip_worktree || args.no_skip_worktree {
+                    entry.skip_worktree = args.skip_worktree;
+                }
+                continue;
+            }
+
+            if let Err(e) = index.update_path(&path, args.add, args.remove) {
+                eprintln!("error: {}", e);
+                return Err(format!("Unable to process path {}", path).into());
+            }
+            if let Some(executable) = executable {
+                index.chmod(&path, executable)?;
+            }
+        }
+
+        index.write()?;
+        if needs_update {
+            std::process::exit(1);
+        }
+        Ok(())
+    }
+
+    /// Applies one line of `--index-info`: `<mode> <sha1> <stage>\t<path>`,
+    /// `<mode> <type> <sha1>\t<path>` or `<mode> <sha1>\t<path>`. Mode 0
+    /// removes the path.
+    fn add_index_info(&mut self, line: &str) -> Result<()> {
+        let malformed = || -> Error { format!("malformed index info {}", line).into() };
+        let (fields, path) = line.split_once('\t').ok_or_else(malformed)?;
+        let fields: Vec<&str> = fields.split(' ').collect();
+        let (mode, sha1, stage) = match fields[..] {
+            [mode, sha1] => (mode, sha1, "0"),
+            [mode, sha1, stage] if sha1.len() == 40 => (mode, sha1, stage),
+            [mode, _, sha1] => (mode, sha1, "0"),
+            _ => return Err(malformed()),
+        };
+        let mode = u32::from_str_radix(mode, 8).map_err(|_| malformed())?;
+        let stage: u8 = match stage.parse() {
+            Ok(stage) if stage <= 3 => stage,
+            _ => return Err(malformed()),
+        };
+        if sha1.len() != 40 || !sha1.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(malformed());
+        }
+
+        let path = utils::unquote_path(path)?;
+        if !utils::verify_path(&path) {
+            eprintln!("Ignoring path {}", path);
+            return Ok(());
+        }
+        if mode == 0 {
+            self.remove(&path);
+            return Ok(());
+        }
+        self.add(IndexEntry {
+            stage,
+            ..IndexEntry::new(&path, mode, &sha1.to_lowercase())
+        });
+        Ok(())
+    }
+
+    /// Restages the working tree file at `path`. A missing file is only
+    /// unstaged with `remove`, and a new one only staged with `add`.
+    fn update_path(&mut self, path: &str, add: bool, remove: bool) -> Result<()> {
+        if !utils::verify_path(path) {
+            return Err(format!("Invalid path '{}'", path).into());
+        }
+
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) if remove => {
+                self.remove(path);
+                return Ok(());
+            }
+            Err(_) => {
+                return Err(format!("{}: does not exist and --remove not passed", path).into())
+            }
+        };
+        if metadata.is_dir() {
+            if remove && self.find(path).is_some() {
+                self.remove(path);
+                return Ok(());
+            }
+            let under = format!("{}/", path);
+            if self
+                .entries
+                .iter()
+                .any(|entry| entry.path.starts_with(&under))
+            {
+                return Err(
+                    format!("{}: is a directory - add individual files instead", path).into(),
+                );
+            }
+            return Err(format!("{}: is a directory - add files inside instead", path).into());
+        }
+
+        self.check_addable(path, add)?;
+        let sha1 = hash_worktree_file(path, &metadata, true)?;
+        self.add(IndexEntry::from_metadata(path, &sha1, &metadata));
+        Ok(())
+    }
+
+    /// Whether an entry may be staged at `path`: new paths need `add`, and may
+    /// not turn a tracked file into a directory or the other way round.
+    fn check_addable(&self, path: &str, add: bool) -> Result<()> {
+        if self.is_tracked(path) {
+            return Ok(());
+        }
+        let under = format!("{}/", path);
+        let clash = path
+            .match_indices('/')
+            .any(|(index, _)| self.is_tracked(&path[..index]))
+            || self
+                .entries
+                .iter()
+                .any(|entry| entry.path.starts_with(&under));
+        if add && !clash {
+            return Ok(());
+        }
+        if clash {
+            eprintln!(
+                "error: '{}' appears as both a file and as a directory",
+                path
+            );
+        }
+        Err(format!("{}: cannot add to the index - missing --add option?", path).into())
+    }
+
+    fn chmod(&mut self, path: &str, executable: bool) -> Result<()> {
+        let flip = if executable { '+' } else { '-' };
+        let entry = match self.position(path, 0) {
+            Ok(position) if self.entries[position].mode & 0o170000 == 0o100000 => {
+                &mut self.entries[position]
+            }
+            _ => return Err(format!("git update-index: cannot chmod {}x '{}'", flip, path).into()),
+        };
+        entry.mode = if executable { 0o100755 } else { 0o100644 };
+        self.invalidate(path);
+        Ok(())
+    }
+
+    /// Takes in the stat data of files that only look changed, so they no
+    /// longer need hashing. Files that really changed are reported, unless
+    /// `quiet`; returns whether there were any.
+    fn refresh(&mut self, quiet: bool) -> Result<bool> {
+        let mut needs_update = false;
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &self.entries[i];
+            i += 1;
+            if entry.stage != 0 {
+                // once for all the stages of a conflict
+                while self.entries.get(i).map(|next| &next.path) == Some(&entry.path) {
+                    i += 1;
+                }
+                if !quiet {
+                    println!("{}: needs merge", entry.path);
+                }
+                needs_update = true;
+                continue;
+            }
+            if entry.skip_worktree || entry.assume_valid || entry.mode == 0o160000 {
+                continue;
+            }
+
+            let unchanged = match fs::symlink_metadata(&entry.path) {
+                Ok(metadata) if !metadata.is_dir() => {
+                    if self.is_clean(entry, &metadata) {
+                        continue;
+                    }
+                    match self.worktree_matches(entry, &metadata)? {
+                        true => Some(entry.with_stat(&metadata)),
+                        false => None,
+                    }
+                }
+                _ => None,
+            };
+            match unchanged {
+                Some(refreshed) => self.entries[i - 1] = refreshed,
+                None => {
+                    if !quiet {
+                        println!("{}: needs update", entry.path);
+                    }
+                    needs_update = true;
+                }
+            }
+        }
+        Ok(needs_update)
+    }
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    fn round_trip(index: &Index) -> Index {
+        Index::parse(&index.serialize()).unwrap()
+    }
+
+    fn paths(index: &Index) -> Vec<&str> {
+        index
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_v4_prefixes() {
+        let mut index = Index::new();
+        index.version = 4;
+        for path in ["a/b/c", "a/b/d", "a/bc", "b", "é", "è", "èa"] {
+            index.add(IndexEntry::new(path, 0o100644, SHA1));
+        }
+
+        let read = round_trip(&index);
+        assert_eq!(read.version, 4);
+        assert_eq!(paths(&read), paths(&index));
+    }
+
+    #[test]
+    fn keeps_extended_flags() {
+        let mut index = Index::new();
+        let mut entry = IndexEntry::new("x", 0o100755, SHA1);
+        entry.skip_worktree = true;
+        index.add(entry);
+        index.add(IndexEntry::new("y", 0o120000, SHA1));
+
+        let read = round_trip(&index);
+        assert_eq!(read.version, 3);
+        assert!(read.entries[0].skip_worktree);
+        assert_eq!(read.entries[0].mode, 0o100755);
+        assert!(!read.entries[1].skip_worktree);
+        assert_eq!(read.entries[1].mode, 0o120000);
+    }
+
+    #[test]
+    fn rejects_corrupt_counts() {
+        let mut index = Index::new();
+        index.add(IndexEntry::new("x", 0o100644, SHA1));
+        let mut bytes = index.serialize();
+        // an entry count far beyond what the file holds, with the checksum
+        // nulled so it isn't what fails
+        bytes[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        let end = bytes.len() - 20;
+        bytes[end..].fill(0);
+        assert!(Index::parse(&bytes).is_err());
+
+        let mut pos = 0;
+        assert!(read_varint(&[0xff; 12], &mut pos).is_err());
+        let mut pos = 0;
+        assert_eq!(read_varint(&[0x80, 0x00], &mut pos).unwrap(), 128);
+        let mut pos = 0;
+        assert!(CacheTree::parse(b"\x00-1 99999\n", &mut pos).is_err());
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut index = Index::new();
+        index.add(IndexEntry::new("x", 0o100644, SHA1));
+        let mut bytes = index.serialize();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(Index::parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+
+use crate::args::LsFiles as LsFilesArgs;
+use crate::index::{Index, IndexEntry};
+use crate::utils::{self, Ignore};
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The `ls-files` command, which shows the index next to the working tree.
+pub struct LsFiles {}
+
+impl LsFiles {
+    /// Lists paths the way `ls-files` does: untracked files first, then each
+    /// entry of the index (every stage of a conflict) with a line for each of
+    /// the cached, deleted and modified views it shows up in.
+    pub fn ls_files(args: &LsFilesArgs) -> Result<()> {
+        if args.ignored && !args.others && !args.cached {
+            return Err("ls-files -i must be used with either -o or -c".into());
+        }
+        if args.ignored && !args.exclude_standard {
+            return Err("ls-files --ignored needs some exclude pattern".into());
+        }
+        let show_stage = args.stage || args.unmerged;
+        let show_cached =
+            args.cached || !(show_stage || args.deleted || args.modified || args.others);
+
+        let specs: Vec<String> = args
+            .pathspec
+            .iter()
+            .map(|spec| utils::normalize_pathspec(spec))
+            .collect();
+        let matches = |path: &str| {
+            specs.is_empty() || specs.iter().any(|spec| utils::pathspec_matches(spec, path))
+        };
+        let end = if args.null_terminated { '\0' } else { '\n' };
+        let quote = |path: &str| match args.null_terminated {
+            true => Ok(path.to_string()),
+            false => utils::quote_path(path),
+        };
+
+        let index = Index::read()?;
+        let mut ignore = Ignore::load()?;
+
+        if args.others {
+            // ignored directories only need reading when their files are shown
+            let ignored_contents = args.ignored || !args.exclude_standard;
+            for (path, ignored) in utils::walk_worktree(&mut ignore, ignored_contents)? {
+                // without exclude rules, ignored files are just untracked ones
+                let ignored = args.exclude_standard && ignored;
+                if ignored == args.ignored && !index.is_tracked(&path) && matches(&path) {
+                    print!("{}{}", quote(&path)?, end);
+                }
+            }
+        }
+
+        for entry in &index.entries {
+            if !matches(&entry.path) || (args.ignored && !ignore.is_ignored(&entry.path, false)) {
+                continue;
+            }
+            let show = |entry: &IndexEntry| -> Result<()> {
+                match show_stage {
+                    true => print!(
+                        "{:06o} {} {}\t{}{}",
+                        entry.mode,
+                        entry.sha1,
+                        entry.stage,
+                        quote(&entry.path)?,
+                        end
+                    ),
+                    false => print!("{}{}", quote(&entry.path)?, end),
+                }
+                Ok(())
+            };
+
+            if (show_cached || show_stage) && (!args.unmerged || entry.stage != 0) {
+                show(entry)?;
+            }
+            if !(args.deleted || args.modified) || entry.skip_worktree {
+                continue;
+            }
+            let metadata = fs::symlink_metadata(&entry.path).ok();
+            if metadata.is_none() && args.deleted {
+                show(entry)?;
+            }
+            let modified = match &metadata {
+                None => true,
+                Some(_) if entry.mode == 0o160000 => false,
+                // a conflict's stages carry no stat data to compare with
+                Some(metadata) if metadata.is_dir() || entry.stage != 0 => true,
+                Some(metadata) => !index.worktree_matches(entry, metadata)?,
+            };
+            if args.modified && modified {
+                show(entry)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,27 @@
 mod args;
+mod batch;
 mod blob;
+mod checkout;
 mod clone;
 mod commands;
 mod commit;
+mod config;
+mod date;
+mod fsck;
+mod index;
+mod ls_files;
+mod merge_file;
 mod object;
 mod packfile;
+mod packindex;
+mod refs;
+mod signature;
+mod staging;
+mod status;
+mod switch;
+mod tag;
 mod tree;
+mod unpack;
 mod utils;
 
 pub type Error = Box<dyn std::error::Error>;
@@ -0,0 +1,223 @@
+/// The result of merging two versions of a file with their common base.
+pub struct MergedFile {
+    pub content: Vec<u8>,
+    /// Whether some of it is left between conflict markers.
+    pub conflicted: bool,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line, the way
+/// `git merge-file` does: a region changed on one side only takes that side,
+/// one changed alike on both takes either, and anything else becomes a
+/// conflict between markers naming the sides. Lines both sides of a conflict
+/// start or end with are left out of it.
+pub fn merge(base: &[u8], ours: &[u8], theirs: &[u8], labels: [&str; 2]) -> MergedFile {
+    let base = lines(base);
+    let ours = lines(ours);
+    let theirs = lines(theirs);
+    let ours_matches = matches(&base, &ours);
+    let theirs_matches = matches(&base, &theirs);
+
+    let mut merged = MergedFile {
+        content: Vec::new(),
+        conflicted: false,
+    };
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        // lines kept by both sides go through as they are
+        let mut stable = 0;
+        while o + stable < base.len()
+            && ours_matches[o + stable] == Some(a + stable)
+            && theirs_matches[o + stable] == Some(b + stable)
+        {
+            stable += 1;
+        }
+        if stable > 0 {
+            merged.content.extend(base[o..o + stable].concat());
+            o += stable;
+            a += stable;
+            b += stable;
+            continue;
+        }
+
+        // up to the next base line both sides still have, something changed
+        let next = (o..base.len()).find_map(|i| match (ours_matches[i], theirs_matches[i]) {
+            (Some(j), Some(k)) => Some((i, j, k)),
+            _ => None,
+        });
+        let (end_o, end_a, end_b) = next.unwrap_or((base.len(), ours.len(), theirs.len()));
+        let changed = (&base[o..end_o], &ours[a..end_a], &theirs[b..end_b]);
+        match changed {
+            (base, ours, theirs) if ours == base || ours == theirs => {
+                merged.content.extend(theirs.concat())
+            }
+            (base, ours, theirs) if theirs == base => merged.content.extend(ours.concat()),
+            (_, ours, theirs) => {
+                merged.conflicted = true;
+                write_conflict(&mut merged.content, ours, theirs, labels);
+            }
+        }
+        if next.is_none() {
+            return merged;
+        }
+        o = end_o;
+        a = end_a;
+        b = end_b;
+    }
+}
+
+fn lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|x| *x == b'\n').collect()
+}
+
+fn write_conflict(content: &mut Vec<u8>, ours: &[&[u8]], theirs: &[&[u8]], labels: [&str; 2]) {
+    let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+    let suffix = ours[prefix..]
+        .iter()
+        .rev()
+        .zip(theirs[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    content.extend(ours[..prefix].concat());
+    content.extend(format!("<<<<<<< {}\n", labels[0]).as_bytes());
+    let sides = [
+        (&ours[prefix..ours.len() - suffix], String::from("=======")),
+        (
+            &theirs[prefix..theirs.len() - suffix],
+            format!(">>>>>>> {}", labels[1]),
+        ),
+    ];
+    for (lines, marker) in sides.iter() {
+        content.extend(lines.concat());
+        if !content.ends_with(b"\n") {
+            content.push(b'\n');
+        }
+        content.extend(format!("{}\n", marker).as_bytes());
+    }
+    content.extend(ours[ours.len() - suffix..].concat());
+}
+
+/// For each line of `old`, the line of `new` it is kept as in a shortest
+/// edit script, found with Myers' algorithm.
+fn matches(old: &[&[u8]], new: &[&[u8]]) -> Vec<Option<usize>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    // the furthest `x` reached on each diagonal `k = x - y`, offset by `max + 1`
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let at = |k: isize| (k + max + 1) as usize;
+    // the diagonals -d..=d as they were before round d, to trace the path back
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v[at(-d)..=at(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = match k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                true => v[at(k + 1)],
+                false => v[at(k - 1)] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut matched = vec![None; old.len()];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let previous = match k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let (previous_x, previous_y) = match d {
+            0 => (0, 0),
+            _ => (get(previous), get(previous) - previous),
+        };
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            matched[x as usize] = Some(y as usize);
+        }
+        x = previous_x;
+        y = previous_y;
+    }
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LABELS: [&str; 2] = ["ours", "theirs"];
+
+    fn merged(base: &str, ours: &str, theirs: &str) -> (String, bool) {
+        let merged = merge(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), LABELS);
+        (
+            String::from_utf8(merged.content).unwrap(),
+            merged.conflicted,
+        )
+    }
+
+    #[test]
+    fn matches_kept_lines() {
+        let old: Vec<&[u8]> = vec![b"a", b"b", b"c", b"a", b"b", b"b", b"a"];
+        let new: Vec<&[u8]> = vec![b"c", b"b", b"a", b"b", b"a", b"c"];
+        let matched = matches(&old, &new);
+        // a shortest edit script keeps 4 of the lines, in order
+        let kept: Vec<(usize, usize)> = matched
+            .iter()
+            .enumerate()
+            .filter_map(|(i, j)| j.map(|j| (i, j)))
+            .collect();
+        assert_eq!(kept.len(), 4);
+        assert!(kept.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        assert!(kept.iter().all(|(i, j)| old[*i] == new[*j]));
+        assert_eq!(matches(&[], &new), vec![]);
+        assert_eq!(matches(&old[..2], &[]), vec![None, None]);
+    }
+
+    #[test]
+    fn merges_changes_to_different_lines() {
+        let base = "1\n2\n3\n4\n5\n";
+        assert_eq!(
+            merged(base, "one\n2\n3\n4\n5\n", "1\n2\n3\n4\nfive\n"),
+            (String::from("one\n2\n3\n4\nfive\n"), false)
+        );
+        assert_eq!(
+            merged(base, "1\n3\n4\n5\n", "1\n2\n3\n4\n5\n6\n"),
+            (String::from("1\n3\n4\n5\n6\n"), false)
+        );
+        // the same change on both sides is no conflict
+        assert_eq!(
+            merged(base, "1\n2\nthree\n4\n5\n", "1\n2\nthree\n4\n5\n"),
+            (String::from("1\n2\nthree\n4\n5\n"), false)
+        );
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        assert_eq!(
+            merged("1\n2\n3\n", "1\ntwo\nsame\n3\n", "1\nzwei\nsame\n3\n"),
+            (
+                String::from("1\n<<<<<<< ours\ntwo\n=======\nzwei\n>>>>>>> theirs\nsame\n3\n"),
+                true
+            )
+        );
+        // a missing newline at the end is made up for before a marker
+        assert_eq!(
+            merged("", "a", "b"),
+            (
+                String::from("<<<<<<< ours\na\n=======\nb\n>>>>>>> theirs\n"),
+                true
+            )
+        );
+    }
+}
@@ -1,8 +1,11 @@
-use crate::utils;
+use crate::config::Config;
+use crate::{packfile, packindex, utils};
 use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
 use std::fmt::Display;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
 use std::str;
 
 pub type Error = Box<dyn std::error::Error>;
@@ -17,6 +20,7 @@ pub enum ObjectType {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl Display for ObjectType {
@@ -25,85 +29,397 @@ impl Display for ObjectType {
             ObjectType::Blob => write!(f, "blob"),
             ObjectType::Tree => write!(f, "tree"),
             ObjectType::Commit => write!(f, "commit"),
+            ObjectType::Tag => write!(f, "tag"),
         }
     }
 }
 
+impl ObjectType {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "blob" => Ok(ObjectType::Blob),
+            "tree" => Ok(ObjectType::Tree),
+            "commit" => Ok(ObjectType::Commit),
+            "tag" => Ok(ObjectType::Tag),
+            _ => Err("Unidentified Git Object".into()),
+        }
+    }
+}
+
+/// An object whose header has been read but whose content is still waiting
+/// in the (inflating) reader, so big blobs never have to sit in memory.
+pub struct ObjectReader {
+    pub object_type: ObjectType,
+    pub size: usize,
+    reader: Box<dyn Read>,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl ObjectReader {
+    /// Copies the rest of the content to `writer`, reporting corruption found on
+    /// the way as the plain error message rather than a wrapped io error.
+    pub fn copy_to<W: Write>(&mut self, writer: &mut W) -> Result<u64> {
+        io::copy(self, writer).map_err(unwrap_io_error)
+    }
+
+    pub fn read_all(&mut self) -> Result<Vec<u8>> {
+        // the header's size is only trusted once the content has been read
+        let mut buffer = Vec::with_capacity(self.size.min(packfile::MAX_PREALLOCATION));
+        self.read_to_end(&mut buffer).map_err(unwrap_io_error)?;
+        Ok(buffer)
+    }
+}
+
+fn unwrap_io_error(e: io::Error) -> Error {
+    if e.get_ref().is_some() {
+        e.into_inner().unwrap()
+    } else {
+        e.into()
+    }
+}
+
+/// Passes an object's content through while checking that it is exactly as long
+/// as its header claims and, unless `core.verifyObjectHash` is off, that it
+/// hashes back to the name it was looked up by.
+struct VerifyingReader<R: Read> {
+    inner: R,
+    hasher: Option<Sha1>,
+    remaining: usize,
+    object_sha: String,
+    source: String,
+}
+
+impl<R: Read> VerifyingReader<R> {
+    fn new(
+        inner: R,
+        object_type: &str,
+        size: usize,
+        object_sha: &str,
+        source: String,
+        verify_hash: bool,
+    ) -> Self {
+        let hasher = if verify_hash {
+            let mut hasher = Sha1::new();
+            hasher.update(format!("{} {}\0", object_type, size).as_bytes());
+            Some(hasher)
+        } else {
+            None
+        };
+
+        VerifyingReader {
+            inner,
+            hasher,
+            remaining: size,
+            object_sha: object_sha.to_string(),
+            source,
+        }
+    }
+
+    fn corrupt(&self, reason: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is corrupt: {}", self.source, reason),
+        )
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self
+            .inner
+            .read(buf)
+            .map_err(|e| self.corrupt(&e.to_string()))?;
+
+        if n == 0 {
+            if self.remaining != 0 {
+                return Err(self.corrupt("it is shorter than its header says"));
+            }
+            if let Some(hasher) = self.hasher.take() {
+                let actual_sha = hex::encode(hasher.finalize());
+                if actual_sha != self.object_sha {
+                    return Err(self.corrupt(&format!(
+                        "it hashes to {}, not {}",
+                        actual_sha, self.object_sha
+                    )));
+                }
+            }
+            return Ok(0);
+        }
+
+        if n > self.remaining {
+            return Err(self.corrupt("it is longer than its header says"));
+        }
+        self.remaining -= n;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
 impl Object {
     pub fn open(object_sha: &str) -> Result<Self> {
+        let mut reader = Object::open_reader(object_sha)?;
+        let buffer = reader.read_all()?;
+
+        Ok(Object {
+            content: utils::object_bytes(&reader.object_type.to_string(), buffer),
+            object_type: reader.object_type,
+        })
+    }
+
+    pub fn open_reader(object_sha: &str) -> Result<ObjectReader> {
+        let object_sha = Object::resolve_prefix(object_sha)?;
         let dir_sha = &object_sha[..2];
         let file_name = &object_sha[2..];
+        let verify_hash = Config::cached()?
+            .get_bool("core.verifyObjectHash")?
+            .unwrap_or(true);
 
         let file_dir = format!(".git/objects/{}/{}", dir_sha, file_name);
-        let f = File::open(file_dir)?;
+        let f = match File::open(&file_dir) {
+            Ok(f) => f,
+            Err(e) => {
+                // packed objects may be deltas, so they're resolved in memory
+                let (pack_path, offset) = packindex::find_packed(&object_sha)?.ok_or(e)?;
+                let source = format!(
+                    "packed object {} (stored in {})",
+                    object_sha,
+                    pack_path.display()
+                );
+                let (object_type, content) = packfile::read_packed_object(&pack_path, offset)
+                    .map_err(|e| format!("{} is corrupt: {}", source, e))?;
+                let object_type = ObjectType::from_name(object_type.name())?;
+                let size = content.len();
+                return Ok(ObjectReader {
+                    reader: Box::new(VerifyingReader::new(
+                        Cursor::new(content),
+                        &object_type.to_string(),
+                        size,
+                        &object_sha,
+                        source,
+                        verify_hash,
+                    )),
+                    object_type,
+                    size,
+                });
+            }
+        };
+
+        let source = format!("object file {}", file_dir);
+        let corrupt =
+            |reason: &str| -> Error { format!("{} is corrupt: {}", source, reason).into() };
 
         let mut z = ZlibDecoder::new(f);
-        let mut buffer = Vec::new();
-        z.read_to_end(&mut buffer)?;
-        // println!("{:?}", buffer);
-
-        let index = buffer
-            .iter()
-            .position(|x| *x == ' ' as u8)
-            .expect("Error identifying Object Type");
-
-        let git_object = str::from_utf8(&buffer[..index])?;
-
-        match git_object {
-            "blob" => Ok(Object {
-                object_type: ObjectType::Blob,
-                content: buffer,
-            }),
-            "tree" => Ok(Object {
-                object_type: ObjectType::Tree,
-                content: buffer,
-            }),
-            "commit" => Ok(Object {
-                object_type: ObjectType::Commit,
-                content: buffer,
-            }),
-            _ => Err("Unidentified Git Object".into()),
+        let mut header = Vec::new();
+        let mut byte = [0; 1];
+        loop {
+            match z.read(&mut byte) {
+                Ok(0) if header.is_empty() => return Err(corrupt("it is empty")),
+                Ok(0) => return Err(corrupt("the header is truncated")),
+                Ok(_) => {}
+                Err(e) => return Err(corrupt(&e.to_string())),
+            }
+            if byte[0] == b'\0' {
+                break;
+            }
+            header.push(byte[0]);
+            if header.len() > 32 {
+                return Err(corrupt("the header is too long"));
+            }
         }
+
+        let header = str::from_utf8(&header).map_err(|_| corrupt("the header is not text"))?;
+        let index = header
+            .find(' ')
+            .ok_or_else(|| corrupt("the header has no size"))?;
+        let object_type = ObjectType::from_name(&header[..index])
+            .map_err(|_| corrupt(&format!("unknown object type \"{}\"", &header[..index])))?;
+        let size_field = &header[index + 1..];
+        if size_field.is_empty() || !size_field.bytes().all(|x| x.is_ascii_digit()) {
+            return Err(corrupt(&format!(
+                "bad size \"{}\" in the header",
+                size_field
+            )));
+        }
+        let size = size_field
+            .parse()
+            .map_err(|_| corrupt("the size in the header is too large"))?;
+
+        Ok(ObjectReader {
+            reader: Box::new(VerifyingReader::new(
+                z,
+                &object_type.to_string(),
+                size,
+                &object_sha,
+                source,
+                verify_hash,
+            )),
+            object_type,
+            size,
+        })
     }
 
-    pub fn hash_object<T: AsRef<[u8]>>(object_type: ObjectType, byte_vec: T) -> Result<String> {
-        let hex_sha1;
-        let content;
-
-        match object_type {
-            ObjectType::Tree => {
-                let mut byte_content: Vec<u8> = Vec::new();
-                byte_content.extend("tree ".as_bytes());
-                byte_content.extend(byte_vec.as_ref().len().to_string().as_bytes());
-                byte_content.push('\0' as u8);
-                byte_content.extend(byte_vec.as_ref());
-                hex_sha1 = utils::hex_sha1(&byte_content);
-                content = byte_content;
+    /// Every object, loose or packed, whose id starts with the hex `prefix`.
+    pub fn find_prefix(prefix: &str) -> Result<Vec<String>> {
+        let prefix = prefix.to_lowercase();
+        let mut objects = packindex::packed_with_prefix(&prefix)?;
+
+        let dir = format!(".git/objects/{}", &prefix[..2]);
+        if Path::new(&dir).is_dir() {
+            for file in utils::sorted_current_dir(&dir)? {
+                if let Some(file_name) = file.file_name().and_then(|x| x.to_str()) {
+                    if file_name.len() == 38 && file_name.starts_with(&prefix[2..]) {
+                        objects.push(format!("{}{}", &prefix[..2], file_name));
+                    }
+                }
             }
-            ObjectType::Commit => {
-                let mut byte_content: Vec<u8> = Vec::new();
-                byte_content.extend("commit ".as_bytes());
-                byte_content.extend(byte_vec.as_ref().len().to_string().as_bytes());
-                byte_content.push('\0' as u8);
-                byte_content.extend(byte_vec.as_ref());
-                hex_sha1 = utils::hex_sha1(&byte_content);
-                content = byte_content;
+        }
+
+        objects.sort();
+        objects.dedup();
+        Ok(objects)
+    }
+
+    /// Expands an abbreviated object id of at least four hex digits to the one
+    /// object it names, listing the candidates if there is more than one.
+    pub fn resolve_prefix(prefix: &str) -> Result<String> {
+        if prefix.len() < 4 || prefix.len() > 40 || !prefix.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(format!("Not a valid object name {}", prefix).into());
+        }
+        if prefix.len() == 40 {
+            return Ok(prefix.to_lowercase());
+        }
+
+        let mut candidates = Object::find_prefix(prefix)?;
+        match candidates.len() {
+            0 => Err(format!("Not a valid object name {}", prefix).into()),
+            1 => Ok(candidates.remove(0)),
+            _ => {
+                eprintln!("hint: The candidates are:");
+                for candidate in &candidates {
+                    let object_type = match Object::open_reader(candidate) {
+                        Ok(reader) => reader.object_type.to_string(),
+                        Err(_) => String::from("bad object"),
+                    };
+                    eprintln!(
+                        "hint:   {} {}",
+                        Object::shortest_abbrev(candidate)?,
+                        object_type
+                    );
+                }
+                Err(format!("short object ID {} is ambiguous", prefix).into())
             }
-            ObjectType::Blob => {
-                let fs = byte_vec.as_ref();
-                content = format!(
-                    "{} {}\u{0000}{}",
-                    object_type,
-                    fs.len(),
-                    str::from_utf8(fs)?
-                )
-                .as_bytes()
-                .to_vec();
-                hex_sha1 = utils::hex_sha1(&content);
+        }
+    }
+
+    /// The shortest prefix of `object_sha` that no other object in the repository
+    /// shares, but never shorter than `core.abbrev` (7 by default).
+    pub fn shortest_abbrev(object_sha: &str) -> Result<String> {
+        let min_length = match Config::cached()?.get("core.abbrev") {
+            Some(abbrev) => match abbrev.parse::<usize>() {
+                Ok(length) => length.clamp(4, 40),
+                Err(_) if abbrev == "auto" => 7,
+                Err(_) => return Err(format!("bad core.abbrev value '{}'", abbrev).into()),
+            },
+            None => 7,
+        };
+
+        let mut length = min_length;
+        for other in Object::find_prefix(&object_sha[..4])? {
+            if other == object_sha {
+                continue;
             }
+            let common = other
+                .bytes()
+                .zip(object_sha.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            length = length.max(common + 1);
         }
 
-        utils::save_object(&hex_sha1, content)?;
+        Ok(object_sha[..length.min(40)].to_string())
+    }
+
+    /// Every object in the repository, loose or packed, sorted and without duplicates.
+    pub fn all_objects() -> Result<Vec<String>> {
+        let mut objects = packindex::packed_objects()?;
+
+        for dir in utils::sorted_current_dir(".git/objects")? {
+            let dir_name = match dir.file_name().and_then(|x| x.to_str()) {
+                Some(name) if name.len() == 2 && name.bytes().all(|x| x.is_ascii_hexdigit()) => {
+                    name.to_string()
+                }
+                _ => continue,
+            };
+            for file in utils::sorted_current_dir(&dir)? {
+                if let Some(file_name) = file.file_name().and_then(|x| x.to_str()) {
+                    if file_name.len() == 38 && file_name.bytes().all(|x| x.is_ascii_hexdigit()) {
+                        objects.push(format!("{}{}", dir_name, file_name));
+                    }
+                }
+            }
+        }
+
+        objects.sort();
+        objects.dedup();
+        Ok(objects)
+    }
+
+    /// The object's content with the `<type> <size>\0` header stripped.
+    pub fn body(&self) -> &[u8] {
+        match self.content.iter().position(|x| *x == 0) {
+            Some(index) => &self.content[index + 1..],
+            None => &[],
+        }
+    }
+
+    pub fn hash_object<T: AsRef<[u8]>>(object_type: ObjectType, byte_vec: T) -> Result<String> {
+        Object::hash_bytes(&object_type.to_string(), byte_vec, true)
+    }
+
+    /// Hashes (and with `write`, stores) a blob straight from a file on disk.
+    pub fn hash_file<T: AsRef<Path>>(path: T, write: bool) -> Result<String> {
+        let f = File::open(path.as_ref())?;
+        let size = f.metadata()?.len();
+        Object::hash_reader("blob", f, size, write)
+    }
+
+    /// Hashes `size` bytes from `reader` in a single pass, compressing them into
+    /// the object store on the way when `write` is set.
+    pub fn hash_reader<R: Read>(
+        object_type: &str,
+        mut reader: R,
+        size: u64,
+        write: bool,
+    ) -> Result<String> {
+        let mut writer = utils::ObjectWriter::new(object_type, size, write)?;
+        let copied = io::copy(&mut reader.by_ref().take(size), &mut writer)?;
+        if copied != size || reader.read(&mut [0; 1])? != 0 {
+            return Err("File changed size while it was being hashed".into());
+        }
+        writer.finish()
+    }
+
+    /// Computes the id `byte_vec` gets as an object of `object_type`, only storing it
+    /// when `write` is set. The type isn't checked, so `hash-object --literally` can
+    /// create objects of made up types.
+    pub fn hash_bytes<T: AsRef<[u8]>>(
+        object_type: &str,
+        byte_vec: T,
+        write: bool,
+    ) -> Result<String> {
+        let content = utils::object_bytes(object_type, byte_vec);
+        let hex_sha1 = utils::hex_sha1(&content);
+
+        if write {
+            utils::save_object(&hex_sha1, content)?;
+        }
 
         Ok(hex_sha1)
     }
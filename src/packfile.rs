@@ -1,13 +1,30 @@
 #![allow(non_camel_case_types)]
 
 use std::{
+    collections::HashMap,
     convert::TryInto,
     fmt::Debug,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
 };
 
+use flate2::bufread::ZlibDecoder;
+
+use crate::object::Object;
+use crate::{fsck, utils};
+
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The most that is reserved up front for an object, whatever size the pack
+/// claims; anything bigger grows as it is inflated.
+pub const MAX_PREALLOCATION: usize = 1 << 20;
+
+/// How long a chain of deltas is followed when reading an object, well past
+/// the 4095 `pack-objects` goes to, so a pack looping back on itself fails.
+const MAX_DELTA_DEPTH: usize = 10000;
+
 pub struct Packfile {
     header: PackHeader,
     chunks: Vec<u8>,
@@ -20,7 +37,8 @@ impl Debug for Packfile {
     }
 }
 
-enum _OBJECT_TYPE {
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OBJECT_TYPE {
     OBJ_COMMIT = 1,
     OBJ_TREE = 2,
     OBJ_BLOB = 3,
@@ -29,7 +47,42 @@ enum _OBJECT_TYPE {
     OBJ_REF_DELTA = 7,
 }
 
-struct PackHeader {
+impl OBJECT_TYPE {
+    fn from_bits(bits: u8) -> Result<Self> {
+        match bits {
+            1 => Ok(OBJECT_TYPE::OBJ_COMMIT),
+            2 => Ok(OBJECT_TYPE::OBJ_TREE),
+            3 => Ok(OBJECT_TYPE::OBJ_BLOB),
+            4 => Ok(OBJECT_TYPE::OBJ_TAG),
+            6 => Ok(OBJECT_TYPE::OBJ_OFS_DELTA),
+            7 => Ok(OBJECT_TYPE::OBJ_REF_DELTA),
+            _ => Err(format!("Invalid Pack Object Type {}", bits).into()),
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "commit" => Ok(OBJECT_TYPE::OBJ_COMMIT),
+            "tree" => Ok(OBJECT_TYPE::OBJ_TREE),
+            "blob" => Ok(OBJECT_TYPE::OBJ_BLOB),
+            "tag" => Ok(OBJECT_TYPE::OBJ_TAG),
+            _ => Err(format!("Invalid Object Type {}", name).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OBJECT_TYPE::OBJ_COMMIT => "commit",
+            OBJECT_TYPE::OBJ_TREE => "tree",
+            OBJECT_TYPE::OBJ_BLOB => "blob",
+            OBJECT_TYPE::OBJ_TAG => "tag",
+            OBJECT_TYPE::OBJ_OFS_DELTA => "ofs-delta",
+            OBJECT_TYPE::OBJ_REF_DELTA => "ref-delta",
+        }
+    }
+}
+
+pub struct PackHeader {
     pack: [u8; 4],
     version: [u8; 4],
     object_number: [u8; 4],
@@ -45,30 +98,533 @@ impl Debug for PackHeader {
     }
 }
 
+impl PackHeader {
+    pub fn version(&self) -> u32 {
+        u32::from_be_bytes(self.version)
+    }
+
+    pub fn object_number(&self) -> u32 {
+        u32::from_be_bytes(self.object_number)
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(12);
+        vec.extend(self.pack);
+        vec.extend(self.version);
+        vec.extend(self.object_number);
+        vec
+    }
+}
+
+pub enum DeltaBase {
+    Offset(usize),
+    Ref(String),
+}
+
+/// An entry exactly as it is stored in the pack, with its data inflated but
+/// not yet resolved against a delta base.
+pub struct PackEntry {
+    pub offset: usize,
+    pub object_type: OBJECT_TYPE,
+    pub size: usize,
+    pub packed_size: usize,
+    pub base: Option<DeltaBase>,
+    pub data: Vec<u8>,
+}
+
+/// A fully resolved object along with the bookkeeping of where it came from.
+pub struct PackObject {
+    pub sha1: String,
+    pub object_type: OBJECT_TYPE,
+    pub content: Vec<u8>,
+    pub offset: usize,
+    pub size: usize,
+    pub packed_size: usize,
+    pub depth: usize,
+    pub base: Option<String>,
+}
+
 impl Packfile {
     pub fn new<T: AsRef<[u8]>>(bytes: T) -> Result<Self> {
+        let bytes = bytes.as_ref();
+        if bytes.len() < 32 {
+            return Err("Pack too short".into());
+        }
+
         let header = PackHeader {
-            pack: bytes.as_ref()[..4].try_into().unwrap(),
-            version: bytes.as_ref()[4..8].try_into().unwrap(),
-            object_number: bytes.as_ref()[8..12].try_into().unwrap(),
+            pack: bytes[..4].try_into().unwrap(),
+            version: bytes[4..8].try_into().unwrap(),
+            object_number: bytes[8..12].try_into().unwrap(),
         };
 
         let pack_header: [u8; 4] = "PACK".as_bytes().try_into()?;
         if header.pack != pack_header {
             return Err("Invalid Pack Header".into());
         }
+        if header.version() != 2 && header.version() != 3 {
+            return Err(format!("Unsupported Pack Version {}", header.version()).into());
+        }
 
-        let byte_length = bytes.as_ref().len();
+        let byte_length = bytes.len();
         Ok(Packfile {
             header,
-            chunks: bytes.as_ref()[12..byte_length - 20].to_vec(),
-            checksum: bytes.as_ref()[byte_length - 20..byte_length]
-                .try_into()
-                .unwrap(),
+            chunks: bytes[12..byte_length - 20].to_vec(),
+            checksum: bytes[byte_length - 20..byte_length].try_into().unwrap(),
+        })
+    }
+
+    pub fn header(&self) -> &PackHeader {
+        &self.header
+    }
+
+    pub fn checksum(&self) -> &[u8; 20] {
+        &self.checksum
+    }
+
+    pub fn verify_checksum(&self) -> Result<()> {
+        let mut bytes = self.header.as_bytes();
+        bytes.extend(&self.chunks);
+        if utils::hex_sha1(bytes) != hex::encode(self.checksum) {
+            return Err("Pack Checksum Mismatch".into());
+        }
+        Ok(())
+    }
+
+    /// Reads the entry starting at `offset`, counted from the start of the pack.
+    pub fn entry_at(&self, offset: usize) -> Result<PackEntry> {
+        let pos = offset
+            .checked_sub(12)
+            .filter(|pos| *pos < self.chunks.len())
+            .ok_or_else(|| format!("Invalid Pack Offset {}", offset))?;
+
+        let mut reader = &self.chunks[pos..];
+        let (object_type, size, base) = entry_header(&mut reader, offset)?;
+
+        let mut z = ZlibDecoder::new(reader);
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+        z.read_to_end(&mut data)?;
+        if data.len() != size {
+            return Err(format!("Pack Entry at {} has Incorrect Size", offset).into());
+        }
+        let header_size = self.chunks.len() - pos - reader.len();
+
+        Ok(PackEntry {
+            offset,
+            object_type,
+            size,
+            packed_size: header_size + z.total_in() as usize,
+            base,
+            data,
         })
     }
 
-    pub fn parse_pack(&self) {
-        todo!()
+    pub fn entries(&self) -> Result<Vec<PackEntry>> {
+        let mut entries = Vec::new();
+        let mut offset = 12;
+        for _ in 0..self.header.object_number() {
+            let entry = self.entry_at(offset)?;
+            offset += entry.packed_size;
+            entries.push(entry);
+        }
+
+        if offset != self.chunks.len() + 12 {
+            return Err("Pack has Trailing Garbage".into());
+        }
+
+        Ok(entries)
+    }
+
+    /// Resolves every entry in the pack. Ref deltas whose base isn't in the pack
+    /// (thin packs) are resolved against the objects already in the repository.
+    pub fn parse_pack(&self) -> Result<Vec<PackObject>> {
+        let entries = self.entries()?;
+        let by_offset: HashMap<usize, usize> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.offset, i))
+            .collect();
+
+        let mut resolved: Vec<Option<PackObject>> = entries.iter().map(|_| None).collect();
+        let mut by_sha1: HashMap<String, usize> = HashMap::new();
+        let mut remaining = entries.len();
+
+        while remaining > 0 {
+            let mut progress = false;
+
+            for (i, entry) in entries.iter().enumerate() {
+                if resolved[i].is_some() {
+                    continue;
+                }
+
+                let (object_type, content, depth, base) = match &entry.base {
+                    None => (entry.object_type, entry.data.clone(), 0, None),
+                    Some(DeltaBase::Offset(base_offset)) => {
+                        let base_index = *by_offset
+                            .get(base_offset)
+                            .ok_or("Delta Base Offset is not an Entry")?;
+                        match &resolved[base_index] {
+                            Some(base) => (
+                                base.object_type,
+                                apply_delta(&base.content, &entry.data)?,
+                                base.depth + 1,
+                                Some(base.sha1.clone()),
+                            ),
+                            None => continue,
+                        }
+                    }
+                    Some(DeltaBase::Ref(base_sha1)) => match by_sha1.get(base_sha1) {
+                        Some(base_index) => {
+                            let base = resolved[*base_index].as_ref().unwrap();
+                            (
+                                base.object_type,
+                                apply_delta(&base.content, &entry.data)?,
+                                base.depth + 1,
+                                Some(base.sha1.clone()),
+                            )
+                        }
+                        None => continue,
+                    },
+                };
+
+                let sha1 = utils::hex_sha1(utils::object_bytes(object_type.name(), &content));
+                by_sha1.insert(sha1.clone(), i);
+                resolved[i] = Some(PackObject {
+                    sha1,
+                    object_type,
+                    content,
+                    offset: entry.offset,
+                    size: entry.size,
+                    packed_size: entry.packed_size,
+                    depth,
+                    base,
+                });
+                remaining -= 1;
+                progress = true;
+            }
+
+            if !progress {
+                // whatever is left must be a ref delta against an object outside the
+                // pack, or a delta waiting on one
+                let mut waiting = entries.iter().enumerate().filter_map(|(i, entry)| {
+                    match (&resolved[i], &entry.base) {
+                        (None, Some(DeltaBase::Ref(sha1))) => Some((i, entry, sha1)),
+                        _ => None,
+                    }
+                });
+                let (i, entry, base_sha1) =
+                    waiting.next().ok_or("Unresolvable Delta Chain in Pack")?;
+                let (i, entry, base_sha1, base) = match Object::open(base_sha1) {
+                    Ok(base) => (i, entry, base_sha1, base),
+                    Err(_) => waiting
+                        .find_map(|(i, entry, sha1)| {
+                            Object::open(sha1).ok().map(|base| (i, entry, sha1, base))
+                        })
+                        .ok_or_else(|| format!("Missing Delta Base {}", base_sha1))?,
+                };
+                let object_type = OBJECT_TYPE::from_name(&base.object_type.to_string())?;
+                let content = apply_delta(base.body(), &entry.data)?;
+
+                let sha1 = utils::hex_sha1(utils::object_bytes(object_type.name(), &content));
+                by_sha1.insert(sha1.clone(), i);
+                resolved[i] = Some(PackObject {
+                    sha1,
+                    object_type,
+                    content,
+                    offset: entry.offset,
+                    size: entry.size,
+                    packed_size: entry.packed_size,
+                    depth: 1,
+                    base: Some(base_sha1.clone()),
+                });
+                remaining -= 1;
+            }
+        }
+
+        Ok(resolved.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Explodes the pack into loose objects. With `strict` every object is checked
+    /// before the first one is written, so a bad pack leaves the repository untouched.
+    pub fn unpack(&self, strict: bool) -> Result<Vec<String>> {
+        self.verify_checksum()?;
+        let objects = self.parse_pack()?;
+
+        if strict {
+            let in_pack: HashMap<&str, &str> = objects
+                .iter()
+                .map(|object| (object.sha1.as_str(), object.object_type.name()))
+                .collect();
+
+            for object in &objects {
+                fsck::check_object(object.object_type.name(), &object.content)
+                    .map_err(|e| format!("{}: {}", object.sha1, e))?;
+                for (link, link_type) in
+                    fsck::object_links(object.object_type.name(), &object.content)
+                {
+                    let found = match in_pack.get(link.as_str()) {
+                        Some(found) => found.to_string(),
+                        None => match Object::open(&link) {
+                            Ok(linked) => linked.object_type.to_string(),
+                            Err(_) => {
+                                return Err(format!(
+                                    "{}: missing {} {}",
+                                    object.sha1, link_type, link
+                                )
+                                .into())
+                            }
+                        },
+                    };
+                    if found != link_type {
+                        return Err(format!(
+                            "{}: {} is a {}, not a {}",
+                            object.sha1, link, found, link_type
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+
+        let mut written = Vec::new();
+        for object in objects {
+            utils::save_object(
+                &object.sha1,
+                utils::object_bytes(object.object_type.name(), &object.content),
+            )?;
+            written.push(object.sha1);
+        }
+
+        Ok(written)
+    }
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> Result<u8> {
+    let mut byte = [0; 1];
+    reader
+        .read_exact(&mut byte)
+        .map_err(|_| "Truncated Pack Entry")?;
+    Ok(byte[0])
+}
+
+/// Parses the type and size of the entry at `offset` and, for deltas, where
+/// its base lives, leaving `reader` at the start of the zlib stream.
+fn entry_header<R: Read>(
+    reader: &mut R,
+    offset: usize,
+) -> Result<(OBJECT_TYPE, usize, Option<DeltaBase>)> {
+    let mut byte = read_byte(reader)?;
+    let object_type = OBJECT_TYPE::from_bits((byte >> 4) & 0x7)?;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        if shift >= usize::BITS {
+            return Err("Pack Entry Size Overflows".into());
+        }
+        byte = read_byte(reader)?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let base = match object_type {
+        OBJECT_TYPE::OBJ_OFS_DELTA => {
+            byte = read_byte(reader)?;
+            let mut distance = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = read_byte(reader)?;
+                distance = distance
+                    .checked_add(1)
+                    .and_then(|x| x.checked_mul(1 << 7))
+                    .ok_or("Invalid Delta Base Offset")?
+                    | (byte & 0x7f) as usize;
+            }
+            let base_offset = offset
+                .checked_sub(distance)
+                .filter(|base| *base >= 12 && *base < offset)
+                .ok_or("Invalid Delta Base Offset")?;
+            Some(DeltaBase::Offset(base_offset))
+        }
+        OBJECT_TYPE::OBJ_REF_DELTA => {
+            let mut sha1 = [0; 20];
+            reader
+                .read_exact(&mut sha1)
+                .map_err(|_| "Truncated Pack Entry")?;
+            Some(DeltaBase::Ref(hex::encode(sha1)))
+        }
+        _ => None,
+    };
+
+    Ok((object_type, size, base))
+}
+
+/// Reads one object straight out of a pack on disk, seeking to the entries it
+/// needs instead of loading the whole pack.
+pub fn read_packed_object<T: AsRef<Path>>(
+    pack_path: T,
+    offset: usize,
+) -> Result<(OBJECT_TYPE, Vec<u8>)> {
+    let mut file = File::open(pack_path.as_ref())?;
+    read_packed_entry(&mut file, offset)
+}
+
+fn read_packed_entry(file: &mut File, offset: usize) -> Result<(OBJECT_TYPE, Vec<u8>)> {
+    // the deltas met on the way down to a whole object, applied on the way up
+    let mut deltas: Vec<Vec<u8>> = Vec::new();
+    let mut offset = offset;
+    let (object_type, mut content) = loop {
+        file.seek(SeekFrom::Start(offset as u64))?;
+        let mut reader = BufReader::new(&mut *file);
+        let (object_type, size, base) = entry_header(&mut reader, offset)?;
+
+        let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+        ZlibDecoder::new(reader).read_to_end(&mut data)?;
+        if data.len() != size {
+            return Err(format!("Pack Entry at {} has Incorrect Size", offset).into());
+        }
+
+        match base {
+            None => break (object_type, data),
+            Some(DeltaBase::Offset(base_offset)) => {
+                if deltas.len() >= MAX_DELTA_DEPTH {
+                    return Err(format!("Delta Chain at {} is too Long", offset).into());
+                }
+                deltas.push(data);
+                offset = base_offset;
+            }
+            Some(DeltaBase::Ref(base_sha1)) => {
+                let base = Object::open(&base_sha1)?;
+                let base_type = OBJECT_TYPE::from_name(&base.object_type.to_string())?;
+                break (base_type, apply_delta(base.body(), &data)?);
+            }
+        }
+    };
+
+    for delta in deltas.iter().rev() {
+        content = apply_delta(&content, delta)?;
+    }
+    Ok((object_type, content))
+}
+
+fn delta_size(delta: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        if shift >= usize::BITS {
+            return Err("Delta Size Overflows".into());
+        }
+        let byte = *delta.get(*pos).ok_or("Truncated Delta")?;
+        *pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = delta_size(delta, &mut pos)?;
+    if base_size != base.len() {
+        return Err("Delta Base Size Mismatch".into());
+    }
+    let result_size = delta_size(delta, &mut pos)?;
+
+    let mut result = Vec::with_capacity(result_size.min(MAX_PREALLOCATION));
+    while pos < delta.len() {
+        let instruction = delta[pos];
+        pos += 1;
+
+        if instruction & 0x80 != 0 {
+            // copy from base: the low 4 bits select offset bytes, the next 3 size bytes
+            let mut copy_offset = 0;
+            let mut copy_size = 0;
+            for i in 0..4 {
+                if instruction & (1 << i) != 0 {
+                    copy_offset |= (*delta.get(pos).ok_or("Truncated Delta")? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if instruction & (0x10 << i) != 0 {
+                    copy_size |= (*delta.get(pos).ok_or("Truncated Delta")? as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+            let copy = base
+                .get(copy_offset..copy_offset + copy_size)
+                .ok_or("Delta Copy out of Range")?;
+            result.extend(copy);
+        } else if instruction != 0 {
+            let insert = delta
+                .get(pos..pos + instruction as usize)
+                .ok_or("Truncated Delta")?;
+            result.extend(insert);
+            pos += instruction as usize;
+        } else {
+            return Err("Invalid Delta Instruction".into());
+        }
+    }
+
+    if result.len() != result_size {
+        return Err("Delta Result Size Mismatch".into());
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &[u8] = b"hello, world\n";
+
+    #[test]
+    fn copies_and_inserts() {
+        // copy "hello" (offset 0, size 5), insert " there", copy ", world\n"
+        let delta = [
+            13, 19, 0x90, 5, 6, b' ', b't', b'h', b'e', b'r', b'e', 0x91, 5, 8,
+        ];
+        assert_eq!(apply_delta(BASE, &delta).unwrap(), b"hello there, world\n");
+    }
+
+    #[test]
+    fn reads_multibyte_sizes() {
+        let base = vec![b'x'; 200];
+        // sizes of 200 take two bytes
+        let delta = [0xc8, 0x01, 0xc8, 0x01, 0x90, 200];
+        assert_eq!(apply_delta(&base, &delta).unwrap(), base);
+        // a copy without size bytes is 0x10000 long, more than the base has
+        assert!(apply_delta(&base, &[0xc8, 0x01, 0x80, 0x80, 0x04, 0x80]).is_err());
+    }
+
+    #[test]
+    fn rejects_bases_not_before_the_delta() {
+        // an OBJ_OFS_DELTA of size 0 whose base is 0 bytes back, then 200 back
+        assert!(entry_header(&mut &[0x60, 0x00][..], 100).is_err());
+        assert!(entry_header(&mut &[0x60, 0x80, 0x48][..], 100).is_err());
+        assert!(matches!(
+            entry_header(&mut &[0x60, 0x10][..], 100).unwrap().2,
+            Some(DeltaBase::Offset(84))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_deltas() {
+        // the base doesn't have the size the delta expects
+        assert!(apply_delta(BASE, &[12, 5, 0x90, 5]).is_err());
+        // the result doesn't have the size announced
+        assert!(apply_delta(BASE, &[13, 6, 0x90, 5]).is_err());
+        // a copy past the end of the base
+        assert!(apply_delta(BASE, &[13, 5, 0x91, 10, 5]).is_err());
+        // an insert cut short, and a copy missing its operands
+        assert!(apply_delta(BASE, &[13, 5, 5, b'a']).is_err());
+        assert!(apply_delta(BASE, &[13, 5, 0x91]).is_err());
+        // instruction 0 is reserved
+        assert!(apply_delta(BASE, &[13, 0, 0]).is_err());
+        // sizes that don't fit
+        assert!(apply_delta(BASE, &[0xff; 12]).is_err());
     }
 }
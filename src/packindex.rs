@@ -0,0 +1,302 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flate2::Crc;
+
+use crate::packfile::Packfile;
+use crate::utils;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub struct PackIndex {
+    entries: Vec<IndexEntry>,
+    pack_checksum: [u8; 20],
+    checksum: [u8; 20],
+    computed_checksum: String,
+}
+
+pub struct IndexEntry {
+    pub sha1: String,
+    pub offset: usize,
+    pub crc32: Option<u32>,
+}
+
+thread_local! {
+    // indexes are loaded once per process, objects are looked up in them many times
+    static PACKS: RefCell<Option<Vec<(PathBuf, PackIndex)>>> = const { RefCell::new(None) };
+}
+
+fn with_packs<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce(&[(PathBuf, PackIndex)]) -> T,
+{
+    PACKS.with(|packs| {
+        let mut packs = packs.borrow_mut();
+        if packs.is_none() {
+            let mut loaded = Vec::new();
+            if Path::new(".git/objects/pack").is_dir() {
+                for path in utils::sorted_current_dir(".git/objects/pack")? {
+                    if path.extension() == Some(OsStr::new("idx")) {
+                        let index = PackIndex::open(&path)?;
+                        loaded.push((path.with_extension("pack"), index));
+                    }
+                }
+            }
+            *packs = Some(loaded);
+        }
+        Ok(f(packs.as_ref().unwrap()))
+    })
+}
+
+/// Forgets the loaded indexes, for packs created or removed since to be seen.
+pub fn reset_packs() {
+    PACKS.with(|packs| *packs.borrow_mut() = None);
+}
+
+/// Finds the pack in `.git/objects/pack` holding `sha1` and the object's offset in it.
+pub fn find_packed(sha1: &str) -> Result<Option<(PathBuf, usize)>> {
+    with_packs(|packs| {
+        packs.iter().find_map(|(pack_path, index)| {
+            index
+                .find(sha1)
+                .map(|entry| (pack_path.clone(), entry.offset))
+        })
+    })
+}
+
+/// The packed object ids starting with the hex `prefix`.
+pub fn packed_with_prefix(prefix: &str) -> Result<Vec<String>> {
+    with_packs(|packs| {
+        packs
+            .iter()
+            .flat_map(|(_, index)| {
+                let start = index
+                    .entries
+                    .partition_point(|entry| entry.sha1.as_str() < prefix);
+                index.entries[start..]
+                    .iter()
+                    .take_while(|entry| entry.sha1.starts_with(prefix))
+                    .map(|entry| entry.sha1.clone())
+            })
+            .collect()
+    })
+}
+
+/// Every object id listed in the repository's pack indexes.
+pub fn packed_objects() -> Result<Vec<String>> {
+    with_packs(|packs| {
+        packs
+            .iter()
+            .flat_map(|(_, index)| index.entries.iter().map(|entry| entry.sha1.clone()))
+            .collect()
+    })
+}
+
+fn be_u32(bytes: &[u8], pos: usize) -> Result<u32> {
+    let word = bytes.get(pos..pos + 4).ok_or("Truncated Pack Index")?;
+    Ok(u32::from_be_bytes(word.try_into().unwrap()))
+}
+
+impl PackIndex {
+    const MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self> {
+        let bytes =
+            fs::read(path.as_ref()).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        PackIndex::new(bytes)
+    }
+
+    pub fn new<T: AsRef<[u8]>>(bytes: T) -> Result<Self> {
+        let bytes = bytes.as_ref();
+        if bytes.len() < 256 * 4 + 40 {
+            return Err("Pack Index too short".into());
+        }
+
+        let (version, fanout_start) = if bytes[..4] == PackIndex::MAGIC {
+            (be_u32(bytes, 4)?, 8)
+        } else {
+            (1, 0)
+        };
+        if version != 1 && version != 2 {
+            return Err(format!("Unsupported Pack Index Version {}", version).into());
+        }
+
+        let count = be_u32(bytes, fanout_start + 255 * 4)? as usize;
+        let table = fanout_start + 256 * 4;
+        // the count is checked against the tables it needs before anything
+        // is reserved for it
+        let entry_size = if version == 1 { 24 } else { 28 };
+        let tables_end = count
+            .checked_mul(entry_size)
+            .and_then(|size| size.checked_add(table + 40));
+        if !matches!(tables_end, Some(end) if end <= bytes.len()) {
+            return Err("Truncated Pack Index".into());
+        }
+        let mut entries = Vec::with_capacity(count);
+
+        if version == 1 {
+            for i in 0..count {
+                let pos = table + i * 24;
+                let sha1 = bytes.get(pos + 4..pos + 24).ok_or("Truncated Pack Index")?;
+                entries.push(IndexEntry {
+                    sha1: hex::encode(sha1),
+                    offset: be_u32(bytes, pos)? as usize,
+                    crc32: None,
+                });
+            }
+        } else {
+            let crc_table = table + count * 20;
+            let offset_table = crc_table + count * 4;
+            let large_offset_table = offset_table + count * 4;
+            for i in 0..count {
+                let sha1 = bytes
+                    .get(table + i * 20..table + (i + 1) * 20)
+                    .ok_or("Truncated Pack Index")?;
+                let mut offset = be_u32(bytes, offset_table + i * 4)? as usize;
+                if offset & 0x8000_0000 != 0 {
+                    // the low bits index into the table of 64 bit offsets
+                    let pos = large_offset_table + (offset & 0x7fff_ffff) * 8;
+                    let large = bytes.get(pos..pos + 8).ok_or("Truncated Pack Index")?;
+                    offset = u64::from_be_bytes(large.try_into().unwrap()) as usize;
+                }
+                entries.push(IndexEntry {
+                    sha1: hex::encode(sha1),
+                    offset,
+                    crc32: Some(be_u32(bytes, crc_table + i * 4)?),
+                });
+            }
+        }
+
+        let length = bytes.len();
+        Ok(PackIndex {
+            entries,
+            pack_checksum: bytes[length - 40..length - 20].try_into().unwrap(),
+            checksum: bytes[length - 20..].try_into().unwrap(),
+            computed_checksum: utils::hex_sha1(&bytes[..length - 20]),
+        })
+    }
+
+    pub fn find(&self, sha1: &str) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by(|entry| entry.sha1.as_str().cmp(sha1))
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+
+    pub fn verify_checksum(&self) -> Result<()> {
+        if self.computed_checksum != hex::encode(self.checksum) {
+            return Err("Pack Index Checksum Mismatch".into());
+        }
+        Ok(())
+    }
+
+    pub fn show_index(&self) -> Result<()> {
+        for entry in &self.entries {
+            match entry.crc32 {
+                Some(crc32) => println!("{} {} ({:08x})", entry.offset, entry.sha1, crc32),
+                None => println!("{} {}", entry.offset, entry.sha1),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a pack against its index the way `git verify-pack` does, optionally
+    /// listing every object and the delta chain histogram.
+    pub fn verify_pack<T: AsRef<Path>>(path: T, verbose: bool) -> Result<()> {
+        let idx_path = path.as_ref().with_extension("idx");
+        let pack_path: PathBuf = path.as_ref().with_extension("pack");
+
+        let index = PackIndex::open(&idx_path)?;
+        index.verify_checksum()?;
+
+        let bytes = fs::read(&pack_path).map_err(|e| format!("{}: {}", pack_path.display(), e))?;
+        let pack_file = Packfile::new(&bytes)?;
+        pack_file.verify_checksum()?;
+        if index.pack_checksum != *pack_file.checksum() {
+            return Err("Pack Checksum does not match its Index".into());
+        }
+        if index.entries.len() != pack_file.header().object_number() as usize {
+            return Err("Pack and Index disagree on the Number of Objects".into());
+        }
+
+        let mut objects = pack_file.parse_pack()?;
+        objects.sort_by_key(|object| object.offset);
+
+        let by_sha1: HashMap<&str, &IndexEntry> = index
+            .entries
+            .iter()
+            .map(|entry| (entry.sha1.as_str(), entry))
+            .collect();
+        for object in &objects {
+            let entry = by_sha1
+                .get(object.sha1.as_str())
+                .ok_or_else(|| format!("{} is missing from the Index", object.sha1))?;
+            if entry.offset != object.offset {
+                return Err(format!("{} has the wrong Offset in the Index", object.sha1).into());
+            }
+            if let Some(crc32) = entry.crc32 {
+                let mut crc = Crc::new();
+                crc.update(&bytes[object.offset..object.offset + object.packed_size]);
+                if crc.sum() != crc32 {
+                    return Err(format!("{} has a bad CRC32", object.sha1).into());
+                }
+            }
+        }
+
+        if !verbose {
+            return Ok(());
+        }
+
+        let mut chain_lengths: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut non_delta = 0;
+        for object in &objects {
+            match &object.base {
+                Some(base) => {
+                    *chain_lengths.entry(object.depth).or_insert(0) += 1;
+                    println!(
+                        "{} {:<6} {} {} {} {} {}",
+                        object.sha1,
+                        object.object_type.name(),
+                        object.size,
+                        object.packed_size,
+                        object.offset,
+                        object.depth,
+                        base
+                    );
+                }
+                None => {
+                    non_delta += 1;
+                    println!(
+                        "{} {:<6} {} {} {}",
+                        object.sha1,
+                        object.object_type.name(),
+                        object.size,
+                        object.packed_size,
+                        object.offset
+                    );
+                }
+            }
+        }
+
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        if non_delta > 0 {
+            println!("non delta: {} object{}", non_delta, plural(non_delta));
+        }
+        for (length, count) in chain_lengths {
+            println!(
+                "chain length = {}: {} object{}",
+                length,
+                count,
+                plural(count)
+            );
+        }
+        println!("{}: ok", pack_path.display());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use crate::commit::Commit;
+use crate::object::{Object, ObjectType};
+use crate::signature::Signature;
+use crate::tag::Tag;
+use crate::tree::Tree;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The old value logged for a ref that didn't exist before.
+pub const ZERO_SHA1: &str = "0000000000000000000000000000000000000000";
+
+fn is_hex_sha1(s: &str) -> bool {
+    s.len() == 40 && s.bytes().all(|x| x.is_ascii_hexdigit())
+}
+
+/// Looks a ref up in `.git/packed-refs`, which stock git writes on clone and gc.
+fn packed_ref(name: &str) -> Result<Option<String>> {
+    let packed = match fs::read_to_string(".git/packed-refs") {
+        Ok(packed) => packed,
+        Err(_) => return Ok(None),
+    };
+
+    for line in packed.lines() {
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        let mut split_iter = line.splitn(2, ' ');
+        let sha1 = split_iter.next().unwrap_or("");
+        if split_iter.next() == Some(name) {
+            return Ok(Some(sha1.to_string()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads a ref such as `HEAD` or `refs/heads/master`, following symbolic refs.
+/// Returns `None` when the ref (or the branch a symref points at) doesn't exist yet.
+pub fn read_ref(name: &str) -> Result<Option<String>> {
+    let mut name = name.to_string();
+
+    // git gives up after five levels of symbolic refs as well
+    for _ in 0..5 {
+        let path = format!(".git/{}", name);
+        if !Path::new(&path).is_file() {
+            return packed_ref(&name);
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let content = content.trim_end();
+        match content.strip_prefix("ref: ") {
+            Some(target) => name = target.to_string(),
+            None if is_hex_sha1(content) => return Ok(Some(content.to_string())),
+            None => return Err(format!("Invalid Ref {}", name).into()),
+        }
+    }
+
+    Err(format!("Symbolic Ref Loop at {}", name).into())
+}
+
+/// The ref a symbolic ref such as `HEAD` points at, or `None` when it is
+/// detached or not symbolic.
+pub fn symbolic_ref(name: &str) -> Result<Option<String>> {
+    let content = match fs::read_to_string(format!(".git/{}", name)) {
+        Ok(content) => content,
+        Err(_) => return Ok(None),
+    };
+    Ok(content
+        .trim_end()
+        .strip_prefix("ref: ")
+        .map(|target| target.to_string()))
+}
+
+/// The full name of the ref a short name like `main` or `v1.0` stands for,
+/// trying the places git looks in, in git's order.
+pub fn dwim_ref(name: &str) -> Result<Option<String>> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for candidate in candidates {
+        if read_ref(&candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+fn resolve_name(name: &str) -> Result<Option<String>> {
+    if is_hex_sha1(name) {
+        return Ok(Some(name.to_lowercase()));
+    }
+    if let Some(previous) = previous_checkout(name)? {
+        return resolve_name(&previous);
+    }
+
+    if let Some(full_name) = dwim_ref(name)? {
+        return read_ref(&full_name);
+    }
+
+    // anything that isn't a ref may still be an abbreviated object id
+    if name.len() >= 4 && name.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return Object::resolve_prefix(name).map(Some);
+    }
+
+    Ok(None)
+}
+
+/// What `@{-n}` stands for: the branch checked out before the `n`th last
+/// checkout, or the commit when `HEAD` was detached, as told by the
+/// `checkout: moving from X to Y` entries of `HEAD`'s log. `None` when `spec`
+/// is something else or there weren't that many checkouts.
+pub fn previous_checkout(spec: &str) -> Result<Option<String>> {
+    let n = match spec
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        Some(n) if n > 0 => n,
+        _ => return Ok(None),
+    };
+    let log = match fs::read(".git/logs/HEAD") {
+        Ok(log) => log,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let log = String::from_utf8_lossy(&log);
+    let previous = log
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let (_, message) = line.split_once('\t')?;
+            let moved = message.strip_prefix("checkout: moving from ")?;
+            moved.split_once(" to ").map(|(from, _)| from.to_string())
+        })
+        .nth(n - 1);
+    Ok(previous)
+}
+
+/// Follows tags (and commits, when after a tree) until it reaches an object
+/// of `target_type`.
+pub fn peel(sha1: &str, target_type: &str) -> Result<String> {
+    let mut sha1 = sha1.to_string();
+    loop {
+        let object = Object::open(&sha1)?;
+        if object.object_type.to_string() == target_type {
+            return Ok(sha1);
+        }
+
+        sha1 = match (&object.object_type, target_type) {
+            (ObjectType::Tag, _) => Tag::parse(object.body())?.object,
+            (ObjectType::Commit, "tree") => Commit::parse(object.body())?.tree,
+            _ => {
+                return Err(format!(
+                    "{} is a {}, not a {}",
+                    sha1, object.object_type, target_type
+                )
+                .into())
+            }
+        };
+    }
+}
+
+/// Follows tags and commits down to the tree they point at.
+pub fn peel_to_tree(sha1: &str) -> Result<String> {
+    peel(sha1, "tree")
+}
+
+/// Checks a full ref name like `refs/tags/v1.0` against git's naming rules.
+pub fn check_ref_format(name: &str) -> Result<()> {
+    let bad = name.is_empty()
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.ends_with(".lock")
+        || name.starts_with('/')
+        || name.contains("..")
+        || name.contains("//")
+        || name.contains("@{")
+        || name == "@"
+        || name.split('/').any(|component| component.starts_with('.'))
+        || name
+            .chars()
+            .any(|x| x.is_ascii_control() || " ~^:?*[\\".contains(x));
+    if bad {
+        return Err(format!("'{}' is not a valid ref name", name).into());
+    }
+    Ok(())
+}
+
+/// Points the ref `name` (e.g. `refs/tags/v1.0`) at `sha1`.
+pub fn write_ref(name: &str, sha1: &str) -> Result<()> {
+    check_ref_format(name)?;
+    write_ref_file(name, &format!("{}\n", sha1))
+}
+
+fn write_ref_file(name: &str, content: &str) -> Result<()> {
+    let path = Path::new(".git").join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // write beside the ref and rename, so readers never see a half written file;
+    // creating the lock fails while another writer holds it
+    let lock_path = path.with_file_name(format!(
+        "{}.lock",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+    let mut lock = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+    {
+        Ok(lock) => lock,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(format!(
+                "cannot lock ref '{}': Unable to create '{}': File exists. Another git process seems to be running in this repository",
+                name,
+                lock_path.display()
+            )
+            .into())
+        }
+        Err(e) => return Err(format!("{}: {}", lock_path.display(), e).into()),
+    };
+    if let Err(e) = lock.write_all(content.as_bytes()) {
+        let _ = fs::remove_file(&lock_path);
+        return Err(e.into());
+    }
+    fs::rename(lock_path, path)?;
+    Ok(())
+}
+
+fn append_reflog(
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    committer: &Signature,
+    message: &str,
+) -> Result<()> {
+    let path = Path::new(".git/logs").join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let message = message.replace('\n', " ");
+    let mut line = format!("{} {} ", old.unwrap_or(ZERO_SHA1), new).into_bytes();
+    line.extend(committer.to_bytes());
+    line.extend(format!("\t{}\n", message.trim_end()).as_bytes());
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)?;
+    Ok(())
+}
+
+/// Points `name` at `sha1` like [`write_ref`], recording the change in the ref's
+/// log under `.git/logs`. Updating `HEAD` moves the branch it points at, and
+/// moving the checked out branch is logged for `HEAD` as well.
+pub fn update_ref(name: &str, sha1: &str, message: &str, committer: &Signature) -> Result<()> {
+    let head = symbolic_ref("HEAD")?;
+    let name = match (name, &head) {
+        ("HEAD", Some(branch)) => branch.as_str(),
+        _ => name,
+    };
+
+    let old = read_ref(name)?;
+    write_ref(name, sha1)?;
+    append_reflog(name, old.as_deref(), sha1, committer, message)?;
+    if name != "HEAD" && head.as_deref() == Some(name) {
+        append_reflog("HEAD", old.as_deref(), sha1, committer, message)?;
+    }
+    Ok(())
+}
+
+/// Makes `HEAD` a symbolic ref to the branch `target` (e.g. `refs/heads/main`),
+/// or detaches it at `target` when that is an object id, and logs the move.
+pub fn update_head(target: &str, message: &str, committer: &Signature) -> Result<()> {
+    let old = read_ref("HEAD")?;
+    let content = match is_hex_sha1(target) {
+        true => format!("{}\n", target),
+        false => {
+            check_ref_format(target)?;
+            format!("ref: {}\n", target)
+        }
+    };
+    write_ref_file("HEAD", &content)?;
+
+    // a branch without commits yet has nothing to log
+    if let Some(new) = read_ref("HEAD")? {
+        append_reflog("HEAD", old.as_deref(), &new, committer, message)?;
+    }
+    Ok(())
+}
+
+/// The names of all refs under `prefix` (e.g. `refs/tags/`), loose or packed, sorted.
+pub fn list_refs(prefix: &str) -> Result<Vec<String>> {
+    fn walk(dir: &Path, name: &str, refs: &mut Vec<String>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let child = format!("{}{}", name, file_name);
+            if entry.file_type()?.is_dir() {
+                walk(&entry.path(), &format!("{}/", child), refs)?;
+            } else if !file_name.ends_with(".lock") {
+                refs.push(child);
+            }
+        }
+        Ok(())
+    }
+
+    let mut refs = Vec::new();
+    let dir = Path::new(".git").join(prefix);
+    if dir.is_dir() {
+        walk(&dir, prefix, &mut refs)?;
+    }
+
+    if let Ok(packed) = fs::read_to_string(".git/packed-refs") {
+        for line in packed.lines() {
+            if let Some((_, name)) = line.split_once(' ') {
+                if name.starts_with(prefix) && !line.starts_with('#') && !line.starts_with('^') {
+                    refs.push(name.to_string());
+                }
+            }
+        }
+    }
+
+    refs.sort();
+    refs.dedup();
+    Ok(refs)
+}
+
+/// Turns a revision such as `HEAD`, `master`, a full object id or
+/// `HEAD:src/main.rs` into the object id it names.
+pub fn rev_parse(spec: &str) -> Result<String> {
+    let not_valid = || -> Error { format!("Not a valid object name {}", spec).into() };
+
+    let (rev, path) = match spec.find(':') {
+        Some(index) => (&spec[..index], Some(&spec[index + 1..])),
+        None => (spec, None),
+    };
+    let sha1 = resolve_name(rev)?.ok_or_else(not_valid)?;
+    let path = match path {
+        Some(path) => path.trim_matches('/'),
+        None => return Ok(sha1),
+    };
+
+    let mut sha1 = peel_to_tree(&sha1)?;
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        let tree = Object::open(&sha1)?;
+        if !matches!(tree.object_type, ObjectType::Tree) {
+            return Err(format!("path '{}' does not exist in '{}'", path, rev).into());
+        }
+        let entry = Tree::parse_entries(tree.body())?
+            .into_iter()
+            .find(|entry| entry.filename.as_os_str() == component)
+            .ok_or_else(|| format!("path '{}' does not exist in '{}'", path, rev))?;
+        sha1 = String::from_utf8(entry.sha1.to_vec())?;
+    }
+
+    Ok(sha1)
+}
@@ -0,0 +1,197 @@
+use std::env;
+use std::fmt::Display;
+
+use crate::config::Config;
+use crate::date;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The `name <email> time offset` identity found on author, committer and
+/// tagger lines. The name and email are kept as the bytes they were found as,
+/// which need not be UTF-8 in a commit with an `encoding` header.
+#[derive(Clone)]
+pub struct Signature {
+    pub name: Vec<u8>,
+    pub email: Vec<u8>,
+    pub time: i64,
+    pub offset: String,
+    /// What stood between the name and `<`, and between `>` and the time, so
+    /// a parsed identity is written back byte for byte. A space each otherwise.
+    pub spacing: [Vec<u8>; 2],
+}
+
+impl Signature {
+    pub fn now(name: &str, email: &str) -> Self {
+        let time = date::now();
+        Signature {
+            name: name.as_bytes().to_vec(),
+            email: email.as_bytes().to_vec(),
+            time,
+            offset: date::format_offset(date::local_offset(time)),
+            spacing: [b" ".to_vec(), b" ".to_vec()],
+        }
+    }
+
+    /// The author of new commits, from `GIT_AUTHOR_NAME`, `GIT_AUTHOR_EMAIL` and
+    /// `GIT_AUTHOR_DATE`, falling back to `author.*` and then `user.*` config.
+    pub fn author() -> Result<Self> {
+        Signature::identity("author")
+    }
+
+    /// The committer (and tagger) of new objects, resolved like [`Signature::author`]
+    /// from `GIT_COMMITTER_*` and `committer.*`.
+    pub fn committer() -> Result<Self> {
+        Signature::identity("committer")
+    }
+
+    fn identity(role: &str) -> Result<Self> {
+        let config = Config::cached()?;
+        let var = |field: &str| env::var(format!("GIT_{}_{}", role.to_uppercase(), field)).ok();
+
+        let name = var("NAME")
+            .or_else(|| config.get(&format!("{}.name", role)))
+            .or_else(|| config.get("user.name"));
+        let email = var("EMAIL")
+            .or_else(|| config.get(&format!("{}.email", role)))
+            .or_else(|| config.get("user.email"))
+            .or_else(|| env::var("EMAIL").ok());
+
+        let (name, email) = match (name, email) {
+            (Some(name), Some(email)) => (Signature::sanitize(&name), Signature::sanitize(&email)),
+            (name, _) => {
+                eprintln!(
+                    "{}{} identity unknown\n\n\
+                     *** Please tell me who you are.\n\n\
+                     Run\n\n  \
+                     git config --global user.email \"you@example.com\"\n  \
+                     git config --global user.name \"Your Name\"\n\n\
+                     to set your account's default identity.\n\
+                     Omit --global to set the identity only in this repository.\n",
+                    role[..1].to_uppercase(),
+                    &role[1..]
+                );
+                let missing = if name.is_none() {
+                    "name"
+                } else {
+                    "email address"
+                };
+                return Err(format!("unable to auto-detect {}", missing).into());
+            }
+        };
+        if name.is_empty() {
+            return Err(format!("empty ident name (for <{}>) not allowed", email).into());
+        }
+
+        let mut signature = Signature::now(&name, &email);
+        signature.date_from_env(role)?;
+        Ok(signature)
+    }
+
+    /// An identity given as `Name <email>`, e.g. to `commit --author`, dated from
+    /// `GIT_<ROLE>_DATE` when that is set.
+    pub fn from_ident(ident: &str, role: &str) -> Result<Self> {
+        let bad = || -> Error { format!("'{}' is not 'Name <email>'", ident).into() };
+        let (name, email) = ident.split_once('<').ok_or_else(bad)?;
+        let email = email.trim_end().strip_suffix('>').ok_or_else(bad)?;
+
+        let mut signature = Signature::now(&Signature::sanitize(name), &Signature::sanitize(email));
+        signature.date_from_env(role)?;
+        Ok(signature)
+    }
+
+    fn date_from_env(&mut self, role: &str) -> Result<()> {
+        if let Ok(date) = env::var(format!("GIT_{}_DATE", role.to_uppercase())) {
+            let (time, offset) = date::parse_date(&date)?;
+            self.time = time;
+            self.offset = date::format_offset(offset);
+        }
+        Ok(())
+    }
+
+    /// Drops the characters that would break the `name <email>` form and the
+    /// punctuation git trims from either end.
+    fn sanitize(value: &str) -> String {
+        value
+            .chars()
+            .filter(|x| !matches!(x, '<' | '>' | '\n'))
+            .collect::<String>()
+            .trim_matches(|x: char| x.is_whitespace() || ".,:;\"\\'".contains(x))
+            .to_string()
+    }
+
+    pub fn parse(line: &[u8]) -> Result<Self> {
+        let bad =
+            || -> Error { format!("bad identity {:?}", String::from_utf8_lossy(line)).into() };
+
+        // the name may be empty, and only the date has to be text
+        let open = line.iter().position(|x| *x == b'<').ok_or_else(bad)?;
+        let close = line[open..]
+            .iter()
+            .position(|x| *x == b'>')
+            .ok_or_else(bad)?
+            + open;
+        let date = std::str::from_utf8(&line[close + 1..]).map_err(|_| bad())?;
+        let (time, offset) = date.trim_start().split_once(' ').ok_or_else(bad)?;
+
+        let name = line[..open].trim_ascii_end();
+        Ok(Signature {
+            name: name.to_vec(),
+            email: line[open + 1..close].to_vec(),
+            time: time.parse().map_err(|_| bad())?,
+            offset: offset.to_string(),
+            spacing: [
+                line[name.len()..open].to_vec(),
+                line[close + 1..line.len() - date.trim_start().len()].to_vec(),
+            ],
+        })
+    }
+
+    /// The identity as it is written into objects and reflogs.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.name.clone();
+        bytes.extend(&self.spacing[0]);
+        bytes.push(b'<');
+        bytes.extend(&self.email);
+        bytes.push(b'>');
+        bytes.extend(&self.spacing[1]);
+        bytes.extend(format!("{} {}", self.time, self.offset).as_bytes());
+        bytes
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.to_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_idents() {
+        for ident in [
+            &b"A U Thor <a@x> 1700000000 +0100"[..],
+            b"Name<e> 1 +0000",
+            b"Name  <e> 1 +0000",
+            b"<e> 1 +0000",
+            b" <e>  1 -0130",
+            b"N\xe9 <\xe9@x> 1 +0000",
+        ] {
+            assert_eq!(Signature::parse(ident).unwrap().to_bytes(), ident);
+        }
+    }
+
+    #[test]
+    fn parses_fields() {
+        let signature = Signature::parse(b"Name  <e@x>  1700000000 -0130").unwrap();
+        assert_eq!(signature.name, b"Name");
+        assert_eq!(signature.email, b"e@x");
+        assert_eq!(signature.time, 1700000000);
+        assert_eq!(signature.offset, "-0130");
+        assert!(Signature::parse(b"Name e@x 1 +0000").is_err());
+        assert!(Signature::parse(b"Name <e@x>").is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::args::{Add, Mv, Rm};
+use crate::index::{self, Index, IndexEntry};
+use crate::refs;
+use crate::tree::Tree;
+use crate::utils::{self, Ignore};
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The `add`, `rm` and `mv` commands, which change the working tree and the
+/// index together.
+pub struct Staging {}
+
+impl Staging {
+    pub fn add(args: &Add) -> Result<()> {
+        if args.pathspec.is_empty() && !args.all && !args.update {
+            eprintln!("hint: Maybe you wanted to say 'git add .'?");
+            return Err("Nothing specified, nothing added.".into());
+        }
+        let specs: Vec<String> = match args.pathspec.is_empty() {
+            true => vec![String::new()],
+            false => args
+                .pathspec
+                .iter()
+                .map(|spec| utils::normalize_pathspec(spec))
+                .collect(),
+        };
+
+        let mut index = Index::read()?;
+        let mut ignore = Ignore::load()?;
+        let mut matched: HashSet<&str> = HashSet::new();
+        let mut changes: Vec<(String, Option<IndexEntry>)> = Vec::new();
+
+        // tracked paths get their new content staged, or are dropped when deleted
+        let mut previous: Option<&str> = None;
+        for entry in &index.entries {
+            if previous == Some(entry.path.as_str()) {
+                continue;
+            }
+            previous = Some(&entry.path);
+            let spec = match specs
+                .iter()
+                .find(|spec| utils::pathspec_matches(spec, &entry.path))
+            {
+                Some(spec) => spec,
+                None => continue,
+            };
+            matched.insert(spec);
+            if entry.skip_worktree || entry.mode == 0o160000 {
+                continue;
+            }
+
+            match fs::symlink_metadata(&entry.path) {
+                Ok(metadata) if !metadata.is_dir() => {
+                    let unchanged = entry.stage == 0
+                        && !entry.intent_to_add
+                        && index.worktree_matches(entry, &metadata)?;
+                    if !unchanged {
+                        let sha1 =
+                            index::hash_worktree_file(&entry.path, &metadata, !args.dry_run)?;
+                        let staged = IndexEntry::from_metadata(&entry.path, &sha1, &metadata);
+                        changes.push((entry.path.clone(), Some(staged)));
+                    }
+                }
+                _ => changes.push((entry.path.clone(), None)),
+            }
+        }
+
+        // and new files are picked up unless they are ignored
+        let mut ignored_specs: Vec<(&str, String)> = Vec::new();
+        if !args.update {
+            for (path, ignored) in utils::walk_worktree(&mut ignore, args.force)? {
+                if index.is_tracked(&path) {
+                    continue;
+                }
+                // an ignored directory also stands for the specs naming files in it
+                let spec = match specs.iter().find(|spec| match path.strip_suffix('/') {
+                    Some(dir) => utils::pathspec_matches(spec, dir) || spec.starts_with(&path),
+                    None => utils::pathspec_matches(spec, &path),
+                }) {
+                    Some(spec) => spec.as_str(),
+                    None => continue,
+                };
+                if ignored && !args.force {
+                    // a spec inside an ignored directory is reported as the directory
+                    let shown = match path.strip_suffix('/') {
+                        Some(dir) if spec.starts_with(&path) => dir.to_string(),
+                        _ => spec.to_string(),
+                    };
+                    if !spec.is_empty() && !ignored_specs.iter().any(|(x, _)| *x == spec) {
+                        ignored_specs.push((spec, shown));
+                    }
+                    continue;
+                }
+                matched.insert(spec);
+
+                let metadata = fs::symlink_metadata(&path)?;
+                let sha1 = index::hash_worktree_file(&path, &metadata, !args.dry_run)?;
+                changes.push((
+                    path.clone(),
+                    Some(IndexEntry::from_metadata(&path, &sha1, &metadata)),
+                ));
+            }
+        }
+
+        for spec in &specs {
+            if !matched.contains(spec.as_str()) && !ignored_specs.iter().any(|(x, _)| x == spec) {
+                return Err(format!("pathspec '{}' did not match any files", spec).into());
+            }
+        }
+
+        changes.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        for (path, entry) in changes {
+            if args.dry_run || args.verbose {
+                match entry {
+                    Some(_) => println!("add '{}'", path),
+                    None => println!("remove '{}'", path),
+                }
+            }
+            match entry {
+                Some(entry) => index.add(entry),
+                None => index.remove(&path),
+            }
+        }
+        if !args.dry_run {
+            index.write()?;
+        }
+
+        // specs that only named ignored files are refused after adding the rest
+        ignored_specs.retain(|(spec, _)| !matched.contains(spec));
+        if !ignored_specs.is_empty() {
+            eprintln!("The following paths are ignored by one of your .gitignore files:");
+            for (_, shown) in &ignored_specs {
+                eprintln!("{}", shown);
+            }
+            eprintln!("hint: Use -f if you really want to add them.");
+            return Err("ignored paths were not added".into());
+        }
+
+        Ok(())
+    }
+
+    pub fn rm(args: &Rm) -> Result<()> {
+        let mut index = Index::read()?;
+        let specs: Vec<String> = args
+            .pathspec
+            .iter()
+            .map(|spec| utils::normalize_pathspec(spec))
+            .collect();
+
+        let mut paths: Vec<String> = Vec::new();
+        for spec in &specs {
+            let matching: Vec<&IndexEntry> = index
+                .entries
+                .iter()
+                .filter(|entry| utils::pathspec_matches(spec, &entry.path))
+                .collect();
+            if matching.is_empty() {
+                return Err(format!("pathspec '{}' did not match any files", spec).into());
+            }
+            if !args.recursive
+                && matching.iter().all(|entry| entry.path != *spec)
+                && !spec.contains(['*', '?', '['])
+            {
+                return Err(format!("not removing '{}' recursively without -r", spec).into());
+            }
+            paths.extend(matching.iter().map(|entry| entry.path.clone()));
+        }
+        paths.sort();
+        paths.dedup();
+
+        if !args.force {
+            Staging::check_removable(&index, &paths, args.cached)?;
+        }
+
+        for path in &paths {
+            if !args.quiet {
+                println!("rm '{}'", path);
+            }
+            if args.dry_run {
+                continue;
+            }
+            index.remove(path);
+            if !args.cached {
+                Staging::remove_worktree_file(path)?;
+            }
+        }
+        if !args.dry_run {
+            index.write()?;
+        }
+
+        Ok(())
+    }
+
+    /// Refuses to lose work: staged content that isn't in HEAD, and (unless
+    /// only the index entry goes) working tree changes that aren't staged.
+    fn check_removable(index: &Index, paths: &[String], cached: bool) -> Result<()> {
+        let head: HashMap<String, (u32, String)> = match refs::read_ref("HEAD")? {
+            Some(head) => Tree::flatten(&refs::peel_to_tree(&head)?)?
+                .into_iter()
+                .map(|(path, mode, sha1)| (path, (mode, sha1)))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut both = Vec::new();
+        let mut staged = Vec::new();
+        let mut local = Vec::new();
+        for path in paths {
+            // conflicted paths can always be removed
+            let entry = match index.find(path) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let staged_changes = head.get(path) != Some(&(entry.mode, entry.sha1.clone()));
+            let local_changes = match fs::symlink_metadata(path) {
+                Ok(metadata) if !metadata.is_dir() => !index.worktree_matches(entry, &metadata)?,
+                _ => false,
+            };
+
+            match (staged_changes, local_changes) {
+                (true, true) => both.push(path),
+                (true, false) if !cached => staged.push(path),
+                (false, true) if !cached => local.push(path),
+                _ => {}
+            }
+        }
+
+        let report = |paths: &[&String], what: &str, hint: &str| {
+            if paths.is_empty() {
+                return;
+            }
+            let (noun, verb) = match paths.len() {
+                1 => ("file", "has"),
+                _ => ("files", "have"),
+            };
+            eprintln!("error: the following {} {} {}:", noun, verb, what);
+            for path in paths {
+                eprintln!("    {}", path);
+            }
+            eprintln!("{}", hint);
+        };
+        report(
+            &both,
+            "staged content different from both the\nfile and the HEAD",
+            "(use -f to force removal)",
+        );
+        report(
+            &staged,
+            "changes staged in the index",
+            "(use --cached to keep the file, or -f to force removal)",
+        );
+        report(
+            &local,
+            "local modifications",
+            "(use --cached to keep the file, or -f to force removal)",
+        );
+
+        if both.is_empty() && staged.is_empty() && local.is_empty() {
+            Ok(())
+        } else {
+            Err("not removing files with uncommitted changes".into())
+        }
+    }
+
+    /// Deletes a file and then any directories it leaves empty.
+    fn remove_worktree_file(path: &str) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut dir = Path::new(path).parent();
+        while let Some(parent) = dir {
+            if parent.as_os_str().is_empty() || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+
+    pub fn mv(args: &Mv) -> Result<()> {
+        let (sources, destination) = args.paths.split_at(args.paths.len() - 1);
+        let destination = utils::normalize_pathspec(&destination[0]);
+        // the top of the work tree normalizes to ""
+        let into_dir = destination.is_empty() || Path::new(&destination).is_dir();
+        if sources.len() > 1 && !into_dir {
+            return Err(format!("destination '{}' is not a directory", destination).into());
+        }
+
+        let mut index = Index::read()?;
+        let mut moves: Vec<(String, String)> = Vec::new();
+        for source in sources {
+            let source = utils::normalize_pathspec(source);
+            let target = match (into_dir, source.rsplit('/').next()) {
+                (true, Some(name)) if destination.is_empty() => name.to_string(),
+                (true, Some(name)) => format!("{}/{}", destination, name),
+                _ => destination.clone(),
+            };
+
+            let source_path = Path::new(&source);
+            let tracked = index
+                .entries
+                .iter()
+                .any(|entry| utils::pathspec_matches(&source, &entry.path));
+            let error = if source.is_empty() || fs::symlink_metadata(source_path).is_err() {
+                Some("bad source")
+            } else if source == target
+                || (source_path.is_dir() && utils::pathspec_matches(&source, &target))
+            {
+                Some("can not move directory into itself")
+            } else if !tracked {
+                Some("not under version control")
+            } else if fs::symlink_metadata(&target).is_ok()
+                && (!args.force || Path::new(&target).is_dir())
+            {
+                Some("destination exists")
+            } else if moves.iter().any(|(_, other)| *other == target) {
+                Some("multiple sources for the same target")
+            } else {
+                None
+            };
+
+            match error {
+                Some(_) if args.skip_errors => continue,
+                Some(error) => {
+                    return Err(
+                        format!("{}, source={}, destination={}", error, source, target).into(),
+                    )
+                }
+                None => moves.push((source, target)),
+            }
+        }
+
+        for (source, target) in moves {
+            if args.dry_run {
+                println!("Checking rename of '{}' to '{}'", source, target);
+            }
+            if args.dry_run || args.verbose {
+                println!("Renaming {} to {}", source, target);
+            }
+            if args.dry_run {
+                continue;
+            }
+
+            fs::rename(&source, &target)?;
+            let moved: Vec<IndexEntry> = index
+                .entries
+                .iter()
+                .filter(|entry| utils::pathspec_matches(&source, &entry.path))
+                .cloned()
+                .collect();
+            index.remove(&target);
+            for mut entry in moved {
+                index.remove(&entry.path);
+                entry.path = format!("{}{}", target, &entry.path[source.len()..]);
+                index.add(entry);
+            }
+        }
+        if !args.dry_run {
+            index.write()?;
+        }
+
+        Ok(())
+    }
+}
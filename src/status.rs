@@ -0,0 +1,1022 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+
+use crate::args::Status as StatusArgs;
+use crate::commit::Commit;
+use crate::config::Config;
+use crate::index::{Index, IndexEntry};
+use crate::object::Object;
+use crate::refs::{self, ZERO_SHA1};
+use crate::tree::Tree;
+use crate::utils::{self, Ignore};
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+// similarity scores as diffcore-rename keeps them, where 50% makes a rename
+const MAX_SCORE: u64 = 60000;
+const MIN_SCORE: u64 = 30000;
+
+/// A path whose HEAD, index and working tree versions don't all agree.
+struct Change {
+    path: String,
+    /// Where a staged rename came from, and how similar the two are in percent.
+    renamed_from: Option<(String, u64)>,
+    /// The staged and unstaged status letters, `' '` when unchanged.
+    staged: char,
+    unstaged: char,
+    head: Option<(u32, String)>,
+    index: Option<(u32, String)>,
+    /// The base, ours and theirs stages of a conflict.
+    stages: Option<[Option<(u32, String)>; 3]>,
+    /// 0 when the file is gone from the working tree.
+    worktree_mode: u32,
+}
+
+/// The branch a branch tracks, by its short name like `origin/main`.
+struct Upstream {
+    name: String,
+    /// How many commits the branch is ahead and behind, `None` when the
+    /// upstream branch is gone.
+    ahead_behind: Option<(usize, usize)>,
+}
+
+impl Upstream {
+    /// How the branch compares to its upstream, in the words of `status`.
+    fn print(&self, hints: bool) {
+        let hint = |text: &str| {
+            if hints {
+                println!("  ({})", text);
+            }
+        };
+        let commits = |count: usize| match count {
+            1 => "1 commit".to_string(),
+            count => format!("{} commits", count),
+        };
+        let name = &self.name;
+        match &self.ahead_behind {
+            None => {
+                println!(
+                    "Your branch is based on '{}', but the upstream is gone.",
+                    name
+                );
+                hint("use \"git branch --unset-upstream\" to fixup");
+            }
+            Some((0, 0)) => println!("Your branch is up to date with '{}'.", name),
+            Some((ahead, 0)) => {
+                println!("Your branch is ahead of '{}' by {}.", name, commits(*ahead));
+                hint("use \"git push\" to publish your local commits");
+            }
+            Some((0, behind)) => {
+                println!(
+                    "Your branch is behind '{}' by {}, and can be fast-forwarded.",
+                    name,
+                    commits(*behind)
+                );
+                hint("use \"git pull\" to update your local branch");
+            }
+            Some((ahead, behind)) => {
+                println!("Your branch and '{}' have diverged,", name);
+                println!(
+                    "and have {} and {} different commits each, respectively.",
+                    ahead, behind
+                );
+                hint("use \"git pull\" to merge the remote branch into yours");
+            }
+        }
+    }
+}
+
+enum Format {
+    Long,
+    Short,
+    PorcelainV2,
+}
+
+/// What `status` reports: the branch, the changes between HEAD, the index and
+/// the working tree, and the files git doesn't track.
+pub struct Status {
+    branch: Option<String>,
+    head: Option<String>,
+    upstream: Option<Upstream>,
+    changes: Vec<Change>,
+    untracked: Vec<String>,
+    ignored: Vec<String>,
+}
+
+impl Status {
+    pub fn status(args: &StatusArgs) -> Result<()> {
+        let untracked_mode = match &args.untracked_files {
+            Some(Some(mode)) => mode.clone(),
+            Some(None) => "all".to_string(),
+            None => Config::cached()?
+                .get("status.showUntrackedFiles")
+                .unwrap_or_else(|| "normal".to_string()),
+        };
+        if !["no", "normal", "all"].contains(&untracked_mode.as_str()) {
+            return Err(format!("Invalid untracked files mode '{}'", untracked_mode).into());
+        }
+
+        let format = match (&args.porcelain, args.short) {
+            (Some(Some(version)), _) => match version.as_str() {
+                "v1" | "1" => Format::Short,
+                "v2" | "2" => Format::PorcelainV2,
+                _ => return Err(format!("unsupported porcelain version '{}'", version).into()),
+            },
+            (Some(None), _) | (None, true) => Format::Short,
+            // -z on its own asks for the porcelain format
+            (None, false) if args.null_terminated => Format::Short,
+            (None, false) => Format::Long,
+        };
+
+        let specs: Vec<String> = match args.pathspec.is_empty() {
+            true => vec![String::new()],
+            false => args
+                .pathspec
+                .iter()
+                .map(|spec| utils::normalize_pathspec(spec))
+                .collect(),
+        };
+        let status = Status::collect(&specs, &untracked_mode, args.ignored, !args.no_renames)?;
+
+        match format {
+            Format::Long => status.print_long(&untracked_mode),
+            Format::Short => status.print_short(args.branch, args.null_terminated),
+            Format::PorcelainV2 => status.print_porcelain_v2(args.branch, args.null_terminated),
+        }
+    }
+
+    /// Tells how `branch` compares to its upstream, as `switch` does after
+    /// moving to it.
+    pub fn report_tracking(branch: &str) -> Result<()> {
+        let head = refs::read_ref(&format!("refs/heads/{}", branch))?;
+        if let Some(upstream) = Status::upstream(branch, head.as_deref())? {
+            let hints = Config::cached()?
+                .get_bool("advice.statusHints")?
+                .unwrap_or(true);
+            upstream.print(hints);
+        }
+        Ok(())
+    }
+
+    /// Lists the paths where the working tree differs from HEAD, with a status
+    /// letter, as `switch` does for the changes it carried along.
+    pub fn show_local_changes() -> Result<()> {
+        let status = Status::collect(&[String::new()], "no", false, false)?;
+        for change in &status.changes {
+            let letter = match change {
+                Change {
+                    stages: Some(_), ..
+                } => 'M',
+                Change { head: None, .. } => 'A',
+                Change { staged: 'D', .. } | Change { unstaged: 'D', .. } => 'D',
+                Change { staged: 'T', .. } | Change { unstaged: 'T', .. } => 'T',
+                _ => 'M',
+            };
+            println!("{}\t{}", letter, utils::quote_path(&change.path)?);
+        }
+        Ok(())
+    }
+
+    fn collect(
+        specs: &[String],
+        untracked_mode: &str,
+        show_ignored: bool,
+        renames: bool,
+    ) -> Result<Self> {
+        let branch = refs::symbolic_ref("HEAD")?.map(|name| {
+            name.strip_prefix("refs/heads/")
+                .unwrap_or(&name)
+                .to_string()
+        });
+        let head = refs::read_ref("HEAD")?;
+        let upstream = match &branch {
+            Some(branch) => Status::upstream(branch, head.as_deref())?,
+            None => None,
+        };
+
+        let head_entries: BTreeMap<String, (u32, String)> = match &head {
+            Some(head) => Tree::flatten(&refs::peel_to_tree(head)?)?
+                .into_iter()
+                .map(|(path, mode, sha1)| (path, (mode, sha1)))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let index = Index::read()?;
+        let matches = |path: &str| specs.iter().any(|spec| utils::pathspec_matches(spec, path));
+
+        let mut changes = Vec::new();
+        let mut i = 0;
+        while i < index.entries.len() {
+            let path = &index.entries[i].path;
+            let count = index.entries[i..]
+                .iter()
+                .take_while(|entry| entry.path == *path)
+                .count();
+            let entries = &index.entries[i..i + count];
+            i += count;
+            if !matches(path) {
+                continue;
+            }
+
+            let head = head_entries.get(path).cloned();
+            let change = match entries[0].stage {
+                0 => Status::compare(&index, &entries[0], head)?,
+                _ => Some(Status::conflict(entries, head)),
+            };
+            changes.extend(change);
+        }
+        for (path, head) in &head_entries {
+            if !index.is_tracked(path) && matches(path) {
+                changes.push(Change {
+                    path: path.clone(),
+                    renamed_from: None,
+                    staged: 'D',
+                    unstaged: ' ',
+                    head: Some(head.clone()),
+                    index: None,
+                    stages: None,
+                    worktree_mode: 0,
+                });
+            }
+        }
+        if renames {
+            Status::detect_renames(&mut changes)?;
+        }
+        changes.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+        let (untracked, ignored) = match untracked_mode {
+            "no" => (Vec::new(), Vec::new()),
+            mode => Status::untracked(&index, specs, mode == "all", show_ignored)?,
+        };
+
+        Ok(Status {
+            branch,
+            head,
+            upstream,
+            changes,
+            untracked,
+            ignored,
+        })
+    }
+
+    /// The change recorded for a merged index entry, if it differs from HEAD
+    /// or from the working tree.
+    fn compare(
+        index: &Index,
+        entry: &IndexEntry,
+        head: Option<(u32, String)>,
+    ) -> Result<Option<Change>> {
+        let staged = match &head {
+            // an intent-to-add entry only shows up as a working tree change
+            _ if entry.intent_to_add => ' ',
+            None => 'A',
+            Some((mode, sha1)) if *mode == entry.mode && *sha1 == entry.sha1 => ' ',
+            Some((mode, _)) if mode & 0o170000 != entry.mode & 0o170000 => 'T',
+            Some(_) => 'M',
+        };
+
+        let mut worktree_mode = entry.mode;
+        let unstaged = if entry.skip_worktree || entry.assume_valid || entry.mode == 0o160000 {
+            ' '
+        } else {
+            match fs::symlink_metadata(&entry.path) {
+                Ok(metadata) if !metadata.is_dir() => {
+                    let fresh = IndexEntry::from_metadata(&entry.path, &entry.sha1, &metadata);
+                    worktree_mode = fresh.mode;
+                    if entry.intent_to_add {
+                        'A'
+                    } else if fresh.mode & 0o170000 != entry.mode & 0o170000 {
+                        'T'
+                    } else if !index.worktree_matches(entry, &metadata)? {
+                        'M'
+                    } else {
+                        ' '
+                    }
+                }
+                _ => {
+                    worktree_mode = 0;
+                    'D'
+                }
+            }
+        };
+
+        if staged == ' ' && unstaged == ' ' {
+            return Ok(None);
+        }
+        Ok(Some(Change {
+            path: entry.path.clone(),
+            renamed_from: None,
+            staged,
+            unstaged,
+            head,
+            // an intent-to-add entry has no staged content to show
+            index: match entry.intent_to_add {
+                true => None,
+                false => Some((entry.mode, entry.sha1.clone())),
+            },
+            stages: None,
+            worktree_mode,
+        }))
+    }
+
+    /// The change for a conflicted path, lettered by which stages it has.
+    fn conflict(entries: &[IndexEntry], head: Option<(u32, String)>) -> Change {
+        let mut stages: [Option<(u32, String)>; 3] = [None, None, None];
+        for entry in entries {
+            stages[entry.stage as usize - 1] = Some((entry.mode, entry.sha1.clone()));
+        }
+        let (staged, unstaged) = match (
+            stages[0].is_some(),
+            stages[1].is_some(),
+            stages[2].is_some(),
+        ) {
+            (true, false, false) => ('D', 'D'),
+            (false, true, false) => ('A', 'U'),
+            (true, true, false) => ('U', 'D'),
+            (false, false, true) => ('U', 'A'),
+            (true, false, true) => ('D', 'U'),
+            (false, true, true) => ('A', 'A'),
+            _ => ('U', 'U'),
+        };
+        let worktree_mode = match fs::symlink_metadata(&entries[0].path) {
+            Ok(metadata) if !metadata.is_dir() => {
+                IndexEntry::from_metadata(&entries[0].path, "", &metadata).mode
+            }
+            _ => 0,
+        };
+
+        Change {
+            path: entries[0].path.clone(),
+            renamed_from: None,
+            staged,
+            unstaged,
+            head,
+            index: None,
+            stages: Some(stages),
+            worktree_mode,
+        }
+    }
+
+    /// Pairs staged deletions with staged additions: identical content first,
+    /// then the most similar files that are at least half the same.
+    fn detect_renames(changes: &mut Vec<Change>) -> Result<()> {
+        let sources: Vec<usize> = (0..changes.len())
+            .filter(|i| changes[*i].staged == 'D' && changes[*i].stages.is_none())
+            .collect();
+        let targets: Vec<usize> = (0..changes.len())
+            .filter(|i| changes[*i].staged == 'A' && changes[*i].stages.is_none())
+            .collect();
+        if sources.is_empty() || targets.is_empty() {
+            return Ok(());
+        }
+
+        let file_type = |side: &Option<(u32, String)>| side.as_ref().map(|x| x.0 & 0o170000);
+        let same_name = |source: usize, target: usize| {
+            let name = |path: &str| path.rsplit('/').next().unwrap_or("").to_string();
+            name(&changes[source].path) == name(&changes[target].path)
+        };
+
+        let mut renames: Vec<(usize, usize, u64)> = Vec::new();
+        let mut used: HashSet<usize> = HashSet::new();
+        let mut unmatched: Vec<usize> = Vec::new();
+        for &target in &targets {
+            let candidates: Vec<usize> = sources
+                .iter()
+                .copied()
+                .filter(|source| {
+                    !used.contains(source) && changes[*source].head == changes[target].index
+                })
+                .collect();
+            let source = candidates
+                .iter()
+                .find(|source| same_name(**source, target))
+                .or_else(|| candidates.first());
+            match source {
+                Some(&source) => {
+                    used.insert(source);
+                    renames.push((source, target, MAX_SCORE));
+                }
+                None => unmatched.push(target),
+            }
+        }
+
+        let mut blobs: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut scored: Vec<(u64, bool, usize, usize)> = Vec::new();
+        for &target in &unmatched {
+            for &source in sources.iter().filter(|source| !used.contains(source)) {
+                if file_type(&changes[source].head) != file_type(&changes[target].index) {
+                    continue;
+                }
+                let mut load = |side: &Option<(u32, String)>| -> Result<Vec<u8>> {
+                    let sha1 = &side.as_ref().unwrap().1;
+                    if !blobs.contains_key(sha1) {
+                        blobs.insert(sha1.clone(), Object::open(sha1)?.body().to_vec());
+                    }
+                    Ok(blobs[sha1].clone())
+                };
+                let source_data = load(&changes[source].head)?;
+                let target_data = load(&changes[target].index)?;
+                let score = similarity(&source_data, &target_data);
+                if score >= MIN_SCORE {
+                    scored.push((score, same_name(source, target), source, target));
+                }
+            }
+        }
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+        let mut renamed: HashSet<usize> = HashSet::new();
+        for (score, _, source, target) in scored {
+            if !used.contains(&source) && !renamed.contains(&target) {
+                used.insert(source);
+                renamed.insert(target);
+                renames.push((source, target, score));
+            }
+        }
+
+        for (source, target, score) in &renames {
+            changes[*target].renamed_from =
+                Some((changes[*source].path.clone(), score * 100 / MAX_SCORE));
+            changes[*target].staged = 'R';
+            changes[*target].head = changes[*source].head.clone();
+        }
+        // the sources live on as the renames
+        let mut sources: Vec<usize> = used.into_iter().collect();
+        sources.sort_unstable();
+        for source in sources.into_iter().rev() {
+            changes.remove(source);
+        }
+        Ok(())
+    }
+
+    /// Untracked and ignored files. Unless all of them are wanted, a directory
+    /// without tracked files is shown instead of its contents, and so is one
+    /// holding nothing but ignored files.
+    fn untracked(
+        index: &Index,
+        specs: &[String],
+        all: bool,
+        show_ignored: bool,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let mut ignore = Ignore::load()?;
+        let files: Vec<(String, bool)> = utils::walk_worktree(&mut ignore, show_ignored && all)?
+            .into_iter()
+            .filter(|(path, _)| {
+                !index.is_tracked(path)
+                    && specs.iter().any(|spec| utils::pathspec_matches(spec, path))
+            })
+            .collect();
+
+        fn parents(path: &str) -> impl Iterator<Item = &str> {
+            path.match_indices('/').map(move |(i, _)| &path[..i])
+        }
+        let tracked_dirs: HashSet<&str> = index
+            .entries
+            .iter()
+            .flat_map(|entry| parents(&entry.path))
+            .collect();
+        let untracked_dirs: HashSet<&str> = files
+            .iter()
+            .filter(|(_, ignored)| !ignored)
+            .flat_map(|(path, _)| parents(path))
+            .collect();
+
+        let mut untracked = Vec::new();
+        let mut ignored = Vec::new();
+        for (path, is_ignored) in &files {
+            if *is_ignored && !show_ignored {
+                continue;
+            }
+            let dir = parents(path).find(|dir| {
+                let shows_contents =
+                    tracked_dirs.contains(dir) || (*is_ignored && untracked_dirs.contains(dir));
+                !all && !shows_contents
+            });
+            let shown = match dir {
+                Some(dir) => format!("{}/", dir),
+                None => path.clone(),
+            };
+            match is_ignored {
+                true => ignored.push(shown),
+                false => untracked.push(shown),
+            }
+        }
+        untracked.dedup();
+        ignored.dedup();
+        Ok((untracked, ignored))
+    }
+
+    /// The upstream configured for `branch`, with the commits on either side
+    /// when it still exists.
+    fn upstream(branch: &str, head: Option<&str>) -> Result<Option<Upstream>> {
+        let config = Config::cached()?;
+        let remote = config.get(&format!("branch.{}.remote", branch));
+        let merge = config.get(&format!("branch.{}.merge", branch));
+        let (remote, merge) = match (remote, merge) {
+            (Some(remote), Some(merge)) => (remote, merge),
+            _ => return Ok(None),
+        };
+
+        // a remote's branches are tracked wherever its fetch refspecs put them
+        let full_name = match remote.as_str() {
+            "." => merge,
+            remote => {
+                let mapped = config
+                    .get_all(&format!("remote.{}.fetch", remote))
+                    .iter()
+                    .find_map(|refspec| map_refspec(refspec, &merge));
+                match mapped {
+                    Some(full_name) => full_name,
+                    None => return Ok(None),
+                }
+            }
+        };
+        let name = ["refs/heads/", "refs/remotes/"]
+            .iter()
+            .find_map(|prefix| full_name.strip_prefix(prefix))
+            .unwrap_or(&full_name)
+            .to_string();
+        let ahead_behind = match (refs::read_ref(&full_name)?, head) {
+            (Some(upstream), Some(head)) => Some(Commit::ahead_behind(head, &upstream)?),
+            (Some(_), None) => Some((0, 0)),
+            (None, _) => None,
+        };
+        Ok(Some(Upstream { name, ahead_behind }))
+    }
+
+    /// What the detached HEAD was checked out as, from the last `checkout:
+    /// moving from X to Y` entry in its reflog, and whether it is still there.
+    fn detached_from(head: &str) -> Result<Option<(String, bool)>> {
+        let log = match fs::read_to_string(".git/logs/HEAD") {
+            Ok(log) => log,
+            Err(_) => return Ok(None),
+        };
+        let checkout = log.lines().rev().find_map(|line| {
+            let (entry, message) = line.split_once('\t')?;
+            let target = message.strip_prefix("checkout: moving from ")?;
+            let new = entry.split(' ').nth(1)?;
+            Some((new.to_string(), target.rsplit(" to ").next()?.to_string()))
+        });
+        let (new, target) = match checkout {
+            Some(checkout) => checkout,
+            None => return Ok(None),
+        };
+
+        // the ref it was checked out by, if that still names the same commit
+        let full_name = match refs::dwim_ref(&target)? {
+            Some(full_name) => match refs::read_ref(&full_name)? {
+                Some(sha1) if refs::peel(&sha1, "commit").ok() == Some(new.clone()) => {
+                    Some(full_name)
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        let name = match full_name {
+            Some(full_name) => ["refs/tags/", "refs/remotes/"]
+                .iter()
+                .find_map(|prefix| full_name.strip_prefix(prefix))
+                .unwrap_or(&full_name)
+                .to_string(),
+            None => Object::shortest_abbrev(&new)?,
+        };
+        Ok(Some((name, new == head)))
+    }
+
+    fn print_long(&self, untracked_mode: &str) -> Result<()> {
+        let hints = Config::cached()?
+            .get_bool("advice.statusHints")?
+            .unwrap_or(true);
+        let hint = |text: &str| {
+            if hints {
+                println!("  ({})", text);
+            }
+        };
+
+        match (&self.branch, &self.head) {
+            (Some(branch), _) => println!("On branch {}", branch),
+            (None, Some(head)) => match Status::detached_from(head)? {
+                Some((name, true)) => println!("HEAD detached at {}", name),
+                Some((name, false)) => println!("HEAD detached from {}", name),
+                None => println!("Not currently on any branch."),
+            },
+            (None, None) => println!("Not currently on any branch."),
+        }
+        if let Some(upstream) = &self.upstream {
+            upstream.print(hints);
+            println!();
+        }
+        if self.head.is_none() {
+            println!();
+            println!("No commits yet");
+            println!();
+        }
+
+        let unstage_hint = match self.head {
+            Some(_) => "use \"git restore --staged <file>...\" to unstage",
+            None => "use \"git rm --cached <file>...\" to unstage",
+        };
+        let staged: Vec<&Change> = self
+            .changes
+            .iter()
+            .filter(|change| change.stages.is_none() && change.staged != ' ')
+            .collect();
+        let unmerged: Vec<&Change> = self
+            .changes
+            .iter()
+            .filter(|change| change.stages.is_some())
+            .collect();
+        let unstaged: Vec<&Change> = self
+            .changes
+            .iter()
+            .filter(|change| change.stages.is_none() && change.unstaged != ' ')
+            .collect();
+
+        if !staged.is_empty() {
+            println!("Changes to be committed:");
+            hint(unstage_hint);
+            for change in &staged {
+                let path = match &change.renamed_from {
+                    Some((from, _)) => format!(
+                        "{} -> {}",
+                        utils::quote_path(from)?,
+                        utils::quote_path(&change.path)?
+                    ),
+                    None => utils::quote_path(&change.path)?,
+                };
+                println!("\t{:<12}{}", change_label(change.staged), path);
+            }
+            println!();
+        }
+
+        if !unmerged.is_empty() {
+            println!("Unmerged paths:");
+            hint(unstage_hint);
+            let deleted = |change: &&&Change| change.staged == 'D' || change.unstaged == 'D';
+            let both_deleted = unmerged
+                .iter()
+                .any(|change| change.staged == 'D' && change.unstaged == 'D');
+            let one_deleted = unmerged
+                .iter()
+                .filter(deleted)
+                .any(|change| change.staged != change.unstaged);
+            let none_deleted = unmerged.iter().any(|change| !deleted(&change));
+            if !both_deleted && !one_deleted {
+                hint("use \"git add <file>...\" to mark resolution");
+            } else if both_deleted && !one_deleted && !none_deleted {
+                hint("use \"git rm <file>...\" to mark resolution");
+            } else {
+                hint("use \"git add/rm <file>...\" as appropriate to mark resolution");
+            }
+            for change in &unmerged {
+                println!(
+                    "\t{:<17}{}",
+                    conflict_label(change.staged, change.unstaged),
+                    utils::quote_path(&change.path)?
+                );
+            }
+            println!();
+        }
+
+        if !unstaged.is_empty() {
+            println!("Changes not staged for commit:");
+            match unstaged.iter().any(|change| change.unstaged == 'D') {
+                true => hint("use \"git add/rm <file>...\" to update what will be committed"),
+                false => hint("use \"git add <file>...\" to update what will be committed"),
+            }
+            hint("use \"git restore <file>...\" to discard changes in working directory");
+            for change in &unstaged {
+                println!(
+                    "\t{:<12}{}",
+                    change_label(change.unstaged),
+                    utils::quote_path(&change.path)?
+                );
+            }
+            println!();
+        }
+
+        if untracked_mode == "no" {
+            if !staged.is_empty() {
+                match hints {
+                    true => println!(
+                        "Untracked files not listed (use -u option to show untracked files)"
+                    ),
+                    false => println!("Untracked files not listed"),
+                }
+            }
+        } else {
+            for (title, paths, what) in [
+                ("Untracked files:", &self.untracked, "git add <file>..."),
+                ("Ignored files:", &self.ignored, "git add -f <file>..."),
+            ] {
+                if paths.is_empty() {
+                    continue;
+                }
+                println!("{}", title);
+                hint(&format!(
+                    "use \"{}\" to include in what will be committed",
+                    what
+                ));
+                for path in paths {
+                    println!("\t{}", utils::quote_path(path)?);
+                }
+                println!();
+            }
+        }
+
+        if staged.is_empty() {
+            let (message, hint) = if !unstaged.is_empty() || !unmerged.is_empty() {
+                (
+                    "no changes added to commit",
+                    " (use \"git add\" and/or \"git commit -a\")",
+                )
+            } else if !self.untracked.is_empty() {
+                (
+                    "nothing added to commit but untracked files present",
+                    " (use \"git add\" to track)",
+                )
+            } else if self.head.is_none() {
+                (
+                    "nothing to commit",
+                    " (create/copy files and use \"git add\" to track)",
+                )
+            } else if untracked_mode == "no" {
+                ("nothing to commit", " (use -u to show untracked files)")
+            } else {
+                ("nothing to commit, working tree clean", "")
+            };
+            println!("{}{}", message, if hints { hint } else { "" });
+        }
+
+        Ok(())
+    }
+
+    /// The `-s` and `--porcelain` (v1) format: two status letters and the path.
+    fn print_short(&self, show_branch: bool, null_terminated: bool) -> Result<()> {
+        let end = if null_terminated { '\0' } else { '\n' };
+        let quote = |path: &str| match null_terminated {
+            true => Ok(path.to_string()),
+            false => utils::quote_path(path),
+        };
+
+        if show_branch {
+            let mut header = match (&self.branch, &self.head) {
+                (Some(branch), None) => format!("No commits yet on {}", branch),
+                (Some(branch), Some(_)) => branch.clone(),
+                (None, _) => "HEAD (no branch)".to_string(),
+            };
+            if let Some(Upstream { name, ahead_behind }) = &self.upstream {
+                header.push_str(&format!("...{}", name));
+                match ahead_behind {
+                    None => header.push_str(" [gone]"),
+                    Some((0, 0)) => {}
+                    Some((ahead, 0)) => header.push_str(&format!(" [ahead {}]", ahead)),
+                    Some((0, behind)) => header.push_str(&format!(" [behind {}]", behind)),
+                    Some((ahead, behind)) => {
+                        header.push_str(&format!(" [ahead {}, behind {}]", ahead, behind))
+                    }
+                }
+            }
+            print!("## {}{}", header, end);
+        }
+
+        for change in &self.changes {
+            let letters = format!("{}{}", change.staged, change.unstaged);
+            match (&change.renamed_from, null_terminated) {
+                (Some((from, _)), true) => print!("{} {}\0{}\0", letters, change.path, from),
+                (Some((from, _)), false) => {
+                    println!("{} {} -> {}", letters, quote(from)?, quote(&change.path)?)
+                }
+                (None, _) => print!("{} {}{}", letters, quote(&change.path)?, end),
+            }
+        }
+        for path in &self.untracked {
+            print!("?? {}{}", quote(path)?, end);
+        }
+        for path in &self.ignored {
+            print!("!! {}{}", quote(path)?, end);
+        }
+
+        Ok(())
+    }
+
+    /// `--porcelain=v2`: the object ids and modes of every side of a change,
+    /// with optional `# branch.*` headers.
+    fn print_porcelain_v2(&self, show_branch: bool, null_terminated: bool) -> Result<()> {
+        let end = if null_terminated { '\0' } else { '\n' };
+        let quote = |path: &str| match null_terminated {
+            true => Ok(path.to_string()),
+            false => utils::quote_path(path),
+        };
+
+        if show_branch {
+            let oid = self.head.as_deref().unwrap_or("(initial)");
+            print!("# branch.oid {}{}", oid, end);
+            let branch = self.branch.as_deref().unwrap_or("(detached)");
+            print!("# branch.head {}{}", branch, end);
+            if let Some(Upstream { name, ahead_behind }) = &self.upstream {
+                print!("# branch.upstream {}{}", name, end);
+                if let Some((ahead, behind)) = ahead_behind {
+                    print!("# branch.ab +{} -{}{}", ahead, behind, end);
+                }
+            }
+        }
+
+        let side = |side: &Option<(u32, String)>| match side {
+            Some((mode, sha1)) => (format!("{:06o}", mode), sha1.clone()),
+            None => ("000000".to_string(), ZERO_SHA1.to_string()),
+        };
+        // conflicts come after all the other changes
+        let (unmerged, merged): (Vec<&Change>, Vec<&Change>) = self
+            .changes
+            .iter()
+            .partition(|change| change.stages.is_some());
+        for change in merged.into_iter().chain(unmerged) {
+            let letters: String = [change.staged, change.unstaged]
+                .iter()
+                .map(|x| if *x == ' ' { '.' } else { *x })
+                .collect();
+            let worktree_mode = format!("{:06o}", change.worktree_mode);
+
+            if let Some(stages) = &change.stages {
+                let (m1, h1) = side(&stages[0]);
+                let (m2, h2) = side(&stages[1]);
+                let (m3, h3) = side(&stages[2]);
+                print!(
+                    "u {} N... {} {} {} {} {} {} {} {}{}",
+                    letters,
+                    m1,
+                    m2,
+                    m3,
+                    worktree_mode,
+                    h1,
+                    h2,
+                    h3,
+                    quote(&change.path)?,
+                    end
+                );
+                continue;
+            }
+
+            let (head_mode, head_sha1) = side(&change.head);
+            let (index_mode, index_sha1) = side(&change.index);
+            let submodule = match [&change.head, &change.index]
+                .iter()
+                .any(|side| matches!(side, Some((0o160000, _))))
+            {
+                true => "S...",
+                false => "N...",
+            };
+            let fields = format!(
+                "{} {} {} {} {} {} {}",
+                letters, submodule, head_mode, index_mode, worktree_mode, head_sha1, index_sha1
+            );
+            match &change.renamed_from {
+                Some((from, score)) => {
+                    let separator = if null_terminated { '\0' } else { '\t' };
+                    print!(
+                        "2 {} R{} {}{}{}{}",
+                        fields,
+                        score,
+                        quote(&change.path)?,
+                        separator,
+                        quote(from)?,
+                        end
+                    );
+                }
+                None => print!("1 {} {}{}", fields, quote(&change.path)?, end),
+            }
+        }
+        for path in &self.untracked {
+            print!("? {}{}", quote(path)?, end);
+        }
+        for path in &self.ignored {
+            print!("! {}{}", quote(path)?, end);
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the fetch refspec `refspec` (like `+refs/heads/*:refs/remotes/origin/*`)
+/// stores the remote ref `name`, if it fetches it at all.
+fn map_refspec(refspec: &str, name: &str) -> Option<String> {
+    let (source, destination) = refspec.trim_start_matches('+').split_once(':')?;
+    match source.split_once('*') {
+        Some((prefix, suffix)) => {
+            let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(destination.replacen('*', matched, 1))
+        }
+        None if source == name => Some(destination.to_string()),
+        None => None,
+    }
+}
+
+fn change_label(status: char) -> &'static str {
+    match status {
+        'A' => "new file:",
+        'D' => "deleted:",
+        'R' => "renamed:",
+        'T' => "typechange:",
+        _ => "modified:",
+    }
+}
+
+fn conflict_label(ours: char, theirs: char) -> &'static str {
+    match (ours, theirs) {
+        ('D', 'D') => "both deleted:",
+        ('A', 'U') => "added by us:",
+        ('U', 'D') => "deleted by them:",
+        ('U', 'A') => "added by them:",
+        ('D', 'U') => "deleted by us:",
+        ('A', 'A') => "both added:",
+        _ => "both modified:",
+    }
+}
+
+/// How much of `source` survives in `target`, out of [`MAX_SCORE`], counted
+/// the way diffcore-rename does: both are cut into lines of at most 64 bytes
+/// and the bytes of the lines they share are added up.
+fn similarity(source: &[u8], target: &[u8]) -> u64 {
+    let max_size = source.len().max(target.len()) as u64;
+    let min_size = source.len().min(target.len()) as u64;
+    // files that differ this much in size can't be similar enough
+    if max_size == 0 || max_size * (MAX_SCORE - MIN_SCORE) < (max_size - min_size) * MAX_SCORE {
+        return 0;
+    }
+
+    let source = span_hashes(source);
+    let target = span_hashes(target);
+    let copied: u64 = source
+        .iter()
+        .map(|(hash, count)| (*count).min(*target.get(hash).unwrap_or(&0)))
+        .sum();
+    copied * MAX_SCORE / max_size
+}
+
+/// The number of bytes in each kind of line of `data`, keyed by git's hash of
+/// the line. The CR of a CRLF doesn't count in text.
+fn span_hashes(data: &[u8]) -> HashMap<u32, u64> {
+    const HASHBASE: u32 = 107927;
+    let is_text = !data[..data.len().min(8000)].contains(&0);
+
+    let mut hashes: HashMap<u32, u64> = HashMap::new();
+    let (mut accum1, mut accum2, mut n): (u32, u32, u64) = (0, 0, 0);
+    for (i, &c) in data.iter().enumerate() {
+        if is_text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old >> 25);
+        accum1 = accum1.wrapping_add(c as u32);
+        n += 1;
+        if n < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+        *hashes.entry(hash).or_insert(0) += n;
+        accum1 = 0;
+        accum2 = 0;
+        n = 0;
+    }
+    if n > 0 {
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE;
+        *hashes.entry(hash).or_insert(0) += n;
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_shared_lines() {
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nc\nd\n"), MAX_SCORE);
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nx\ny\n"), MAX_SCORE / 2);
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"w\nx\ny\nz\n"), 0);
+        // lines are counted, not where they are
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"d\nc\nb\na\n"), MAX_SCORE);
+    }
+
+    #[test]
+    fn skips_files_of_too_different_sizes() {
+        assert_eq!(similarity(b"", b""), 0);
+        assert_eq!(similarity(b"a\n", b"a\nb\nc\nd\n"), 0);
+    }
+
+    #[test]
+    fn ignores_carriage_returns_in_text() {
+        assert_eq!(similarity(b"a\nb\n", b"a\r\nb\r\n"), MAX_SCORE * 4 / 6);
+        // but not in binary files
+        assert_eq!(similarity(b"\0\nb\n", b"\0\nb\r\n"), MAX_SCORE * 2 / 5);
+    }
+
+    #[test]
+    fn splits_long_lines() {
+        let long = [b'x'; 100];
+        let mut changed = long;
+        changed[99] = b'y';
+        assert_eq!(similarity(&long, &changed), MAX_SCORE * 64 / 100);
+    }
+}
@@ -0,0 +1,747 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use crate::args::{Checkout as CheckoutArgs, Switch as SwitchArgs};
+use crate::checkout::{Checkout, Outcome};
+use crate::commit::Commit;
+use crate::config::Config;
+use crate::index::{Index, IndexEntry};
+use crate::merge_file;
+use crate::object::Object;
+use crate::refs;
+use crate::signature::Signature;
+use crate::status::Status;
+use crate::tree::{Tree, EMPTY_TREE};
+use crate::unpack::{Merge, Rejection, Unpack};
+use crate::utils::{self, Ignore};
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The order rejected paths are reported in, a block for each reason.
+const REJECTIONS: [Rejection; 6] = [
+    Rejection::WouldOverwrite,
+    Rejection::NotUptodate,
+    Rejection::NotUptodateDirectory,
+    Rejection::UntrackedOverwritten,
+    Rejection::UntrackedRemoved,
+    Rejection::BindOverlap,
+];
+
+/// How many lost commits are listed before the rest are only counted.
+const ORPHAN_CUTOFF: usize = 4;
+
+/// Where `HEAD` is to go.
+struct Target {
+    /// The full name of the branch, `None` to detach `HEAD`.
+    branch: Option<String>,
+    commit: String,
+    /// As it was named on the command line, for the reflog.
+    name: String,
+}
+
+/// How `switch` and `checkout` were asked to move `HEAD`.
+struct Options {
+    force: bool,
+    merge: bool,
+    quiet: bool,
+    /// `--detach` was given, so detaching needs no explaining.
+    force_detach: bool,
+    /// The branch to create, and what it starts from as named.
+    new_branch: Option<(String, String)>,
+}
+
+/// The `switch` and `checkout` commands, which move `HEAD` to a branch or
+/// commit and bring the index and working tree along, or check files out.
+pub struct Switch {}
+
+impl Switch {
+    pub fn switch(args: &SwitchArgs) -> Result<()> {
+        if args.create.is_some() && args.detach {
+            return Err("options '-c' and '--detach' cannot be used together".into());
+        }
+        if args.force && args.merge {
+            return Err("options '-f' and '-m' cannot be used together".into());
+        }
+        let mut options = Options {
+            force: args.force,
+            merge: args.merge,
+            quiet: args.quiet,
+            force_detach: args.detach,
+            new_branch: None,
+        };
+
+        let branch = args
+            .branch
+            .as_deref()
+            .map(Switch::expand_previous)
+            .transpose()?;
+        let target = match (&args.create, args.detach) {
+            (Some(name), _) => {
+                let start = branch.as_deref().unwrap_or("HEAD");
+                options.new_branch = Some((name.clone(), start.to_string()));
+                Switch::new_branch_target(name, start)?
+            }
+            (None, true) => {
+                let name = branch.as_deref().unwrap_or("HEAD");
+                Target {
+                    branch: None,
+                    commit: Switch::resolve_commit(name)?,
+                    name: name.to_string(),
+                }
+            }
+            (None, false) => {
+                let name = branch
+                    .as_deref()
+                    .ok_or("missing branch or commit argument")?;
+                match Switch::branch_target(name)? {
+                    Some(target) => target,
+                    None => {
+                        // anything but a branch would detach HEAD, which has to be asked for
+                        Switch::resolve_commit(name)?;
+                        let kind = match refs::dwim_ref(name)? {
+                            Some(full_name) if full_name.starts_with("refs/tags/") => "tag",
+                            Some(full_name) if full_name.starts_with("refs/remotes/") => {
+                                "remote-tracking branch"
+                            }
+                            _ => "commit",
+                        };
+                        eprintln!("hint: If you want to detach HEAD at the commit, try again with the --detach option.");
+                        return Err(format!("a branch is expected, got {} '{}'", kind, name).into());
+                    }
+                }
+            }
+        };
+        Switch::switch_branches(&options, &target)
+    }
+
+    /// Switches branches like `switch`, detaching `HEAD` at anything that
+    /// isn't a branch, or checks out the paths that follow the commit.
+    pub fn checkout(args: &CheckoutArgs) -> Result<()> {
+        if args.force && args.merge {
+            return Err("options '-f' and '-m' cannot be used together".into());
+        }
+
+        let mut arguments = args.args.clone();
+        if let Some(first) = arguments.first_mut() {
+            *first = Switch::expand_previous(first)?;
+        }
+        // without `--`, the first argument is the commit when it names one
+        let (rev, paths) = match (args.paths.is_empty(), arguments.split_first()) {
+            (false, _) if args.args.len() > 1 => {
+                return Err(
+                    format!("only one reference expected, {} given.", args.args.len()).into(),
+                )
+            }
+            (false, first) => (first.map(|(rev, _)| rev.as_str()), args.paths.clone()),
+            (true, Some((rev, paths))) if Switch::resolve_commit(rev).is_ok() => {
+                (Some(rev.as_str()), paths.to_vec())
+            }
+            (true, _) => (None, arguments.clone()),
+        };
+        if !paths.is_empty() {
+            // a lone argument that isn't a commit is only a path without those
+            if let (true, None, [path]) = (args.paths.is_empty(), rev, paths.as_slice()) {
+                if let Some(name) = &args.new_branch {
+                    return Err(format!(
+                        "'{}' is not a commit and a branch '{}' cannot be created from it",
+                        path, name
+                    )
+                    .into());
+                }
+                if args.detach {
+                    return Err(format!(
+                        "git checkout: --detach does not take a path argument '{}'",
+                        path
+                    )
+                    .into());
+                }
+            }
+            if args.new_branch.is_some() {
+                return Err("'-b' cannot be used with updating paths".into());
+            }
+            if args.detach {
+                return Err("'--detach' cannot be used with updating paths".into());
+            }
+            // how many were written is only said when no `--` set the paths apart
+            let report = !args.quiet && args.paths.is_empty();
+            return Switch::checkout_paths(rev, &paths, args.force, report);
+        }
+
+        let mut options = Options {
+            force: args.force,
+            merge: args.merge,
+            quiet: args.quiet,
+            force_detach: args.detach,
+            new_branch: None,
+        };
+        let target = match (&args.new_branch, rev) {
+            (Some(name), rev) => {
+                let start = rev.unwrap_or("HEAD");
+                options.new_branch = Some((name.clone(), start.to_string()));
+                Switch::new_branch_target(name, start)?
+            }
+            (None, Some(rev)) => match Switch::branch_target(rev)? {
+                Some(target) if !args.detach => target,
+                _ => Target {
+                    branch: None,
+                    commit: Switch::resolve_commit(rev)?,
+                    name: rev.to_string(),
+                },
+            },
+            // `HEAD` stays, but the index and working tree are brought up to it
+            (None, None) => Target {
+                branch: None,
+                commit: refs::read_ref("HEAD")?.ok_or("You are on a branch yet to be born")?,
+                name: String::from("HEAD"),
+            },
+        };
+        Switch::switch_branches(&options, &target)
+    }
+
+    /// `name` with `-` (short for `@{-1}`) and `@{-n}` replaced by the branch
+    /// or commit they stand for, so they can be switched to like any other.
+    fn expand_previous(name: &str) -> Result<String> {
+        let spec = match name {
+            "-" => "@{-1}",
+            name => name,
+        };
+        Ok(refs::previous_checkout(spec)?.unwrap_or_else(|| spec.to_string()))
+    }
+
+    /// The commit `name` stands for.
+    fn resolve_commit(name: &str) -> Result<String> {
+        refs::rev_parse(name)
+            .and_then(|sha1| refs::peel(&sha1, "commit"))
+            .map_err(|_| format!("invalid reference: {}", name).into())
+    }
+
+    /// The local branch `name`, if there is one.
+    fn branch_target(name: &str) -> Result<Option<Target>> {
+        let full_name = format!("refs/heads/{}", name);
+        Ok(refs::read_ref(&full_name)?.map(|commit| Target {
+            branch: Some(full_name),
+            commit,
+            name: name.to_string(),
+        }))
+    }
+
+    /// The branch `name`, still to be created at `start`.
+    fn new_branch_target(name: &str, start: &str) -> Result<Target> {
+        let full_name = format!("refs/heads/{}", name);
+        refs::check_ref_format(&full_name)
+            .map_err(|_| format!("'{}' is not a valid branch name", name))?;
+        if refs::read_ref(&full_name)?.is_some() {
+            return Err(format!("a branch named '{}' already exists", name).into());
+        }
+        Ok(Target {
+            branch: Some(full_name),
+            commit: Switch::resolve_commit(start)?,
+            name: name.to_string(),
+        })
+    }
+
+    fn switch_branches(options: &Options, new: &Target) -> Result<()> {
+        let old_branch = refs::symbolic_ref("HEAD")?;
+        let old_commit = refs::read_ref("HEAD")?;
+
+        if !Switch::keeps_working_tree(options, old_commit.as_deref(), new) {
+            let mut index = Index::read()?;
+            Switch::merge_working_tree(options, &mut index, old_commit.as_deref(), new)?;
+            index.write()?;
+        }
+
+        if let (None, Some(old_commit)) = (&old_branch, &old_commit) {
+            if !options.quiet && *old_commit != new.commit {
+                Switch::orphaned_commit_warning(old_commit, &new.commit)?;
+            }
+        }
+        Switch::update_refs(options, old_branch.as_deref(), old_commit.as_deref(), new)
+    }
+
+    /// Whether only a branch is created where `HEAD` already is, which leaves
+    /// the index and working tree alone.
+    fn keeps_working_tree(options: &Options, old_commit: Option<&str>, new: &Target) -> bool {
+        options.new_branch.is_some()
+            && !options.force
+            && !options.merge
+            && old_commit == Some(new.commit.as_str())
+    }
+
+    /// Moves the index and working tree from `old_commit` to the target with a
+    /// two-way merge that keeps local changes, giving up on any path where
+    /// they would be lost. `--force` resets to the target instead.
+    fn merge_working_tree(
+        options: &Options,
+        index: &mut Index,
+        old_commit: Option<&str>,
+        new: &Target,
+    ) -> Result<()> {
+        let new_tree = refs::peel_to_tree(&new.commit)?;
+        if options.force {
+            let mut unpack = Unpack::new(Merge::OneWay);
+            unpack.update = true;
+            unpack.reset = true;
+            unpack.unpack(index, &[new_tree])?;
+        } else {
+            let mut unmerged: Vec<&str> = Vec::new();
+            for entry in index.entries.iter().filter(|entry| entry.stage != 0) {
+                if unmerged.last() != Some(&entry.path.as_str()) {
+                    unmerged.push(&entry.path);
+                }
+            }
+            if !unmerged.is_empty() {
+                eprintln!("error: you need to resolve your current index first");
+                for path in unmerged {
+                    eprintln!("{}: needs merge", path);
+                }
+                std::process::exit(1);
+            }
+
+            let old_tree = match old_commit {
+                Some(old_commit) => refs::peel_to_tree(old_commit)?,
+                None => EMPTY_TREE.to_string(),
+            };
+            let mut unpack = Unpack::new(Merge::TwoWay);
+            unpack.update = true;
+            unpack.show_all_errors = true;
+            // ignored files may be overwritten
+            unpack.ignore = Some(Ignore::load()?);
+            unpack.unpack(index, &[old_tree.clone(), new_tree.clone()])?;
+
+            // an unborn branch has nothing to merge the local changes with
+            if !unpack.rejected.is_empty() && (!options.merge || old_commit.is_none()) {
+                for reason in REJECTIONS.iter() {
+                    let paths: String = unpack
+                        .rejected
+                        .iter()
+                        .filter(|(rejection, _)| rejection == reason)
+                        .map(|(_, path)| format!("\t{}\n", path))
+                        .collect();
+                    if !paths.is_empty() {
+                        eprintln!("error: {}", reason.porcelain_message(&paths));
+                    }
+                }
+                eprintln!("Aborting");
+                std::process::exit(1);
+            }
+            if !unpack.rejected.is_empty() {
+                Switch::merge_local_changes(index, &old_tree, &new_tree, &new.name)?;
+            }
+        }
+
+        // the index is usually exactly a tree now, which is worth caching whole
+        match index.entries.iter().all(|entry| entry.stage == 0) {
+            true => {
+                Tree::write_index_tree(index)?;
+            }
+            false => index.cache_tree = None,
+        }
+        Ok(())
+    }
+
+    /// Carries the local changes over to `new_tree` the way `--merge` does,
+    /// merging them in as a third tree with `old_tree` as the base. A file
+    /// changed on both sides is merged line by line, and only stays unmerged
+    /// when the changes overlap.
+    fn merge_local_changes(
+        index: &mut Index,
+        old_tree: &str,
+        new_tree: &str,
+        label: &str,
+    ) -> Result<()> {
+        let old_entries: BTreeMap<String, (u32, String)> = Tree::flatten(old_tree)?
+            .into_iter()
+            .map(|(path, mode, sha1)| (path, (mode, sha1)))
+            .collect();
+        let mut staged: Vec<&str> = index
+            .entries
+            .iter()
+            .filter(|entry| {
+                old_entries
+                    .get(&entry.path)
+                    .map(|(mode, sha1)| (*mode, sha1.as_str()))
+                    != Some((entry.mode, entry.sha1.as_str()))
+            })
+            .map(|entry| entry.path.as_str())
+            .collect();
+        staged.extend(
+            old_entries
+                .keys()
+                .filter(|path| !index.is_tracked(path))
+                .map(|path| path.as_str()),
+        );
+        if !staged.is_empty() {
+            staged.sort_unstable();
+            return Err(format!(
+                "cannot continue with staged changes in the following files:\n{}",
+                staged.join(" ")
+            )
+            .into());
+        }
+
+        // the local changes are kept in a tree while the new one is checked out
+        index.update_tracked()?;
+        let work = Tree::write_index_tree(index)?;
+        let mut reset = Unpack::new(Merge::OneWay);
+        reset.update = true;
+        reset.reset = true;
+        reset.protect_untracked = true;
+        reset.unpack(index, &[new_tree.to_string()])?;
+        let mut merge = Unpack::new(Merge::ThreeWay);
+        if reset.rejected.is_empty() {
+            merge.update = true;
+            merge.unpack(index, &[old_tree.to_string(), new_tree.to_string(), work])?;
+        }
+        if let Some((rejection, path)) = reset.rejected.iter().chain(&merge.rejected).next() {
+            eprintln!("error: {}", rejection.message(path));
+            std::process::exit(128);
+        }
+
+        let mut stages: BTreeMap<&str, [Option<&IndexEntry>; 3]> = BTreeMap::new();
+        for entry in index.entries.iter().filter(|entry| entry.stage != 0) {
+            stages.entry(&entry.path).or_default()[entry.stage as usize - 1] = Some(entry);
+        }
+        let is_file = |entry: &IndexEntry| entry.mode & 0o170000 == 0o100000;
+        let mut resolved: HashSet<String> = HashSet::new();
+        for (path, [base, ours, theirs]) in stages {
+            let (ours, theirs) = match (ours, theirs) {
+                (Some(ours), Some(theirs)) if is_file(ours) && is_file(theirs) => (ours, theirs),
+                _ => continue,
+            };
+            let base = match base {
+                Some(base) if is_file(base) => Object::open(&base.sha1)?.body().to_vec(),
+                _ => Vec::new(),
+            };
+            let merged = merge_file::merge(
+                &base,
+                Object::open(&ours.sha1)?.body(),
+                Object::open(&theirs.sha1)?.body(),
+                [label, "local"],
+            );
+            fs::write(path, merged.content)?;
+            if !merged.conflicted {
+                resolved.insert(path.to_string());
+            }
+        }
+        index
+            .entries
+            .retain(|entry| entry.stage == 0 || !resolved.contains(&entry.path));
+
+        // what merged cleanly stays in the working tree only, over the new tree
+        let new_entries = Tree::flatten(new_tree)?;
+        let unchanged: HashSet<(&str, u32, &str)> = new_entries
+            .iter()
+            .map(|(path, mode, sha1)| (path.as_str(), *mode, sha1.as_str()))
+            .collect();
+        let mut entries: Vec<IndexEntry> = index
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.stage != 0
+                    || unchanged.contains(&(entry.path.as_str(), entry.mode, entry.sha1.as_str()))
+            })
+            .cloned()
+            .collect();
+        for (path, mode, sha1) in &new_entries {
+            let kept = match index.find(path) {
+                Some(entry) => entry.mode == *mode && entry.sha1 == *sha1,
+                None => index.is_tracked(path),
+            };
+            if !kept {
+                entries.push(IndexEntry::new(path, *mode, sha1));
+            }
+        }
+        entries.sort_by(|a, b| (a.path.as_bytes(), a.stage).cmp(&(b.path.as_bytes(), b.stage)));
+        index.entries = entries;
+        Ok(())
+    }
+
+    /// Warns about the commits only the detached `HEAD` at `old` still
+    /// reaches, which leaving it loses, or says where it was.
+    fn orphaned_commit_warning(old: &str, new: &str) -> Result<()> {
+        let mut kept = vec![new.to_string()];
+        for name in refs::list_refs("refs/")? {
+            if let Some(sha1) = refs::read_ref(&name)? {
+                kept.extend(refs::peel(&sha1, "commit").ok());
+            }
+        }
+        let kept: Vec<&str> = kept.iter().map(|sha1| sha1.as_str()).collect();
+        // painted from `old` down to where the refs take over, newest first
+        let (lost, _) = Commit::paint_down(&[old], &kept)?;
+
+        if lost.is_empty() {
+            return Switch::describe_detached_head("Previous HEAD position was", old);
+        }
+        let mut list = String::new();
+        for (i, sha1) in lost.iter().enumerate() {
+            // a single commit more is listed rather than counted
+            if i < ORPHAN_CUTOFF || lost.len() == ORPHAN_CUTOFF + 1 {
+                list.push_str(&format!(
+                    "  {} {}\n",
+                    Object::shortest_abbrev(sha1)?,
+                    Commit::parse(Object::open(sha1)?.body())?.subject()
+                ));
+            }
+        }
+        if lost.len() > ORPHAN_CUTOFF + 1 {
+            list.push_str(&format!(" ... and {} more.\n", lost.len() - ORPHAN_CUTOFF));
+        }
+        let (commits, them) = match lost.len() {
+            1 => (String::from("1 commit"), "it"),
+            count => (format!("{} commits", count), "them"),
+        };
+        eprint!(
+            "Warning: you are leaving {} behind, not connected to\n\
+             any of your branches:\n\n{}\n",
+            commits, list
+        );
+        if Config::cached()?
+            .get_bool("advice.detachedHead")?
+            .unwrap_or(true)
+        {
+            eprint!(
+                "If you want to keep {} by creating a new branch, this may be a good time\n\
+                 to do so with:\n\n git branch <new-branch-name> {}\n\n",
+                them,
+                Object::shortest_abbrev(old)?
+            );
+        }
+        Ok(())
+    }
+
+    fn describe_detached_head(message: &str, commit: &str) -> Result<()> {
+        let subject = Commit::parse(Object::open(commit)?.body())?.subject();
+        eprintln!(
+            "{} {} {}",
+            message,
+            Object::shortest_abbrev(commit)?,
+            subject
+        );
+        Ok(())
+    }
+
+    /// Creates the new branch, points `HEAD` at the target and says so, with a
+    /// `checkout: moving from X to Y` entry in `HEAD`'s reflog.
+    fn update_refs(
+        options: &Options,
+        old_branch: Option<&str>,
+        old_commit: Option<&str>,
+        new: &Target,
+    ) -> Result<()> {
+        let committer = Signature::committer()?;
+        if let Some((name, start)) = &options.new_branch {
+            refs::update_ref(
+                &format!("refs/heads/{}", name),
+                &new.commit,
+                &format!("branch: Created from {}", start),
+                &committer,
+            )?;
+        }
+
+        let old_name = match (old_branch, old_commit) {
+            (Some(branch), _) => branch.strip_prefix("refs/heads/").unwrap_or(branch),
+            (None, Some(commit)) => commit,
+            (None, None) => "HEAD",
+        };
+        let message = format!("checkout: moving from {} to {}", old_name, new.name);
+
+        // checking out `HEAD` itself leaves it be, attached or not
+        let stays = new.branch.is_none() && new.name == "HEAD" && !options.force_detach;
+        if !stays {
+            refs::update_head(
+                new.branch.as_deref().unwrap_or(&new.commit),
+                &message,
+                &committer,
+            )?;
+        }
+        // what changed is against the new HEAD
+        if !options.force && !options.quiet && !Switch::keeps_working_tree(options, old_commit, new)
+        {
+            Status::show_local_changes()?;
+        }
+        if options.quiet {
+            return Ok(());
+        }
+        if stays {
+            return match old_branch {
+                Some(branch) => {
+                    Status::report_tracking(branch.strip_prefix("refs/heads/").unwrap_or(branch))
+                }
+                None => Ok(()),
+            };
+        }
+
+        let branch = match &new.branch {
+            Some(branch) => branch,
+            None => {
+                let advice = Config::cached()?
+                    .get_bool("advice.detachedHead")?
+                    .unwrap_or(true);
+                if old_branch.is_some() && advice && !options.force_detach {
+                    eprint!("{}", detach_advice(&new.name));
+                }
+                Switch::describe_detached_head("HEAD is now at", &new.commit)?;
+                // a branch detached from is still reported on
+                let branch = format!("refs/heads/{}", new.name);
+                if options.force_detach && refs::read_ref(&branch)?.is_some() {
+                    Status::report_tracking(&new.name)?;
+                }
+                return Ok(());
+            }
+        };
+        let name = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+        if options.new_branch.is_some() {
+            eprintln!("Switched to a new branch '{}'", name);
+        } else if old_branch == Some(branch.as_str()) {
+            eprintln!("Already on '{}'", name);
+        } else {
+            eprintln!("Switched to branch '{}'", name);
+        }
+        Status::report_tracking(name)
+    }
+
+    /// Checks the files matching `specs` out of `rev` and stages them, or out
+    /// of the index without `rev`. `HEAD` stays where it is. With `report`,
+    /// how many files were written is said.
+    fn checkout_paths(
+        rev: Option<&str>,
+        specs: &[String],
+        force: bool,
+        report: bool,
+    ) -> Result<()> {
+        let mut index = Index::read()?;
+        let tree_sha1 = match rev {
+            Some(rev) => {
+                let sha1 =
+                    refs::rev_parse(rev).map_err(|_| format!("invalid reference: {}", rev))?;
+                Some(refs::peel_to_tree(&sha1)?)
+            }
+            None => None,
+        };
+        let tree = match &tree_sha1 {
+            Some(tree_sha1) => Some(Tree::flatten(tree_sha1)?),
+            None => None,
+        };
+
+        let normalized: Vec<String> = specs
+            .iter()
+            .map(|spec| utils::normalize_pathspec(spec))
+            .collect();
+        let matches = |path: &str| {
+            normalized
+                .iter()
+                .any(|spec| utils::pathspec_matches(spec, path))
+        };
+        let mut unmatched = false;
+        for (spec, normalized) in specs.iter().zip(&normalized) {
+            let known = index
+                .entries
+                .iter()
+                .map(|entry| &entry.path)
+                .chain(tree.iter().flatten().map(|(path, _, _)| path))
+                .any(|path| utils::pathspec_matches(normalized, path));
+            if !known {
+                eprintln!(
+                    "error: pathspec '{}' did not match any file(s) known to git",
+                    spec
+                );
+                unmatched = true;
+            }
+        }
+        if unmatched {
+            std::process::exit(1);
+        }
+
+        let mut paths: HashSet<String> = HashSet::new();
+        match &tree {
+            Some(tree) => {
+                for (path, mode, sha1) in tree.iter().filter(|(path, _, _)| matches(path)) {
+                    // an entry already staged that way keeps its stat data
+                    let staged = index
+                        .find(path)
+                        .is_some_and(|entry| entry.mode == *mode && entry.sha1 == *sha1);
+                    if !staged {
+                        index.add(IndexEntry::new(path, *mode, sha1));
+                    }
+                    paths.insert(path.clone());
+                }
+            }
+            None => {
+                let mut unmerged: Vec<&str> = Vec::new();
+                for entry in index.entries.iter().filter(|entry| matches(&entry.path)) {
+                    if entry.stage == 0 {
+                        paths.insert(entry.path.clone());
+                    } else if unmerged.last() != Some(&entry.path.as_str()) {
+                        unmerged.push(&entry.path);
+                    }
+                }
+                for path in &unmerged {
+                    match force {
+                        true => eprintln!("warning: path '{}' is unmerged", path),
+                        false => eprintln!("error: path '{}' is unmerged", path),
+                    }
+                }
+                if !unmerged.is_empty() && !force {
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        let checkout = Checkout {
+            force: true,
+            ..Checkout::default()
+        };
+        let positions: Vec<usize> = (0..index.entries.len())
+            .filter(|i| {
+                let entry = &index.entries[*i];
+                entry.stage == 0 && !entry.skip_worktree && paths.contains(&entry.path)
+            })
+            .collect();
+        let entries: Vec<&IndexEntry> = positions.iter().map(|i| &index.entries[*i]).collect();
+        let outcomes = checkout.checkout_entries(&index, &entries)?;
+        let mut failed = false;
+        let mut written = 0;
+        for (i, outcome) in positions.into_iter().zip(outcomes) {
+            match outcome {
+                Outcome::Written(metadata) => {
+                    index.entries[i] = index.entries[i].with_stat(&metadata);
+                    written += 1;
+                }
+                Outcome::Skipped => {}
+                Outcome::Failed => failed = true,
+            }
+        }
+
+        index.write()?;
+        if report {
+            let source = match &tree_sha1 {
+                Some(tree_sha1) => Object::shortest_abbrev(tree_sha1)?,
+                None => String::from("the index"),
+            };
+            let paths = if written == 1 { "path" } else { "paths" };
+            eprintln!("Updated {} {} from {}", written, paths, source);
+        }
+        if failed {
+            std::process::exit(1);
+        }
+        Ok(())
+    }
+}
+
+/// What is said on leaving a branch for a detached `HEAD` at `name`.
+fn detach_advice(name: &str) -> String {
+    format!(
+        "Note: switching to '{}'.\n\n\
+         You are in 'detached HEAD' state. You can look around, make experimental\n\
+         changes and commit them, and you can discard any commits you make in this\n\
+         state without impacting any branches by switching back to a branch.\n\n\
+         If you want to create a new branch to retain commits you create, you may\n\
+         do so (now or later) by using -c with the switch command. Example:\n\n  \
+         git switch -c <new-branch-name>\n\n\
+         Or undo this operation with:\n\n  \
+         git switch -\n\n\
+         Turn off this advice by setting config variable advice.detachedHead to false\n\n",
+        name
+    )
+}
@@ -0,0 +1,186 @@
+use std::io::Read;
+
+use crate::args::Tag as TagArgs;
+use crate::object::{Object, ObjectType};
+use crate::signature::Signature;
+use crate::{fsck, refs, utils};
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+const SIGNATURE_STARTS: [&str; 4] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN PGP MESSAGE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+    "-----BEGIN SIGNED MESSAGE-----",
+];
+
+/// An annotated tag object.
+pub struct Tag {
+    pub object: String,
+    pub object_type: String,
+    pub tag: String,
+    pub tagger: Option<Signature>,
+    pub message: Vec<u8>,
+    /// A detached signature appended to the message, kept byte for byte.
+    pub signature: Option<Vec<u8>>,
+}
+
+impl Tag {
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let (header, body) = match content.windows(2).position(|x| x == b"\n\n") {
+            Some(index) => (&content[..index], &content[index + 2..]),
+            None => (
+                content.strip_suffix(b"\n").unwrap_or(content),
+                &content[content.len()..],
+            ),
+        };
+
+        // only the tagger may hold something other than UTF-8
+        let mut lines = header.split(|x| *x == b'\n');
+        let mut field = |name: &str| -> Result<String> {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name.as_bytes()))
+                .and_then(|line| line.strip_prefix(b" "))
+                .and_then(|line| String::from_utf8(line.to_vec()).ok())
+                .ok_or_else(|| format!("tag has no {} line", name).into())
+        };
+
+        let object = field("object")?;
+        let object_type = field("type")?;
+        let tag = field("tag")?;
+        let unexpected =
+            |line: &[u8]| format!("unexpected tag header {:?}", String::from_utf8_lossy(line));
+        let tagger = match lines.next() {
+            Some(line) => match line.strip_prefix(b"tagger ") {
+                Some(ident) => Some(Signature::parse(ident)?),
+                None => return Err(unexpected(line).into()),
+            },
+            None => None,
+        };
+        if let Some(line) = lines.next() {
+            return Err(unexpected(line).into());
+        }
+
+        // the signature starts on the last line beginning with one of the armor headers
+        let mut split = body.len();
+        let mut line_start = 0;
+        while line_start < body.len() {
+            if SIGNATURE_STARTS
+                .iter()
+                .any(|start| body[line_start..].starts_with(start.as_bytes()))
+            {
+                split = line_start;
+            }
+            line_start += match body[line_start..].iter().position(|x| *x == b'\n') {
+                Some(index) => index + 1,
+                None => body.len(),
+            };
+        }
+
+        Ok(Tag {
+            object,
+            object_type,
+            tag,
+            tagger,
+            message: body[..split].to_vec(),
+            signature: if split < body.len() {
+                Some(body[split..].to_vec())
+            } else {
+                None
+            },
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut vec: Vec<u8> = Vec::new();
+        vec.extend(format!("object {}\n", self.object).as_bytes());
+        vec.extend(format!("type {}\n", self.object_type).as_bytes());
+        vec.extend(format!("tag {}\n", self.tag).as_bytes());
+        if let Some(tagger) = &self.tagger {
+            vec.extend(b"tagger ");
+            vec.extend(tagger.to_bytes());
+            vec.push(b'\n');
+        }
+        vec.push(b'\n');
+        vec.extend(&self.message);
+        if let Some(signature) = &self.signature {
+            vec.extend(signature);
+        }
+        vec
+    }
+
+    /// Checks a tag the way `git mktag` does: well formed, with a tagger, and
+    /// pointing at an existing object of the type it claims.
+    pub fn validate(content: &[u8]) -> Result<Self> {
+        fsck::check_object("tag", content)?;
+        let tag = Tag::parse(content)?;
+        if tag.tagger.is_none() {
+            return Err("tag has no tagger line".into());
+        }
+
+        let target = Object::open_reader(&tag.object)
+            .map_err(|_| format!("could not read tagged object '{}'", tag.object))?;
+        if target.object_type.to_string() != tag.object_type {
+            return Err(format!(
+                "object '{}' tagged as '{}', but is a '{}' type",
+                tag.object, tag.object_type, target.object_type
+            )
+            .into());
+        }
+
+        Ok(tag)
+    }
+
+    pub fn mktag() -> Result<()> {
+        let mut content = Vec::new();
+        std::io::stdin().read_to_end(&mut content)?;
+
+        // the input is stored as given, only checked
+        Tag::validate(&content)?;
+        let hex_sha1 = Object::hash_object(ObjectType::Tag, content)?;
+        println!("{}", hex_sha1);
+        Ok(())
+    }
+
+    pub fn tag(args: &TagArgs) -> Result<()> {
+        let name = match &args.name {
+            Some(name) if !args.list => name,
+            _ => {
+                for name in refs::list_refs("refs/tags/")? {
+                    println!("{}", name.strip_prefix("refs/tags/").unwrap());
+                }
+                return Ok(());
+            }
+        };
+
+        let ref_name = format!("refs/tags/{}", name);
+        refs::check_ref_format(&ref_name)?;
+        if !args.force && refs::read_ref(&ref_name)?.is_some() {
+            return Err(format!("tag '{}' already exists", name).into());
+        }
+
+        let target = refs::rev_parse(args.object.as_deref().unwrap_or("HEAD"))?;
+
+        let hex_sha1 = if args.annotate || !args.message.is_empty() {
+            if args.message.is_empty() {
+                return Err("no tag message given, use -m".into());
+            }
+            let object_type = Object::open_reader(&target)?.object_type.to_string();
+            let tag = Tag {
+                object: target,
+                object_type,
+                tag: name.to_string(),
+                tagger: Some(Signature::committer()?),
+                message: utils::cleanup_message(&args.message.join("\n\n")).into_bytes(),
+                signature: None,
+            };
+            Object::hash_object(ObjectType::Tag, tag.serialize())?
+        } else {
+            target
+        };
+
+        refs::write_ref(&ref_name, &hex_sha1)
+    }
+}
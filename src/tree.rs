@@ -1,16 +1,16 @@
 use std::convert::TryInto;
 use std::fmt::Debug;
-use std::fs;
-use std::fs::File;
-use std::os::unix::fs::PermissionsExt;
 use std::{path::Path, path::PathBuf};
 
+use crate::args::WriteTree;
+use crate::index::{CacheTree, Index, IndexEntry};
 use crate::object::{Object, ObjectType};
-use crate::utils;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
 pub struct RefEntry {
     pub mode: u32,
     pub filename: PathBuf, //probably should have used String here, would have made things much simpler :/
@@ -33,40 +33,47 @@ impl Debug for RefEntry {
 pub struct Tree {}
 
 impl Tree {
-    const POSSIBLE_MODES: [u32; 7] = [100664, 100775, 100644, 100755, 120000, 40000, 160000];
-
     pub fn ls_tree(data: Vec<u8>) -> Result<()> {
         let header = std::str::from_utf8(&data[..5])?;
         if header != "tree " {
             return Err("Invalid Header, Not a Tree Object".into());
         }
-        let mut index = data.iter().position(|x| *x == '\0' as u8).unwrap();
+        let index = data.iter().position(|x| *x == b'\0').unwrap();
+
+        let ref_entries = Tree::parse_entries(&data[index + 1..])?;
+
+        for entries in &ref_entries {
+            println!("{}", entries.filename.display());
+        }
+
+        Ok(())
+    }
 
+    /// Parses the body of a tree object, i.e. everything after the header.
+    pub fn parse_entries(data: &[u8]) -> Result<Vec<RefEntry>> {
         let mut ref_entries: Vec<RefEntry> = Vec::new();
 
-        let mut counter = index + 1;
-        while counter <= data.len() {
-            let break_var = data[counter..].iter().position(|x| *x == ' ' as u8);
-            if break_var.is_none() {
-                break;
-            } else {
-                index = break_var.unwrap();
-            }
+        let mut counter = 0;
+        while counter < data.len() {
+            let index = match data[counter..].iter().position(|x| *x == b' ') {
+                Some(index) => index,
+                None => break,
+            };
 
-            let mode: u32 = std::str::from_utf8(&data[counter..counter + index])?
-                .parse()
-                .unwrap();
+            let mode: u32 = std::str::from_utf8(&data[counter..counter + index])?.parse()?;
             counter += index + 1;
-            index = data[counter..]
+            let index = data[counter..]
                 .iter()
-                .position(|x| *x == '\0' as u8)
-                .unwrap();
+                .position(|x| *x == b'\0')
+                .ok_or("Invalid Tree Entry")?;
             let filename = PathBuf::from(std::str::from_utf8(&data[counter..counter + index])?);
             counter += index + 1;
-            // let hex_sha1: [u8; 20] = data[counter..counter+20].try_into().unwrap();
-            let sha1: [u8; 40] = hex::encode(&data[counter..counter + 20])
-                .as_bytes()
-                .try_into()?;
+            let sha1: [u8; 40] = hex::encode(
+                data.get(counter..counter + 20)
+                    .ok_or("Invalid Tree Entry")?,
+            )
+            .as_bytes()
+            .try_into()?;
 
             ref_entries.push(RefEntry {
                 mode,
@@ -76,121 +83,199 @@ impl Tree {
             counter += 20;
         }
 
-        for entries in &ref_entries {
-            println!("{}", entries.filename.display());
-        }
-
-        Ok(())
+        Ok(ref_entries)
     }
 
-    pub fn tree_content<T: AsRef<Path>>(path: T) -> Result<Vec<RefEntry>> {
-        let absolute_entries: Vec<PathBuf> = utils::gitignored_sorted_current_dir(path.as_ref())?;
-
-        let mut tree: Vec<RefEntry> = Vec::new();
-
-        for filename in absolute_entries {
-            let f = File::open(&filename)?;
-            let metadata = f.metadata()?;
-            let permissions = metadata.permissions();
-            let mut git_mode: u32 = format!("{:o}", permissions.mode()).parse().unwrap();
-
-            if metadata.is_file() {
-                if Tree::POSSIBLE_MODES.contains(&git_mode) {
-                    if git_mode == 100664 {
-                        git_mode = 100644;
-                    } else if git_mode == 100775 {
-                        git_mode = 100755;
-                    }
-
-                    let byte_vec = fs::read(&filename)?;
-                    let hex_sha1 = Object::hash_object(ObjectType::Blob, byte_vec)?;
+    /// Every blob, symlink and gitlink below the tree `sha1`, by full path in
+    /// index order, with its octal mode and object id.
+    pub fn flatten(sha1: &str) -> Result<Vec<(String, u32, String)>> {
+        fn walk(sha1: &str, prefix: &str, entries: &mut Vec<(String, u32, String)>) -> Result<()> {
+            let tree = Object::open(sha1)?;
+            for entry in Tree::parse_entries(tree.body())? {
+                let path = format!("{}{}", prefix, entry.filename.display());
+                let sha1 = String::from_utf8(entry.sha1.to_vec())?;
+                // tree modes are parsed as if they were decimal
+                let mode = u32::from_str_radix(&entry.mode.to_string(), 8)?;
+                if mode == 0o40000 {
+                    walk(&sha1, &format!("{}/", path), entries)?;
+                } else {
+                    entries.push((path, mode, sha1));
+                }
+            }
+            Ok(())
+        }
 
-                    let entry = RefEntry {
-                        mode: git_mode,
-                        filename,
-                        sha1: hex_sha1
-                            .as_bytes()
-                            .try_into()
-                            .expect("Incorrect sha1 length"),
-                    };
+        let mut entries = Vec::new();
+        walk(sha1, "", &mut entries)?;
+        entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        Ok(entries)
+    }
 
-                    tree.push(entry);
-                }
-            } else if metadata.is_dir() {
-                git_mode = 40000;
+    /// Prints a tree the way `cat-file -p` does: mode, type, object id and name.
+    pub fn cat_file(data: &[u8]) -> Result<()> {
+        for entry in Tree::parse_entries(data)? {
+            let object_type = match entry.mode {
+                40000 => "tree",
+                160000 => "commit",
+                _ => "blob",
+            };
+            println!(
+                "{:06} {} {}\t{}",
+                entry.mode,
+                object_type,
+                String::from_utf8_lossy(&entry.sha1),
+                entry.filename.display()
+            );
+        }
 
-                let byte_vec = Tree::ref_entries_to_bytes(&filename)?;
-                let sha1 = Object::hash_object(ObjectType::Tree, byte_vec)?;
+        Ok(())
+    }
 
-                let entry = RefEntry {
-                    mode: git_mode,
-                    filename,
-                    sha1: sha1.as_bytes().try_into().expect("Incorrect sha1 length"),
-                };
+    /// Writes the trees for the staged entries of `index` and returns the id of
+    /// the root tree. Directories whose cache-tree entry is still valid are
+    /// reused as they are, and the cache is brought up to date for the rest.
+    pub fn write_index_tree(index: &mut Index) -> Result<String> {
+        if let Some(entry) = index.entries.iter().find(|entry| entry.stage != 0) {
+            return Err(format!("{}: unmerged ({})", entry.path, entry.sha1).into());
+        }
+        let mut cache_tree = index.cache_tree.take().unwrap_or_default();
+        let sha1 = Tree::write_index_subtree(&index.entries, 0, &mut cache_tree)?;
+        index.cache_tree = Some(cache_tree);
+        Ok(sha1)
+    }
 
-                tree.push(entry);
+    fn write_index_subtree(
+        entries: &[IndexEntry],
+        prefix_len: usize,
+        cache_tree: &mut CacheTree,
+    ) -> Result<String> {
+        if let Some(sha1) = &cache_tree.sha1 {
+            if cache_tree.entry_count == entries.len() {
+                return Ok(sha1.clone());
             }
         }
 
-        tree.sort_by_key(|x| x.filename.clone());
-
-        //a cool edge case I came across. took me a lot of time to figure this one out. very
-        //surprised this is how git has implemented file order
-        if tree.len() > 0 {
-            for i in 0..tree.len() - 1 {
-                let pathbuf = &tree[i].filename;
-                if let None = pathbuf.extension() {
-                    if pathbuf.is_dir() {
-                        if let Some(extension) = &tree[i + 1].filename.extension() {
-                            let mut new_pathname = String::new();
-                            let string = &tree[i].filename.display().to_string();
-                            new_pathname.push_str(&string);
-                            new_pathname.push_str(".");
-                            new_pathname.push_str(extension.to_str().unwrap());
-                            if tree[i + 1].filename.display().to_string() == new_pathname {
-                                tree.swap(i, i + 1);
-                            }
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut subtrees: Vec<CacheTree> = Vec::new();
+        let mut complete = true;
+
+        // the index is sorted by full path, which is already tree order, and the
+        // entries of each subdirectory sit next to each other
+        let mut i = 0;
+        while i < entries.len() {
+            let name = &entries[i].path[prefix_len..];
+            let (mode, name, sha1) = match name.find('/') {
+                Some(slash) => {
+                    let prefix = &entries[i].path[..prefix_len + slash + 1];
+                    let count = entries[i..]
+                        .iter()
+                        .take_while(|entry| entry.path.starts_with(prefix))
+                        .count();
+                    let name = &name[..slash];
+                    let mut subtree = match cache_tree.subtrees.iter().position(|x| x.name == name)
+                    {
+                        Some(position) => cache_tree.subtrees.remove(position),
+                        None => CacheTree {
+                            name: name.to_string(),
+                            ..CacheTree::default()
+                        },
+                    };
+                    let sha1 = Tree::write_index_subtree(
+                        &entries[i..i + count],
+                        prefix.len(),
+                        &mut subtree,
+                    )?;
+                    i += count;
+                    let partial = subtree.sha1.is_none();
+                    subtrees.push(subtree);
+                    if partial {
+                        complete = false;
+                        // a directory holding nothing but intent-to-add entries
+                        if sha1 == EMPTY_TREE {
+                            continue;
                         }
                     }
+                    (0o40000, name, sha1)
                 }
-            }
+                None => {
+                    i += 1;
+                    // paths added with --intent-to-add have no content to commit yet
+                    if entries[i - 1].intent_to_add {
+                        complete = false;
+                        continue;
+                    }
+                    (entries[i - 1].mode, name, entries[i - 1].sha1.clone())
+                }
+            };
+
+            bytes.extend(format!("{:o} {}\0", mode, name).as_bytes());
+            bytes.extend(hex::decode(sha1)?);
         }
 
-        Ok(tree)
+        let sha1 = Object::hash_object(ObjectType::Tree, bytes)?;
+
+        // a tree missing intent-to-add entries mustn't be taken for the whole
+        // directory, so it stays invalid and gets written again next time
+        subtrees.sort_by(|a, b| (a.name.len(), &a.name).cmp(&(b.name.len(), &b.name)));
+        cache_tree.subtrees = subtrees;
+        cache_tree.entry_count = entries.len();
+        cache_tree.sha1 = match complete {
+            true => Some(sha1.clone()),
+            false => None,
+        };
+
+        Ok(sha1)
     }
 
-    pub fn ref_entries_to_bytes<T: AsRef<Path>>(path: T) -> Result<Vec<u8>> {
-        let vec = Tree::tree_content(path)?;
-        let mut bytes: Vec<u8> = Vec::new();
-        for object in vec {
-            bytes.extend(object.mode.to_string().as_bytes());
-            bytes.push(' ' as u8);
-
-            let mut path = object.filename;
-            if let Some(index) = path.to_str().unwrap().rfind("/") {
-                let pathbuf_string = path.display().to_string();
-                let prefix = &pathbuf_string[..index + 1];
-                path = path.strip_prefix(prefix).unwrap().to_path_buf();
+    /// A fully valid cache tree for an index holding exactly the tree `sha1`.
+    pub fn cache_tree(sha1: &str) -> Result<CacheTree> {
+        let mut cache_tree = CacheTree {
+            sha1: Some(sha1.to_string()),
+            ..CacheTree::default()
+        };
+        for entry in Tree::parse_entries(Object::open(sha1)?.body())? {
+            if entry.mode != 40000 {
+                cache_tree.entry_count += 1;
+                continue;
             }
-
-            bytes.extend(
-                path.into_os_string()
-                    .into_string()
-                    .expect("Path has Invalid Unicode Data")
-                    .as_bytes(),
-            );
-            bytes.push('\0' as u8);
-            let decoded_hex = hex::decode(object.sha1)?;
-            bytes.extend(decoded_hex);
+            let mut subtree = Tree::cache_tree(&String::from_utf8(entry.sha1.to_vec())?)?;
+            subtree.name = entry.filename.display().to_string();
+            cache_tree.entry_count += subtree.entry_count;
+            cache_tree.subtrees.push(subtree);
         }
+        cache_tree
+            .subtrees
+            .sort_by(|a, b| (a.name.len(), &a.name).cmp(&(b.name.len(), &b.name)));
+        Ok(cache_tree)
+    }
 
-        Ok(bytes)
+    /// The id of the tree at `path` below the tree `sha1`, if there is one.
+    pub fn find_subtree(sha1: &str, path: &str) -> Result<Option<String>> {
+        let mut sha1 = sha1.to_string();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let tree = Object::open(&sha1)?;
+            let entry = Tree::parse_entries(tree.body())?
+                .into_iter()
+                .find(|entry| entry.mode == 40000 && entry.filename == Path::new(name));
+            match entry {
+                Some(entry) => sha1 = String::from_utf8(entry.sha1.to_vec())?,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(sha1))
     }
 
-    pub fn write_tree() -> Result<()> {
-        let byte_vec = Tree::ref_entries_to_bytes(".")?;
-        let sha1 = Object::hash_object(ObjectType::Tree, byte_vec)?;
+    pub fn write_tree(args: &WriteTree) -> Result<()> {
+        let mut index = Index::read()?;
+        let mut sha1 = Tree::write_index_tree(&mut index)?;
+        // the tree is written either way; another process holding the lock
+        // only means the cache isn't saved this time
+        let _ = index.write();
 
+        if let Some(prefix) = &args.prefix {
+            sha1 = Tree::find_subtree(&sha1, prefix)?
+                .ok_or_else(|| format!("prefix {} not found", prefix))?;
+        }
         println!("{}", sha1);
 
         Ok(())
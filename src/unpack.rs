@@ -0,0 +1,774 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::args::ReadTree;
+use crate::checkout::{Checkout, Outcome};
+use crate::index::{Index, IndexEntry};
+use crate::refs;
+use crate::tree::Tree;
+use crate::utils::Ignore;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// git refuses to merge more trees than this at once.
+const MAX_TREES: usize = 8;
+
+/// How the trees given to [`Unpack`] are combined with the index.
+#[derive(Clone, PartialEq)]
+pub enum Merge {
+    /// The index becomes the trees, a later tree winning over an earlier one.
+    Replace,
+    /// The index becomes the tree, keeping the stat data of unchanged entries.
+    OneWay,
+    /// Moves from the first tree to the second, carrying staged changes along.
+    TwoWay,
+    /// Merges the last two trees with the others as merge bases, leaving what
+    /// doesn't resolve trivially in stages 1 to 3.
+    ThreeWay,
+    /// Adds the tree below the prefix, next to what is already staged.
+    Bind(String),
+}
+
+/// Why a path stopped a merge.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rejection {
+    WouldOverwrite,
+    NotUptodate,
+    NotUptodateDirectory,
+    UntrackedOverwritten,
+    UntrackedRemoved,
+    BindOverlap,
+}
+
+impl Rejection {
+    /// The error git's `read-tree` gives for `path`.
+    pub fn message(&self, path: &str) -> String {
+        match self {
+            Rejection::WouldOverwrite => {
+                format!(
+                    "Entry '{}' would be overwritten by merge. Cannot merge.",
+                    path
+                )
+            }
+            Rejection::NotUptodate => format!("Entry '{}' not uptodate. Cannot merge.", path),
+            Rejection::NotUptodateDirectory => {
+                format!("Updating '{}' would lose untracked files in it", path)
+            }
+            Rejection::UntrackedOverwritten => format!(
+                "Untracked working tree file '{}' would be overwritten by merge.",
+                path
+            ),
+            Rejection::UntrackedRemoved => format!(
+                "Untracked working tree file '{}' would be removed by merge.",
+                path
+            ),
+            Rejection::BindOverlap => {
+                format!("Entry '{}' overlaps with '{}'.  Cannot bind.", path, path)
+            }
+        }
+    }
+
+    /// The error `switch` and `checkout` give for all the paths rejected for
+    /// this reason, already listed one per line in `paths`.
+    pub fn porcelain_message(&self, paths: &str) -> String {
+        match self {
+            Rejection::WouldOverwrite | Rejection::NotUptodate => format!(
+                "Your local changes to the following files would be overwritten by checkout:\n{}\
+                 Please commit your changes or stash them before you switch branches.",
+                paths
+            ),
+            Rejection::NotUptodateDirectory => format!(
+                "Updating the following directories would lose untracked files in them:\n{}",
+                paths
+            ),
+            Rejection::UntrackedOverwritten => format!(
+                "The following untracked working tree files would be overwritten by checkout:\n{}\
+                 Please move or remove them before you switch branches.",
+                paths
+            ),
+            Rejection::UntrackedRemoved => format!(
+                "The following untracked working tree files would be removed by checkout:\n{}\
+                 Please move or remove them before you switch branches.",
+                paths
+            ),
+            // not something a checkout runs into, so it keeps the plain wording
+            Rejection::BindOverlap => paths
+                .lines()
+                .map(|path| self.message(path.trim_start()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// What one of the trees has at a path.
+#[derive(Clone, Copy)]
+enum Side<'a> {
+    Missing,
+    Entry(&'a IndexEntry),
+    /// A directory, or a file where one of the leading directories would be.
+    Conflict,
+}
+
+impl<'a> Side<'a> {
+    fn entry(self) -> Option<&'a IndexEntry> {
+        match self {
+            Side::Entry(entry) => Some(entry),
+            _ => None,
+        }
+    }
+}
+
+/// Whether two entries have the same mode and content; two missing ones count
+/// as the same too.
+fn same(a: Option<&IndexEntry>, b: Option<&IndexEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.mode == b.mode && a.sha1 == b.sha1,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Whether `tree` has a directory at `path` or a file above it.
+fn has_conflict(tree: &BTreeMap<String, IndexEntry>, path: &str) -> bool {
+    let dir = format!("{}/", path);
+    tree.range(dir.clone()..)
+        .next()
+        .is_some_and(|(other, _)| other.starts_with(&dir))
+        || path
+            .match_indices('/')
+            .any(|(index, _)| tree.contains_key(&path[..index]))
+}
+
+/// Merges trees into the index path by path, the way git's unpack-trees does,
+/// and optionally brings the working tree along. Nothing changes when a path
+/// is rejected.
+pub struct Unpack {
+    merge: Merge,
+    /// Update the working tree to match the new index.
+    pub update: bool,
+    /// Discard local changes instead of refusing to lose them.
+    pub reset: bool,
+    /// Still refuse to overwrite untracked files on a reset.
+    pub protect_untracked: bool,
+    /// Look at every path before giving up, to report all rejections at once.
+    pub show_all_errors: bool,
+    /// Untracked files these rules ignore may be overwritten.
+    pub ignore: Option<Ignore>,
+    /// The paths that stopped the merge and why.
+    pub rejected: Vec<(Rejection, String)>,
+    // the tree holding our side; those before it are merge bases
+    head_idx: usize,
+    // an index that was never written takes the new tree as it is
+    initial_checkout: bool,
+    // paths that were unmerged in the index, collapsed to one entry
+    conflicted: HashSet<String>,
+    result: Vec<IndexEntry>,
+    // paths in `result` to write out to the working tree
+    checkout: HashSet<String>,
+    // paths dropped from the index, to delete from the working tree
+    removed: BTreeSet<String>,
+    invalidated: Vec<String>,
+}
+
+impl Unpack {
+    pub fn new(merge: Merge) -> Self {
+        Unpack {
+            merge,
+            update: false,
+            reset: false,
+            protect_untracked: false,
+            show_all_errors: false,
+            ignore: None,
+            rejected: Vec::new(),
+            head_idx: 1,
+            initial_checkout: false,
+            conflicted: HashSet::new(),
+            result: Vec::new(),
+            checkout: HashSet::new(),
+            removed: BTreeSet::new(),
+            invalidated: Vec::new(),
+        }
+    }
+
+    /// Merges the trees `trees` into `index`, updating the working tree too
+    /// when asked. When any path is rejected, the reasons are left in
+    /// `rejected` and neither the index nor the working tree is touched.
+    pub fn unpack(&mut self, index: &mut Index, trees: &[String]) -> Result<()> {
+        let prefix = match &self.merge {
+            Merge::Bind(prefix) => prefix.clone(),
+            _ => String::new(),
+        };
+        self.head_idx = match trees.len() {
+            0..=2 => 1,
+            count => count - 1,
+        };
+        self.initial_checkout = index.entries.is_empty() && !Path::new(".git/index").exists();
+
+        let mut tree_entries: Vec<BTreeMap<String, IndexEntry>> = Vec::new();
+        for (i, sha1) in trees.iter().enumerate() {
+            let stage = match (&self.merge, (i + 1).cmp(&self.head_idx)) {
+                (Merge::Replace, _) => 0,
+                (_, std::cmp::Ordering::Less) => 1,
+                (_, std::cmp::Ordering::Equal) => 2,
+                (_, std::cmp::Ordering::Greater) => 3,
+            };
+            let mut entries = BTreeMap::new();
+            for (path, mode, sha1) in Tree::flatten(sha1)? {
+                let path = format!("{}{}", prefix, path);
+                let entry = IndexEntry {
+                    stage,
+                    ..IndexEntry::new(&path, mode, &sha1)
+                };
+                entries.insert(path, entry);
+            }
+            tree_entries.push(entries);
+        }
+
+        // an unmerged path takes part with a single entry, made from its lowest stage
+        let mut current: BTreeMap<String, IndexEntry> = BTreeMap::new();
+        if self.merge != Merge::Replace {
+            for entry in &index.entries {
+                if entry.stage != 0 {
+                    self.conflicted.insert(entry.path.clone());
+                }
+                current
+                    .entry(entry.path.clone())
+                    .or_insert_with(|| match entry.stage {
+                        0 => entry.clone(),
+                        _ => IndexEntry::new(&entry.path, entry.mode, &entry.sha1),
+                    });
+            }
+        }
+
+        let mut paths: BTreeSet<&str> = current.keys().map(|path| path.as_str()).collect();
+        for entries in &tree_entries {
+            paths.extend(entries.keys().map(|path| path.as_str()));
+        }
+
+        for path in paths {
+            if !self.rejected.is_empty() && !self.show_all_errors {
+                break;
+            }
+            // already taken out to make room for a file replacing its directory
+            if self.removed.contains(path) {
+                continue;
+            }
+            let old = current.get(path);
+            let sides: Vec<Side> = tree_entries
+                .iter()
+                .map(|entries| match entries.get(path) {
+                    Some(entry) => Side::Entry(entry),
+                    None if has_conflict(entries, path) => Side::Conflict,
+                    None => Side::Missing,
+                })
+                .collect();
+            match self.merge {
+                Merge::Replace => {
+                    if let Some(entry) = sides.iter().rev().find_map(|side| side.entry()) {
+                        self.result.push(entry.clone());
+                    }
+                }
+                Merge::OneWay => self.oneway(index, old, sides[0])?,
+                Merge::TwoWay => self.twoway(index, old, sides[0], sides[1])?,
+                Merge::ThreeWay => self.threeway(index, old, &sides)?,
+                Merge::Bind(_) => self.bind(index, old, sides[0])?,
+            }
+        }
+        if !self.rejected.is_empty() {
+            return Ok(());
+        }
+
+        if self.update {
+            self.update_worktree(index)?;
+        }
+        index.entries = std::mem::take(&mut self.result);
+        for path in &self.invalidated {
+            index.invalidate(path);
+        }
+        // what the conflicts were resolved from no longer applies
+        index
+            .extensions
+            .retain(|(signature, _)| signature != b"REUC");
+        Ok(())
+    }
+
+    fn reject(&mut self, rejection: Rejection, path: &str) {
+        let rejected = (rejection, path.to_string());
+        if !self.rejected.contains(&rejected) {
+            self.rejected.push(rejected);
+        }
+    }
+
+    fn keep_entry(&mut self, entry: &IndexEntry) {
+        if entry.stage != 0 {
+            self.invalidated.push(entry.path.clone());
+        }
+        self.result.push(entry.clone());
+    }
+
+    /// Takes `merge` as the new entry for its path, reusing the old entry (and
+    /// its stat data) when nothing changed.
+    fn merged_entry(
+        &mut self,
+        source: &Index,
+        merge: &IndexEntry,
+        old: Option<&IndexEntry>,
+    ) -> Result<()> {
+        let mut entry = IndexEntry {
+            stage: 0,
+            ..merge.clone()
+        };
+        let mut update = true;
+        match old {
+            None => {
+                if !self.verify_absent(source, merge, Rejection::UntrackedOverwritten)? {
+                    return Ok(());
+                }
+                self.invalidated.push(merge.path.clone());
+            }
+            Some(old) if !self.conflicted.contains(&old.path) => {
+                if same(Some(old), Some(merge)) {
+                    entry = old.clone();
+                    update = false;
+                } else {
+                    if !self.verify_uptodate(source, old, Rejection::NotUptodate)? {
+                        return Ok(());
+                    }
+                    entry.skip_worktree = old.skip_worktree;
+                    self.invalidated.push(old.path.clone());
+                }
+            }
+            Some(old) => self.invalidated.push(old.path.clone()),
+        }
+
+        if update {
+            self.checkout.insert(entry.path.clone());
+        }
+        self.result.push(entry);
+        Ok(())
+    }
+
+    /// Drops the path of `old` from the index, or makes sure there's no
+    /// untracked file in the way of deleting `entry` when it wasn't staged.
+    fn deleted_entry(
+        &mut self,
+        source: &Index,
+        entry: Option<&IndexEntry>,
+        old: Option<&IndexEntry>,
+    ) -> Result<()> {
+        let old = match (old, entry) {
+            (Some(old), _) => old,
+            (None, Some(entry)) => {
+                self.verify_absent(source, entry, Rejection::UntrackedRemoved)?;
+                return Ok(());
+            }
+            (None, None) => return Ok(()),
+        };
+        if !self.conflicted.contains(&old.path)
+            && !self.verify_uptodate(source, old, Rejection::NotUptodate)?
+        {
+            return Ok(());
+        }
+        self.removed.insert(old.path.clone());
+        self.invalidated.push(old.path.clone());
+        Ok(())
+    }
+
+    fn oneway(&mut self, source: &Index, old: Option<&IndexEntry>, side: Side) -> Result<()> {
+        let entry = match side.entry() {
+            Some(entry) => entry,
+            None => return self.deleted_entry(source, old, old),
+        };
+        let old = match old {
+            Some(old) if same(Some(old), Some(entry)) => old,
+            _ => return self.merged_entry(source, entry, old),
+        };
+
+        // a reset puts back files that were changed or deleted
+        if self.reset && self.update && !old.skip_worktree {
+            let changed = match fs::symlink_metadata(&old.path) {
+                Ok(_) if old.mode == 0o160000 => false,
+                Ok(metadata) => source.stat_changed(old, &metadata)?,
+                Err(_) => true,
+            };
+            if changed {
+                self.checkout.insert(old.path.clone());
+            }
+        }
+        self.result.push(IndexEntry {
+            stage: 0,
+            ..old.clone()
+        });
+        Ok(())
+    }
+
+    /// Moves the index from `old` to `new` like a checkout: entries staged
+    /// differently from both are kept, and an entry only changes when the
+    /// index still has what `old` had.
+    fn twoway(
+        &mut self,
+        source: &Index,
+        current: Option<&IndexEntry>,
+        old: Side,
+        new: Side,
+    ) -> Result<()> {
+        let (old, new) = (old.entry(), new.entry());
+        let current = match current {
+            Some(current) => current,
+            None => {
+                return match (old, new) {
+                    // the deletion was staged
+                    (Some(_), Some(_)) if !self.initial_checkout && same(old, new) => Ok(()),
+                    (Some(old), Some(_)) if !self.initial_checkout => {
+                        self.reject(Rejection::WouldOverwrite, &old.path);
+                        Ok(())
+                    }
+                    (_, Some(new)) => self.merged_entry(source, new, None),
+                    (_, None) => self.deleted_entry(source, old, None),
+                };
+            }
+        };
+
+        if self.conflicted.contains(&current.path) {
+            if !same(old, new) && !self.reset {
+                self.reject(Rejection::WouldOverwrite, &current.path);
+                return Ok(());
+            }
+            return match new {
+                Some(new) => self.merged_entry(source, new, Some(current)),
+                None => self.deleted_entry(source, Some(current), Some(current)),
+            };
+        }
+
+        if same(old, new) || same(Some(current), new) {
+            self.keep_entry(current);
+            return Ok(());
+        }
+        match (old, new) {
+            (Some(_), None) if same(Some(current), old) => {
+                self.deleted_entry(source, old, Some(current))
+            }
+            (Some(_), Some(new)) if same(Some(current), old) => {
+                self.merged_entry(source, new, Some(current))
+            }
+            _ => {
+                self.reject(Rejection::WouldOverwrite, &current.path);
+                Ok(())
+            }
+        }
+    }
+
+    /// Resolves the trivial cases of a merge between our tree and theirs and
+    /// stages the bases and both sides of the rest. The index has to match our
+    /// tree.
+    fn threeway(
+        &mut self,
+        source: &Index,
+        index: Option<&IndexEntry>,
+        sides: &[Side],
+    ) -> Result<()> {
+        let bases = &sides[..self.head_idx - 1];
+        let head = sides[self.head_idx - 1];
+        let remote = sides[self.head_idx];
+        let head_conflict = matches!(head, Side::Conflict);
+        let remote_conflict = matches!(remote, Side::Conflict);
+        let (head, remote) = (head.entry(), remote.entry());
+        let any_base_missing = bases.iter().any(|base| base.entry().is_none());
+
+        // a base with a directory where the path is matches neither side
+        let base_matches = |base: &Side, other: Option<&IndexEntry>| match base {
+            Side::Conflict => false,
+            base => same(base.entry(), other),
+        };
+        let (mut head_match, mut remote_match) = (false, false);
+        if !same(remote, head) {
+            for base in bases {
+                head_match |= base_matches(base, head);
+                remote_match |= base_matches(base, remote);
+            }
+        }
+
+        // only they changed the path, so the index may match their side too
+        if let Some(remote) = remote {
+            if !head_conflict && head_match && !remote_match {
+                if let Some(index) = index {
+                    if !same(Some(index), Some(remote)) && !same(Some(index), head) {
+                        self.reject(Rejection::WouldOverwrite, &index.path);
+                        return Ok(());
+                    }
+                }
+                return self.merged_entry(source, remote, index);
+            }
+        }
+        if let Some(entry) = index {
+            if !same(index, head) {
+                self.reject(Rejection::WouldOverwrite, &entry.path);
+                return Ok(());
+            }
+        }
+        if let Some(head) = head {
+            // both made the same change, or only we changed the path
+            if same(Some(head), remote) || (!remote_conflict && remote_match && !head_match) {
+                return self.merged_entry(source, head, index);
+            }
+        }
+        // gone from both sides
+        if head.is_none() && remote.is_none() && any_base_missing {
+            return Ok(());
+        }
+
+        // the stages that replace the entry mustn't lose changes in the file
+        if let Some(index) = index {
+            if !self.verify_uptodate(source, index, Rejection::NotUptodate)? {
+                return Ok(());
+            }
+        }
+        if !head_match || !remote_match {
+            if let Some(base) = bases.iter().find_map(|base| base.entry()) {
+                self.keep_entry(base);
+            }
+        }
+        for entry in [head, remote].iter().flatten() {
+            self.keep_entry(entry);
+        }
+        Ok(())
+    }
+
+    fn bind(&mut self, source: &Index, old: Option<&IndexEntry>, side: Side) -> Result<()> {
+        match (side.entry(), old) {
+            (Some(entry), Some(_)) => self.reject(Rejection::BindOverlap, &entry.path),
+            (Some(entry), None) => return self.merged_entry(source, entry, None),
+            (None, Some(old)) => self.keep_entry(old),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    /// Whether the working tree file of `entry` is as staged, so replacing or
+    /// deleting it loses nothing.
+    fn verify_uptodate(
+        &mut self,
+        source: &Index,
+        entry: &IndexEntry,
+        rejection: Rejection,
+    ) -> Result<bool> {
+        if self.reset && !entry.assume_valid && !entry.skip_worktree {
+            return Ok(true);
+        }
+        let changed = match fs::symlink_metadata(&entry.path) {
+            Ok(_) if entry.mode == 0o160000 => false,
+            Ok(metadata) => source.stat_changed(entry, &metadata)?,
+            Err(_) => false,
+        };
+        if changed {
+            self.reject(rejection, &entry.path);
+        }
+        Ok(!changed)
+    }
+
+    /// Whether `entry` can be written without destroying an untracked file,
+    /// either at its path or where one of its directories has to go.
+    fn verify_absent(
+        &mut self,
+        source: &Index,
+        entry: &IndexEntry,
+        rejection: Rejection,
+    ) -> Result<bool> {
+        if !self.update || (self.reset && !self.protect_untracked) {
+            return Ok(true);
+        }
+        for (index, _) in entry.path.match_indices('/') {
+            let dir = &entry.path[..index];
+            match fs::symlink_metadata(dir) {
+                Ok(metadata) if metadata.is_dir() => continue,
+                Ok(_) => return self.check_ok_to_remove(source, dir, false, rejection),
+                Err(_) => return Ok(true),
+            }
+        }
+        match fs::symlink_metadata(&entry.path) {
+            // a submodule is checked out into the directory
+            Ok(metadata) if metadata.is_dir() && entry.mode == 0o160000 => Ok(true),
+            Ok(metadata) => {
+                self.check_ok_to_remove(source, &entry.path, metadata.is_dir(), rejection)
+            }
+            Err(_) => Ok(true),
+        }
+    }
+
+    fn check_ok_to_remove(
+        &mut self,
+        source: &Index,
+        path: &str,
+        is_dir: bool,
+        rejection: Rejection,
+    ) -> Result<bool> {
+        if let Some(ignore) = &mut self.ignore {
+            if ignore.is_ignored(path, is_dir) {
+                return Ok(true);
+            }
+        }
+        if is_dir {
+            return self.verify_clean_subdirectory(source, path);
+        }
+        // a file on its way out to make room for a directory
+        if self.removed.contains(path) {
+            return Ok(true);
+        }
+        self.reject(rejection, path);
+        Ok(false)
+    }
+
+    /// Whether the directory `dir` can make way for a file: what is tracked in
+    /// it must be unchanged, and then goes, and nothing untracked may be in it.
+    fn verify_clean_subdirectory(&mut self, source: &Index, dir: &str) -> Result<bool> {
+        let under = format!("{}/", dir);
+        for entry in &source.entries {
+            if entry.stage != 0 || !entry.path.starts_with(&under) {
+                continue;
+            }
+            if !self.verify_uptodate(source, entry, Rejection::NotUptodate)? {
+                return Ok(false);
+            }
+            self.removed.insert(entry.path.clone());
+            self.invalidated.push(entry.path.clone());
+        }
+
+        if self.has_untracked(source, dir)? {
+            self.reject(Rejection::NotUptodateDirectory, dir);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn has_untracked(&mut self, source: &Index, dir: &str) -> Result<bool> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            let is_dir = entry.file_type()?.is_dir();
+            if let Some(ignore) = &mut self.ignore {
+                if ignore.is_ignored(&path, is_dir) {
+                    continue;
+                }
+            }
+            let untracked = match is_dir {
+                true => self.has_untracked(source, &path)?,
+                false => !source.is_tracked(&path),
+            };
+            if untracked {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Deletes the files that left the index, then writes out the entries
+    /// that changed, taking in their new stat data.
+    fn update_worktree(&mut self, source: &Index) -> Result<()> {
+        let worktree = Checkout {
+            force: true,
+            ..Checkout::default()
+        };
+        for path in &self.removed {
+            worktree.remove_entry(path)?;
+        }
+
+        let positions: Vec<usize> = (0..self.result.len())
+            .filter(|i| {
+                let entry = &self.result[*i];
+                self.checkout.contains(&entry.path) && !entry.skip_worktree
+            })
+            .collect();
+        let entries: Vec<&IndexEntry> = positions.iter().map(|i| &self.result[*i]).collect();
+        let outcomes = worktree.checkout_entries(source, &entries)?;
+
+        let mut failed = false;
+        for (i, outcome) in positions.into_iter().zip(outcomes) {
+            match outcome {
+                Outcome::Written(metadata) => self.result[i] = self.result[i].with_stat(&metadata),
+                Outcome::Skipped => {}
+                Outcome::Failed => failed = true,
+            }
+        }
+        if failed {
+            return Err("unable to update the working tree".into());
+        }
+        Ok(())
+    }
+}
+
+impl Unpack {
+    /// Reads trees into the index: replacing it, or merging one, two or three
+    /// (and more) trees with `-m`, or adding one below `--prefix`.
+    pub fn read_tree(args: &ReadTree) -> Result<()> {
+        if args.merge as u8 + args.reset as u8 + args.prefix.is_some() as u8 > 1 {
+            return Err("Which one? -m, --reset, or --prefix?".into());
+        }
+        let prefix = match args.prefix.as_deref() {
+            Some(prefix) if prefix.starts_with('/') => {
+                return Err("Invalid prefix, prefix cannot start with '/'".into())
+            }
+            Some(prefix) if !prefix.is_empty() && !prefix.ends_with('/') => {
+                Some(format!("{}/", prefix))
+            }
+            prefix => prefix.map(|prefix| prefix.to_string()),
+        };
+        let merging = args.merge || args.reset || prefix.is_some();
+
+        let mut index = Index::read()?;
+        let unmerged = index.entries.iter().any(|entry| entry.stage != 0);
+        if unmerged && (args.merge || prefix.is_some()) {
+            return Err("You need to resolve your current index first".into());
+        }
+
+        let mut trees = Vec::new();
+        for name in &args.trees {
+            let sha1 =
+                refs::rev_parse(name).map_err(|_| format!("Not a valid object name {}", name))?;
+            let tree = refs::peel_to_tree(&sha1)
+                .map_err(|_| format!("failed to unpack tree object {}", name))?;
+            trees.push(tree);
+        }
+        if trees.len() > MAX_TREES {
+            return Err(format!("I cannot read more than {} trees", MAX_TREES).into());
+        }
+        if trees.is_empty() && !args.empty && !merging {
+            eprintln!(
+                "warning: read-tree: emptying the index with no arguments is deprecated; use --empty"
+            );
+        } else if !trees.is_empty() && args.empty {
+            return Err("passing trees as arguments contradicts --empty".into());
+        }
+        if args.update && !merging {
+            return Err("-u is meaningless without -m, --reset, or --prefix".into());
+        }
+
+        let merge = match (merging, trees.len(), prefix) {
+            (false, _, _) => Merge::Replace,
+            (true, 0, _) => return Err("you must specify at least one tree to merge".into()),
+            (true, 1, Some(prefix)) => Merge::Bind(prefix),
+            (true, 1, None) => Merge::OneWay,
+            (true, 2, _) => Merge::TwoWay,
+            (true, _, _) => Merge::ThreeWay,
+        };
+        let bind = matches!(merge, Merge::Bind(_));
+        let mut unpack = Unpack::new(merge);
+        unpack.update = args.update;
+        unpack.reset = args.reset;
+        unpack.unpack(&mut index, &trees)?;
+        if let Some((rejection, path)) = unpack.rejected.first() {
+            eprintln!("error: {}", rejection.message(path));
+            std::process::exit(128);
+        }
+
+        // an index read from a single tree is described by that tree exactly
+        index.cache_tree = match (trees.len(), bind) {
+            (1, false) => Some(Tree::cache_tree(&trees[0])?),
+            (_, false) if unpack.merge == Merge::Replace => None,
+            _ => index.cache_tree,
+        };
+        index.write()
+    }
+}
@@ -1,13 +1,18 @@
 use sha1::{Digest, Sha1};
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::{fs, path::Path, path::PathBuf};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io, path::Path, path::PathBuf, process};
 
+use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder as WriteEncoder;
 use flate2::Compression;
 use std::io::Write;
 
+use crate::config::Config;
+
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -18,25 +23,223 @@ pub fn hex_sha1<T: AsRef<[u8]>>(data: T) -> String {
     hex_sha1
 }
 
+/// Prepends the `<type> <size>\0` header git hashes and stores objects with.
+pub fn object_bytes<T: AsRef<[u8]>>(object_type: &str, content: T) -> Vec<u8> {
+    let content = content.as_ref();
+    let mut bytes = Vec::with_capacity(content.len() + 32);
+    bytes.extend(object_type.as_bytes());
+    bytes.push(b' ');
+    bytes.extend(content.len().to_string().as_bytes());
+    bytes.push(0);
+    bytes.extend(content);
+    bytes
+}
+
 pub fn save_object<T: AsRef<[u8]>>(hex_sha1: &str, data: T) -> Result<()> {
-    let mut e = WriteEncoder::new(Vec::new(), Compression::default());
-    e.write_all(data.as_ref())?;
-    let buffer = e.finish()?;
+    let file_path = format!(".git/objects/{}/{}", &hex_sha1[..2], &hex_sha1[2..40]);
+    if Path::new(&file_path).exists() && loose_object_is_valid(&file_path, hex_sha1) {
+        return Ok(());
+    }
+
+    let (temp_path, f) = temp_object_file()?;
+    let written = (|| {
+        let mut e = WriteEncoder::new(f, Compression::default());
+        e.write_all(data.as_ref())?;
+        let f = e.finish()?;
+        finalize_object(temp_path.clone(), f, hex_sha1)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Whether the loose object at `file_path` inflates to content hashing to `hex_sha1`.
+fn loose_object_is_valid(file_path: &str, hex_sha1: &str) -> bool {
+    let f = match File::open(file_path) {
+        Ok(f) => f,
+        Err(_) => return false,
+    };
+
+    let mut hasher = Sha1::new();
+    let mut z = ZlibDecoder::new(f);
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        match z.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(_) => return false,
+        }
+    }
+
+    hex::encode(hasher.finalize()) == hex_sha1
+}
+
+/// Whether `core.fsync` (or the older `core.fsyncObjectFiles`) asks for loose
+/// objects to be flushed to disk before they're renamed into place.
+fn fsync_loose_objects() -> Result<bool> {
+    let config = Config::cached()?;
+
+    if let Some(components) = config.get("core.fsync") {
+        let mut fsync = false;
+        for component in components.split(',').map(str::trim) {
+            match component {
+                "none" => fsync = false,
+                "loose-object" | "objects" | "committed" | "added" | "all" => fsync = true,
+                "-loose-object" | "-objects" | "-committed" | "-added" | "-all" => fsync = false,
+                _ => {}
+            }
+        }
+        return Ok(fsync);
+    }
+
+    Ok(config.get_bool("core.fsyncObjectFiles")?.unwrap_or(false))
+}
+
+/// Moves a completely written temp file into place as the loose object `hex_sha1`.
+/// An object that is already there is kept if it's intact and replaced if it isn't.
+fn finalize_object(temp_path: PathBuf, f: File, hex_sha1: &str) -> Result<()> {
+    let fsync = fsync_loose_objects()?;
+    if fsync {
+        f.sync_all()?;
+    }
+    drop(f);
+    fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o444))?;
 
     let file_dir = format!(".git/objects/{}", &hex_sha1[..2]);
-    if !Path::new(&file_dir).exists() {
-        fs::create_dir(file_dir)?;
+    // another writer may create the directory at the same time
+    fs::create_dir_all(&file_dir)?;
+
+    let file_path = format!("{}/{}", file_dir, &hex_sha1[2..40]);
+    if Path::new(&file_path).exists() && loose_object_is_valid(&file_path, hex_sha1) {
+        fs::remove_file(temp_path)?;
+        return Ok(());
     }
 
-    let file_path = format!(".git/objects/{}/{}", &hex_sha1[..2], &hex_sha1[2..40]);
-    if !Path::new(&file_path).exists() {
-        let mut f = File::create(file_path)?;
-        f.write(&buffer)?;
+    fs::rename(&temp_path, &file_path)?;
+    if fsync {
+        File::open(&file_dir)?.sync_all()?;
     }
 
     Ok(())
 }
 
+/// Creates a uniquely named temporary file next to the objects, so that moving
+/// it into place later is a cheap rename on the same filesystem.
+fn temp_object_file() -> Result<(PathBuf, File)> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos();
+    for attempt in 0..100u32 {
+        let temp_path = PathBuf::from(format!(
+            ".git/objects/tmp_obj_{}_{}_{}",
+            process::id(),
+            nanos,
+            attempt
+        ));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(f) => return Ok((temp_path, f)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err("Could not create a temporary object file".into())
+}
+
+/// Writes an object whose content arrives in pieces: the header, SHA-1 and zlib
+/// stream are all fed in one pass and the object is moved into place on `finish`.
+pub struct ObjectWriter {
+    hasher: Sha1,
+    encoder: Option<WriteEncoder<File>>,
+    temp_path: Option<PathBuf>,
+}
+
+impl ObjectWriter {
+    pub fn new(object_type: &str, size: u64, write: bool) -> Result<Self> {
+        let (encoder, temp_path) = if write {
+            let (temp_path, f) = temp_object_file()?;
+            (
+                Some(WriteEncoder::new(f, Compression::default())),
+                Some(temp_path),
+            )
+        } else {
+            (None, None)
+        };
+
+        let mut writer = ObjectWriter {
+            hasher: Sha1::new(),
+            encoder,
+            temp_path,
+        };
+        write!(writer, "{} {}\0", object_type, size)?;
+        Ok(writer)
+    }
+
+    pub fn finish(mut self) -> Result<String> {
+        let hex_sha1 = hex::encode(self.hasher.finalize_reset());
+
+        if let Some(encoder) = self.encoder.take() {
+            let f = encoder.finish()?;
+            let temp_path = self.temp_path.take().unwrap();
+            if let Err(e) = finalize_object(temp_path.clone(), f, &hex_sha1) {
+                let _ = fs::remove_file(temp_path);
+                return Err(e);
+            }
+        }
+
+        Ok(hex_sha1)
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        if let Some(encoder) = &mut self.encoder {
+            encoder.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ObjectWriter {
+    fn drop(&mut self) {
+        // only still set when the object was abandoned before `finish`
+        if let Some(temp_path) = &self.temp_path {
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
+
+/// Tidies a message the way git does before storing it: trailing whitespace and
+/// surrounding blank lines are dropped, runs of blank lines are squeezed into
+/// one and the result ends with a single newline (or is empty).
+pub fn cleanup_message(message: &str) -> String {
+    let mut cleaned = String::new();
+    let mut blank_lines = 0;
+
+    for line in message.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !cleaned.is_empty() && blank_lines > 0 {
+            cleaned.push('\n');
+        }
+        blank_lines = 0;
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+
+    cleaned
+}
+
 pub fn sorted_current_dir<T: AsRef<Path>>(dir_path: T) -> Result<Vec<PathBuf>> {
     use std::io::Result;
 
@@ -49,68 +252,443 @@ pub fn sorted_current_dir<T: AsRef<Path>>(dir_path: T) -> Result<Vec<PathBuf>> {
     Ok(entries)
 }
 
-pub fn gitignored_sorted_current_dir<T: AsRef<Path>>(dir_path: T) -> Result<Vec<PathBuf>> {
-    let mut entries = sorted_current_dir(dir_path.as_ref())?;
+/// Matches `text` against a glob the way git's wildmatch does. With `pathname`
+/// set, `*`, `?` and classes never match a slash, and a `**` between slashes
+/// matches any number of directories.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    glob(pattern.as_bytes(), text.as_bytes(), pathname, true)
+}
 
-    let mut gitignore_content = Vec::new();
+fn glob(pattern: &[u8], text: &[u8], pathname: bool, segment_start: bool) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') => {
+            let stars = pattern.iter().take_while(|x| **x == b'*').count();
+            let rest = &pattern[stars..];
+            if pathname && stars == 2 && segment_start {
+                match rest.first() {
+                    None => return true,
+                    Some(b'/') => {
+                        // `**/` also matches no directories at all
+                        let rest = &rest[1..];
+                        if glob(rest, text, pathname, true) {
+                            return true;
+                        }
+                        return text.iter().enumerate().any(|(i, x)| {
+                            *x == b'/' && glob(rest, &text[i + 1..], pathname, true)
+                        });
+                    }
+                    _ => {}
+                }
+            }
 
-    let mut path = PathBuf::new();
-    match dir_path.as_ref().to_str() {
-        Some(".") => {
-            path.push(".gitignore");
-            gitignore_content.push(String::from("./.git"));
+            for i in 0..=text.len() {
+                if glob(rest, &text[i..], pathname, false) {
+                    return true;
+                }
+                if pathname && text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
         }
-        Some(dir) => {
-            path.push(dir);
-            let gitpath = format!("{}/.git", dir);
-            gitignore_content.push(gitpath);
-            if path.ends_with("/") {
-                path.push(".gitignore");
-            } else {
-                path.push("/.gitignore");
+        Some(b'?') => match text.first() {
+            Some(b'/') if pathname => false,
+            Some(_) => glob(&pattern[1..], &text[1..], pathname, false),
+            None => false,
+        },
+        Some(b'[') => match (
+            class_match(pattern, *text.first().unwrap_or(&0)),
+            text.first(),
+        ) {
+            (Some(_), None) => false,
+            (Some(_), Some(b'/')) if pathname => false,
+            (Some((matched, length)), Some(_)) => {
+                matched && glob(&pattern[length..], &text[1..], pathname, false)
+            }
+            // an unclosed bracket is just a bracket
+            (None, c) => c == Some(&b'[') && glob(&pattern[1..], &text[1..], pathname, false),
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob(&pattern[2..], &text[1..], pathname, false)
+        }
+        Some(c) => text.first() == Some(c) && glob(&pattern[1..], &text[1..], pathname, *c == b'/'),
+    }
+}
+
+/// Matches `c` against the bracket expression at the start of `pattern`,
+/// returning the result and the expression's length, or `None` when the
+/// bracket is never closed and so stands for itself.
+fn class_match(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(1), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(i)?;
+        if current == b']' && !first {
+            break;
+        }
+        first = false;
+
+        if pattern[i..].starts_with(b"[:") {
+            let end = pattern[i + 2..].windows(2).position(|x| x == b":]")? + i + 2;
+            matched |= match &pattern[i + 2..end] {
+                b"alnum" => c.is_ascii_alphanumeric(),
+                b"alpha" => c.is_ascii_alphabetic(),
+                b"blank" => c == b' ' || c == b'\t',
+                b"cntrl" => c.is_ascii_control(),
+                b"digit" => c.is_ascii_digit(),
+                b"graph" => c.is_ascii_graphic(),
+                b"lower" => c.is_ascii_lowercase(),
+                b"print" => c.is_ascii_graphic() || c == b' ',
+                b"punct" => c.is_ascii_punctuation(),
+                b"space" => c.is_ascii_whitespace(),
+                b"upper" => c.is_ascii_uppercase(),
+                b"xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            };
+            i = end + 2;
+            continue;
+        }
+
+        let low = if current == b'\\' {
+            i += 1;
+            *pattern.get(i)?
+        } else {
+            current
+        };
+        if pattern.get(i + 1) == Some(&b'-') && matches!(pattern.get(i + 2), Some(x) if *x != b']')
+        {
+            let mut high = pattern[i + 2];
+            i += 3;
+            if high == b'\\' {
+                high = *pattern.get(i)?;
+                i += 1;
             }
+            matched |= low <= c && c <= high;
+        } else {
+            matched |= low == c;
+            i += 1;
         }
-        None => {
-            return Err("Path Error".into());
+    }
+
+    Some((matched != negated, i + 1))
+}
+
+/// Turns a pathspec typed on the command line into a path relative to the top
+/// of the working tree, with `.` (the whole tree) becoming the empty string.
+pub fn normalize_pathspec(spec: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in spec.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
         }
     }
+    components.join("/")
+}
 
-    //not sure how git actually implements this, but I'm assuming that
-    //.git is ignored in subdirectories as well. Also considering any
-    //.gitignores these subfolders might have
-    if Path::new(&path).exists() {
-        let gitignore = File::open(path)?;
-        let reader = BufReader::new(gitignore);
+/// Whether `path` falls under a normalized pathspec: the path itself, a
+/// directory above it, or a glob over the whole path.
+pub fn pathspec_matches(spec: &str, path: &str) -> bool {
+    spec.is_empty()
+        || path == spec
+        || (path.starts_with(spec) && path.as_bytes().get(spec.len()) == Some(&b'/'))
+        || (spec.contains(['*', '?', '[']) && wildmatch(spec, path, false))
+}
+
+/// Quotes a path for display the way git does: in double quotes with C-style
+/// escapes when it holds control characters, quotes or backslashes, and also
+/// for bytes above ASCII unless `core.quotePath` is off.
+pub fn quote_path(path: &str) -> Result<String> {
+    let quote_high = Config::cached()?
+        .get_bool("core.quotePath")?
+        .unwrap_or(true);
+    let needs_quoting = |x: u8| x < 0x20 || x == b'"' || x == b'\\' || x == 0x7f;
+    if !path
+        .bytes()
+        .any(|x| needs_quoting(x) || (quote_high && x >= 0x80))
+    {
+        return Ok(path.to_string());
+    }
 
-        for line in reader.lines() {
-            let mut name = line?;
-            if name.starts_with("/") {
-                name = name.strip_prefix("/").unwrap().to_string();
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '\x07' => quoted.push_str("\\a"),
+            '\x08' => quoted.push_str("\\b"),
+            '\t' => quoted.push_str("\\t"),
+            '\n' => quoted.push_str("\\n"),
+            '\x0b' => quoted.push_str("\\v"),
+            '\x0c' => quoted.push_str("\\f"),
+            '\r' => quoted.push_str("\\r"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 || c == '\x7f' || (quote_high && !c.is_ascii()) => {
+                let mut buf = [0; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    quoted.push_str(&format!("\\{:03o}", byte));
+                }
             }
-            let mut new_path = String::new();
-            let str = dir_path.as_ref().to_str().unwrap();
-            if str.ends_with("/") {
-                new_path.push_str(str);
-                new_path.push_str(&name);
-            } else {
-                new_path.push_str(str);
-                new_path.push_str("/");
-                new_path.push_str(&name);
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Ok(quoted)
+}
+
+/// Reverses [`quote_path`]; a path that doesn't start with a quote is taken
+/// as it is.
+pub fn unquote_path(path: &str) -> Result<String> {
+    let inner = match path.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(inner) => inner.as_bytes(),
+        None => return Ok(path.to_string()),
+    };
+    let bad = || -> Error { format!("bad quoting in {}", path).into() };
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut i = 0;
+    while i < inner.len() {
+        if inner[i] != b'\\' {
+            bytes.push(inner[i]);
+            i += 1;
+            continue;
+        }
+        let escaped = *inner.get(i + 1).ok_or_else(bad)?;
+        i += 2;
+        bytes.push(match escaped {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'v' => 0x0b,
+            b'f' => 0x0c,
+            b'r' => b'\r',
+            b'"' | b'\\' => escaped,
+            b'0'..=b'3' => {
+                let digits = inner.get(i - 1..i + 2).ok_or_else(bad)?;
+                i += 2;
+                u8::from_str_radix(std::str::from_utf8(digits)?, 8).map_err(|_| bad())?
+            }
+            _ => return Err(bad()),
+        });
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Whether `path` is fit for the index and for writing below the top of the
+/// working tree: relative, with no empty, `.`, `..` or `.git` components.
+pub fn verify_path(path: &str) -> bool {
+    path.split('/').all(|component| {
+        !component.is_empty()
+            && component != "."
+            && component != ".."
+            && !component.eq_ignore_ascii_case(".git")
+    })
+}
+
+/// One line of a `.gitignore`, `.git/info/exclude` or `core.excludesFile`.
+struct IgnorePattern {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    // patterns with a slash match the whole path below their directory, the
+    // others match the file name at any depth
+    anchored: bool,
+}
+
+impl IgnorePattern {
+    fn parse(line: &str) -> Option<Self> {
+        if line.starts_with('#') {
+            return None;
+        }
+
+        // trailing spaces don't count unless they are escaped
+        let mut line = line.trim_end_matches('\r');
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+
+        let (line, negated) = match line.strip_prefix('!') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        let (line, dir_only) = match line.strip_suffix('/') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(IgnorePattern {
+            anchored: line.contains('/'),
+            pattern: line.strip_prefix('/').unwrap_or(line).to_string(),
+            negated,
+            dir_only,
+        })
+    }
+
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            wildmatch(&self.pattern, relative, true)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.pattern, name, true)
+        }
+    }
+}
+
+fn read_ignore_file<T: AsRef<Path>>(path: T) -> Vec<IgnorePattern> {
+    match fs::read(path) {
+        Ok(content) => String::from_utf8_lossy(&content)
+            .lines()
+            .filter_map(IgnorePattern::parse)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// The ignore rules of the working tree. A `.gitignore` outranks the ones in
+/// the directories above it, all of them outrank `.git/info/exclude`, which
+/// outranks `core.excludesFile`; within a file the last matching line wins.
+pub struct Ignore {
+    // core.excludesFile, then .git/info/exclude
+    global: Vec<IgnorePattern>,
+    // each directory's .gitignore, read the first time a path below it is checked
+    per_dir: HashMap<String, Vec<IgnorePattern>>,
+}
+
+impl Ignore {
+    pub fn load() -> Result<Self> {
+        let excludes_file = match Config::cached()?.get("core.excludesFile") {
+            Some(path) => match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+                (Some(rest), Some(home)) => Some(PathBuf::from(home).join(rest)),
+                _ => Some(PathBuf::from(path)),
+            },
+            None => match std::env::var_os("XDG_CONFIG_HOME") {
+                Some(xdg) if !xdg.is_empty() => Some(PathBuf::from(xdg).join("git/ignore")),
+                _ => std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".config/git/ignore")),
+            },
+        };
+
+        let mut global = Vec::new();
+        if let Some(path) = excludes_file {
+            global.extend(read_ignore_file(path));
+        }
+        global.extend(read_ignore_file(".git/info/exclude"));
+
+        Ok(Ignore {
+            global,
+            per_dir: HashMap::new(),
+        })
+    }
+
+    fn load_dir(&mut self, dir: &str) {
+        if !self.per_dir.contains_key(dir) {
+            let path = match dir {
+                "" => PathBuf::from(".gitignore"),
+                dir => Path::new(dir).join(".gitignore"),
+            };
+            self.per_dir.insert(dir.to_string(), read_ignore_file(path));
+        }
+    }
+
+    /// The verdict of the highest ranked pattern matching `path`, if any.
+    fn matches(&mut self, path: &str, is_dir: bool) -> Option<bool> {
+        let mut dirs = vec![""];
+        dirs.extend(path.match_indices('/').map(|(index, _)| &path[..index]));
+        for dir in &dirs {
+            self.load_dir(dir);
+        }
+
+        for dir in dirs.iter().rev() {
+            let relative = match *dir {
+                "" => path,
+                dir => &path[dir.len() + 1..],
+            };
+            let patterns = &self.per_dir[*dir];
+            if let Some(pattern) = patterns.iter().rev().find(|x| x.matches(relative, is_dir)) {
+                return Some(!pattern.negated);
             }
-            gitignore_content.push(new_path);
         }
+        self.global
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .map(|pattern| !pattern.negated)
+    }
+
+    /// Whether `path` is ignored, either itself or because a directory above it
+    /// is; nothing below an ignored directory can be taken back out.
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> bool {
+        let dirs: Vec<&str> = path
+            .match_indices('/')
+            .map(|(index, _)| &path[..index])
+            .collect();
+        dirs.into_iter()
+            .any(|dir| self.matches(dir, true) == Some(true))
+            || self.matches(path, is_dir) == Some(true)
     }
+}
 
-    entries.retain(|x| {
-        let mut boolean = true;
-        for ignored in &gitignore_content {
-            if *x == PathBuf::from(ignored) {
-                boolean = false;
-                break;
+/// Every file and symlink in the working tree, relative to its top and in index
+/// order, paired with whether it is ignored. `.git` and nested repositories
+/// are left out. An ignored directory is only read when `ignored_contents` is
+/// set; otherwise it is listed once, as its path with a trailing `/`.
+pub fn walk_worktree(ignore: &mut Ignore, ignored_contents: bool) -> Result<Vec<(String, bool)>> {
+    fn walk(
+        dir: &str,
+        ignore: &mut Ignore,
+        ignored: bool,
+        ignored_contents: bool,
+        files: &mut Vec<(String, bool)>,
+    ) -> Result<()> {
+        let read_dir = match dir {
+            "" => fs::read_dir(".")?,
+            dir => fs::read_dir(dir)?,
+        };
+        for entry in read_dir {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = match dir {
+                "" => name.clone(),
+                dir => format!("{}/{}", dir, name),
+            };
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                if name == ".git" || Path::new(&path).join(".git").exists() {
+                    continue;
+                }
+                let ignored = ignored || ignore.matches(&path, true) == Some(true);
+                if ignored && !ignored_contents {
+                    files.push((format!("{}/", path), true));
+                    continue;
+                }
+                walk(&path, ignore, ignored, ignored_contents, files)?;
+            } else {
+                let ignored = ignored || ignore.matches(&path, false) == Some(true);
+                files.push((path, ignored));
             }
         }
-        boolean
-    });
+        Ok(())
+    }
 
-    Ok(entries)
+    let mut files = Vec::new();
+    walk("", ignore, false, ignored_contents, &mut files)?;
+    files.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+    Ok(files)
 }